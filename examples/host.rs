@@ -0,0 +1,49 @@
+//! Runs a program that calls methods implemented by the host: `cargo run --example host`
+
+extern crate vpm;
+
+use vpm::punkfile::assembler;
+use vpm::Vm;
+use std::collections::HashMap;
+use std::process::exit;
+
+const PROGRAM: &str = "
+.main
+    CONST apples
+    METHODCALL Host/lookup
+    PRINT
+    CONST Stock checked
+    METHODCALL Host/log
+    CONST pears
+    METHODCALL Host/lookup
+    PRINT
+    RETURN
+";
+
+fn main() {
+    let punk_file = assembler::assemble(PROGRAM).expect("The program is valid");
+    let mut stock = HashMap::new();
+    stock.insert(String::from("apples"), 12);
+
+    // The natives have to be registered before the program is linked, so it's only loaded
+    let mut vm = match Vm::load(punk_file) {
+        Ok(vm) => vm,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1)
+        }
+    };
+    let registered = vm.register_native("Host/lookup", "(S)I", move |item: String| match stock.get(&item) {
+        Some(n) => Ok(*n),
+        None => Err(format!("There are no {} in stock", item))
+    }).and_then(|_| vm.register_native("Host/log", "(S)V", |msg: String| eprintln!("[host] {}", msg)));
+    if let Err(err) = registered {
+        eprintln!("{}", err);
+        exit(1)
+    }
+
+    if let Err(err) = vm.run() {
+        eprintln!("{}", err);
+        exit(1)
+    }
+}
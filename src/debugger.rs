@@ -0,0 +1,282 @@
+//! Interactive debugger that runs a program one instruction at a time. Breakpoints are written as
+//! `Class/method`, `Class/method:label` or `Class/method:pc`, where the pc counts from the first
+//! instruction of the method. The main code is the method `AppMain`.
+
+use crate::vm::{Vm, State};
+use crate::memory::vpk_stack::{Frame, Type};
+use crate::memory::instructions::Method;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+break <Class/method[:label|:pc]>   stop before executing that instruction (b)
+delete <n>                         remove the breakpoint n
+breakpoints                        list the breakpoints
+step                               execute one instruction (s)
+next                               execute one instruction, running whole method calls (n)
+finish                             run until the current method returns (f)
+continue                           run until a breakpoint or the end of the program (c)
+where                              show the next instruction (w)
+stack                              show the operator stack of the current frame
+locals                             show the local variables of the current frame
+frames                             show every active frame (bt)
+object <handle>                    show the fields of an object or the elements of an array (o)
+help                               show this help (h)
+quit                               leave the debugger (q)";
+
+struct Breakpoint {
+    /// As the user wrote it
+    spec: String,
+    pc: usize,
+}
+
+pub struct Debugger {
+    vm: Vm,
+    breakpoints: Vec<Breakpoint>,
+}
+
+/// Why the machine stopped running
+enum Stop {
+    Step,
+    Breakpoint(usize),
+    Finished(Option<Type>),
+    Error(String),
+}
+
+impl Debugger {
+    pub fn new(vm: Vm) -> Debugger {
+        Debugger {
+            vm,
+            breakpoints: Vec::new(),
+        }
+    }
+
+    /// Reads commands from `input` until it ends or the user quits
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        writeln!(output, "Type help to see the commands")?;
+        writeln!(output, "{}", self.location())?;
+        let mut lines = input.lines();
+        loop {
+            write!(output, "(vpm) ")?;
+            output.flush()?;
+            let line = match lines.next() {
+                Some(l) => l?,
+                None => return Ok(())
+            };
+            let mut words = line.split_whitespace();
+            let command = match words.next() {
+                Some(c) => c,
+                None => continue
+            };
+            let arg = words.next();
+            let text = match command {
+                "break" | "b" => self.add_breakpoint(arg),
+                "delete" => self.delete_breakpoint(arg),
+                "breakpoints" => self.list_breakpoints(),
+                "step" | "s" => self.resume(|_| true),
+                "next" | "n" => {
+                    let depth = self.vm.frames().len();
+                    self.resume(move |vm| vm.frames().len() <= depth)
+                },
+                "finish" | "f" => {
+                    let depth = self.vm.frames().len();
+                    self.resume(move |vm| vm.frames().len() < depth)
+                },
+                "continue" | "c" => self.resume(|_| false),
+                "where" | "w" => self.location(),
+                "stack" => self.stack(),
+                "locals" => self.locals(),
+                "frames" | "bt" => self.frames(),
+                "object" | "o" => self.object(arg),
+                "help" | "h" => HELP.to_string(),
+                "quit" | "q" => return Ok(()),
+                c => format!("Unknown command {}, type help to see the commands", c)
+            };
+            writeln!(output, "{}", text)?;
+        }
+    }
+
+    /// Executes instructions until `done` says so, a breakpoint is reached or the program ends
+    fn resume<F: Fn(&Vm) -> bool>(&mut self, done: F) -> String {
+        if let State::Finished(_) = self.vm.state() {
+            return String::from("The program has finished")
+        }
+        let stop = loop {
+            match self.vm.step() {
+                Ok(State::Finished(value)) => break Stop::Finished(value),
+                Ok(State::Running) => (),
+                Err(err) => break Stop::Error(err.to_string())
+            }
+            let pc = self.vm.pc();
+            if done(&self.vm) {
+                break Stop::Step
+            }
+            if let Some(n) = self.breakpoints.iter().position(|b| b.pc == pc) {
+                break Stop::Breakpoint(n)
+            }
+        };
+        match stop {
+            Stop::Step => self.location(),
+            Stop::Breakpoint(n) => format!("Breakpoint {} ({})\n{}", n, self.breakpoints[n].spec, self.location()),
+            Stop::Finished(Some(value)) => format!("The program has finished returning {:?}", value),
+            Stop::Finished(None) => String::from("The program has finished"),
+            Stop::Error(err) => err
+        }
+    }
+
+    fn add_breakpoint(&mut self, spec: Option<&str>) -> String {
+        let spec = match spec {
+            Some(s) => s,
+            None => return String::from("break expects Class/method, Class/method:label or Class/method:pc")
+        };
+        let ins = self.vm.instructions();
+        let (name, position) = match spec.split_once(':') {
+            Some((name, position)) => (name, Some(position)),
+            None => (spec, None)
+        };
+        let index = match ins.get_method_index(name) {
+            Some(i) => i,
+            None => return format!("There's no method {}", name)
+        };
+        let method = ins.get_method(index);
+        let len = self.method_len(index);
+        if len == 0 {
+            return format!("{} has no code", name)
+        }
+        let pc = match position {
+            None => method.pc,
+            Some(p) => match (p.parse::<usize>(), method.labels.get(p)) {
+                (Ok(offset), _) if offset < len => method.pc + offset,
+                (Ok(offset), _) => return format!("{} only has {} instructions, {} is out of it", name, len, offset),
+                (Err(_), Some(label_pc)) => *label_pc,
+                (Err(_), None) => return format!("There's no label {} in {}", p, name)
+            }
+        };
+        self.breakpoints.push(Breakpoint { spec: spec.to_string(), pc });
+        format!("Breakpoint {} at {}", self.breakpoints.len() - 1, self.position(pc))
+    }
+
+    fn delete_breakpoint(&mut self, n: Option<&str>) -> String {
+        match n.map(|n| n.parse::<usize>()) {
+            Some(Ok(n)) if n < self.breakpoints.len() => {
+                let b = self.breakpoints.remove(n);
+                format!("Deleted breakpoint {} ({})", n, b.spec)
+            },
+            _ => String::from("delete expects the number of a breakpoint")
+        }
+    }
+
+    fn list_breakpoints(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return String::from("There are no breakpoints")
+        }
+        let lines: Vec<String> = self.breakpoints.iter().enumerate()
+            .map(|(n, b)| format!("{}: {} at {}", n, b.spec, self.position(b.pc)))
+            .collect();
+        lines.join("\n")
+    }
+
+    /// Next instruction to execute
+    fn location(&self) -> String {
+        if let State::Finished(_) = self.vm.state() {
+            return String::from("The program has finished")
+        }
+        let pc = self.vm.pc();
+        match self.vm.instructions().get_ins(pc) {
+            Ok(ins) => format!("{}: {}", self.position(pc), ins),
+            Err(err) => format!("{}: {}", self.position(pc), err)
+        }
+    }
+
+    fn stack(&self) -> String {
+        let frames = self.vm.frames();
+        let operands = frames[0].0.operands();
+        if operands.is_empty() {
+            return String::from("The operator stack is empty")
+        }
+        let lines: Vec<String> = operands.iter().rev().enumerate()
+            .map(|(i, v)| format!("{}{:?}", if i == 0 { "top -> " } else { "       " }, v))
+            .collect();
+        lines.join("\n")
+    }
+
+    fn locals(&self) -> String {
+        let frames = self.vm.frames();
+        let locals = frames[0].0.locals();
+        if locals.is_empty() {
+            return String::from("There are no local variables")
+        }
+        let lines: Vec<String> = locals.iter().enumerate()
+            .map(|(i, v)| match v {
+                Some(v) => format!("{}: {:?}", i, v),
+                None => format!("{}: uninitialized", i)
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    fn frames(&self) -> String {
+        let lines: Vec<String> = self.vm.frames().iter().enumerate()
+            .map(|(i, (frame, pc))| format!("#{} {}\n{}", i, self.position(*pc), frame_summary(frame)))
+            .collect();
+        lines.join("\n")
+    }
+
+    fn object(&self, handle: Option<&str>) -> String {
+        let handle = match handle.map(|h| h.parse::<usize>()) {
+            Some(Ok(h)) => h,
+            _ => return String::from("object expects the handle of an object or an array")
+        };
+        if let Ok(array) = self.vm.array(handle) {
+            let values: Vec<String> = array.values.iter().enumerate().map(|(i, v)| format!("    [{}]: {:?}", i, v)).collect();
+            return format!("Array {} of {} with {} elements\n{}", handle, array.elem, array.values.len(), values.join("\n")).trim_end().to_string()
+        }
+        let obj = match self.vm.object(handle) {
+            Ok(o) => o,
+            Err(err) => return err.to_string()
+        };
+        // Fields hidden by a field of a subclass are shown with the class that declares them
+        let classes = &self.vm.punk_file().classes;
+        let layout = &self.vm.instructions().layouts[obj.class];
+        let fields: Vec<String> = layout.fields.iter().zip(obj.fields.iter()).enumerate()
+            .map(|(slot, ((owner, name), v))| match layout.slot(name) {
+                Some(s) if s == slot => format!("    {}: {:?}", name, v),
+                _ => format!("    {}/{}: {:?}", classes[*owner].this, name, v)
+            })
+            .collect();
+        let class = &classes[obj.class].this;
+        format!("Object {} of class {}\n{}", handle, class, fields.join("\n")).trim_end().to_string()
+    }
+
+    /// Method, global pc and pc inside the method of the instruction
+    fn position(&self, pc: usize) -> String {
+        match self.method_at(pc) {
+            Some(m) => format!("{} pc {} (+{})", m.name, pc, pc - m.pc),
+            None => format!("pc {}", pc)
+        }
+    }
+
+    fn method_at(&self, pc: usize) -> Option<&Method> {
+        self.vm.instructions().methods.iter().rev().find(|m| m.pc <= pc)
+    }
+
+    fn method_len(&self, index: usize) -> usize {
+        let ins = self.vm.instructions();
+        let end = match ins.methods.get(index + 1) {
+            Some(m) => m.pc,
+            None => ins.code.len()
+        };
+        end - ins.get_method(index).pc
+    }
+}
+
+fn frame_summary(frame: &Frame) -> String {
+    let locals: Vec<String> = frame.locals().iter()
+        .map(|v| match v {
+            Some(v) => format!("{:?}", v),
+            None => String::from("_")
+        })
+        .collect();
+    let operands: Vec<String> = frame.operands().iter().map(|v| format!("{:?}", v)).collect();
+    format!("    locals: [{}]\n    stack: [{}]", locals.join(", "), operands.join(", "))
+}
+
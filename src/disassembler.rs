@@ -0,0 +1,149 @@
+//! Writes what the loader built from a punk file using the assembler syntax, so the output can be
+//! assembled again. What the loader resolved is written as comments:
+//!
+//! ```text
+//! .class Dog
+//! .super Animal
+//! ; inherited from Animal: .field name S Unknown   ; String("Unknown")
+//! .field age I 3   ; Integer(3)
+//! .method constructor ()V   ; method 2, pc 7..9
+//!     LABEL loop    ; pc 7 (+0)
+//!     GOTO loop     ; pc 8 (+1) -> pc 7 (+0)
+//!     RETURN        ; pc 9 (+2)
+//! ```
+//!
+//! Each instruction shows its pc in `Instructions::code` and, between parentheses, its offset from the start
+//! of the method. Jumps show the pc of their label and method calls the method they were linked to.
+
+use crate::vm::{Vm, MAIN_METHOD};
+use crate::punkfile::assembler;
+use crate::punkfile::class::Class;
+use crate::punkfile::field::Field;
+use crate::punkfile::punk_file::PunkFile;
+use crate::memory::instructions::{Instructions, Method};
+use crate::isa::bytecode::ByteCode;
+
+/// Text of the program loaded in the machine
+pub fn disassemble(vm: &Vm) -> String {
+    let punk_file = vm.punk_file();
+    let ins = vm.instructions();
+    let mut out = String::new();
+
+    if !punk_file.overflow.wraps() {
+        out.push_str(".overflow trap\n\n");
+    }
+    // The built-in classes are added by the machine, the program doesn't have them
+    for cls in punk_file.classes.iter().filter(|c| !c.builtin) {
+        let kind = if cls.is_interface { "interface" } else { "class" };
+        out.push_str(format!(".{} {}\n", kind, cls.this).as_str());
+        if !cls.super_cls.is_empty() {
+            out.push_str(format!(".super {}\n", cls.super_cls).as_str());
+        }
+        for interface in cls.interfaces.iter() {
+            out.push_str(format!(".implements {}\n", interface).as_str());
+        }
+        for (parent, field) in inherited_fields(punk_file, cls) {
+            out.push_str(format!("; inherited from {}: {}\n", parent, field_line(field)).as_str());
+        }
+        for field in cls.fields.iter() {
+            out.push_str(format!("{}\n", field_line(field)).as_str());
+        }
+        for code in cls.methods.iter() {
+            let index = match ins.get_method_index(format!("{}/{}", cls.this, code.name).as_str()) {
+                Some(i) => i,
+                None => continue
+            };
+            let modifier = match (code.is_static, code.is_abstract) {
+                (true, _) => "static ",
+                (_, true) => "abstract ",
+                _ => ""
+            };
+            out.push_str(format!(".method {}{} {}   ; {}\n", modifier, code.name, code.desc, method_range(ins, index)).as_str());
+            exception_table(ins, index, &mut out);
+            method_code(ins, index, &mut out);
+        }
+        out.push('\n');
+    }
+
+    if let Some(index) = ins.get_method_index(MAIN_METHOD) {
+        out.push_str(format!(".main   ; {}\n", method_range(ins, index)).as_str());
+        exception_table(ins, index, &mut out);
+        method_code(ins, index, &mut out);
+    }
+    out
+}
+
+/// Fields that the objects of the class get from its parents, with the class that defines them
+fn inherited_fields<'a>(punk_file: &'a PunkFile, cls: &Class) -> Vec<(&'a str, &'a Field)> {
+    let mut fields = Vec::new();
+    let mut parent = punk_file.find_class(cls.super_cls.as_str());
+    while let Some(p) = parent {
+        fields.extend(p.fields.iter().filter(|f| !f.is_static).map(|f| (p.this.as_str(), f)));
+        parent = punk_file.find_class(p.super_cls.as_str());
+    }
+    fields
+}
+
+fn field_line(field: &Field) -> String {
+    let value = if field.value.is_empty() { String::new() } else { format!(" {}", assembler::quote(&field.value)) };
+    let modifier = if field.is_static { "static " } else { "" };
+    format!(".field {}{} {}{}   ; {:?}", modifier, field.name, field.desc, value, field.get_type())
+}
+
+fn method_range(ins: &Instructions, index: usize) -> String {
+    let method = ins.get_method(index);
+    let end = method_end(ins, index);
+    if end == method.pc {
+        format!("method {}, no code", index)
+    }
+    else {
+        format!("method {}, pc {}..{}", index, method.pc, end - 1)
+    }
+}
+
+/// Position after the last instruction of the method
+fn method_end(ins: &Instructions, index: usize) -> usize {
+    match ins.methods.get(index + 1) {
+        Some(m) => m.pc,
+        None => ins.code.len()
+    }
+}
+
+fn exception_table(ins: &Instructions, index: usize, out: &mut String) {
+    let method = ins.get_method(index);
+    for h in method.handlers.iter() {
+        let text = format!(".catch {} {} {} {}", h.class, h.start.name, h.end.name, h.handler.name);
+        out.push_str(format!("{}   ; {}..{} -> {}\n", text, position(method, h.start.index), position(method, h.end.index),
+                             position(method, h.handler.index)).as_str());
+    }
+}
+
+fn method_code(ins: &Instructions, index: usize, out: &mut String) {
+    let method = ins.get_method(index);
+    for pc in method.pc..method_end(ins, index) {
+        let code = &ins.code[pc];
+        let text = match code {
+            ByteCode::CONST(cst) => format!("CONST {}", assembler::quote(cst)),
+            c => c.to_string()
+        };
+        let target = match code {
+            c if c.jump().is_some() => format!(" -> {}", position(method, c.jump().unwrap().index)),
+            c if c.call().is_some() => format!(" -> {}", method_target(ins, c.call().unwrap().index)),
+            ByteCode::NEW {class, constructor} => format!(" -> class {}, {}", class.index, method_target(ins, *constructor)),
+            _ => String::new()
+        };
+        out.push_str(format!("    {:<24} ; {}{}\n", text, position(method, pc), target).as_str());
+    }
+}
+
+fn position(method: &Method, pc: usize) -> String {
+    format!("pc {} (+{})", pc, pc - method.pc)
+}
+
+fn method_target(ins: &Instructions, index: usize) -> String {
+    let m = ins.get_method(index);
+    match m.native {
+        Some(_) => format!("method {} {}, native", index, m.name),
+        None => format!("method {} {} at pc {}", index, m.name, m.pc)
+    }
+}
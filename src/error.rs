@@ -0,0 +1,173 @@
+use std::fmt;
+
+/// What went wrong while executing a program
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// An instruction needed more values than there were on the operator stack
+    StackUnderflow,
+    /// The values on the stack don't have the types the instruction expects
+    TypeMismatch(String),
+    UnknownClass(String),
+    UnknownMethod(String),
+    UnknownField(String),
+    UnknownLabel(String),
+    /// A method defines the same label more than once
+    DuplicateLabel(String),
+    /// The handle doesn't point to any object of the heap
+    UnknownObject(usize),
+    /// A null reference was used to access a field or call a method
+    NullReference,
+    /// A local variable was loaded before something was stored in it
+    UninitializedLocal(usize),
+    /// An integer was divided by zero, or its result overflowed in a program that traps on overflow
+    ArithmeticError(String),
+    /// An instruction that doesn't exist or has missing or malformed operands
+    InvalidInstruction(String),
+    /// A method descriptor that doesn't follow the `(args)Ret` format
+    InvalidDescriptor(String),
+    /// The pc points outside the code
+    InvalidPc(usize),
+    /// A `RETURN` doesn't return what the descriptor of the method says
+    InvalidReturn(String),
+    /// The operator stack has a different depth or types depending on the path that reaches an instruction
+    InconsistentStack(String),
+    /// A frame was needed but the stack of frames is empty
+    NoFrames,
+    /// An array was accessed at the index, out of its length
+    IndexOutOfBounds(i32, usize),
+    /// An array was created with a negative length
+    NegativeArraySize(i32),
+    /// A native method couldn't do its work
+    NativeError(String),
+    /// A class is its own ancestor
+    InheritanceCycle(String),
+    /// A method overrides a method of a parent with a different descriptor, or a static method hides an instance one
+    IncompatibleOverride(String),
+    /// A call that doesn't match the method: a static method called with a receiver or an instance method without one
+    InvalidCall(String),
+    /// An interface with something more than abstract methods, or a class using it as if it was a class
+    InvalidInterface(String),
+    /// A class doesn't have a method that one of its interfaces declares, or has it with another descriptor
+    UnimplementedMethod(String),
+    /// `CHECKCAST` found an object of the first class, which isn't an instance of the second one
+    ClassCast(String, String),
+    /// `THROW` was executed with the exception of the handle, the machine searches the handler that catches it
+    Thrown(usize),
+    /// No handler caught the exception, holds its class and message
+    UncaughtException(String, String),
+    /// A punk file that couldn't be opened or doesn't follow the format
+    MalformedFile(String),
+}
+
+/// A frame that was active when the error happened
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackTraceEntry {
+    /// Method in the form `Class/method`
+    pub method: String,
+    /// pc of the instruction that was being executed in that frame
+    pub pc: usize,
+}
+
+/// What the machine was doing when the error happened
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    /// Reading the punk file or loading and linking the program
+    Load,
+    /// Registering a native method with `Vm::register_native`
+    Register,
+    /// Executing the program
+    Run,
+}
+
+/// Error produced while executing a program. Besides the kind of error it records where it happened
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmError {
+    pub kind: ErrorKind,
+    pub stage: Stage,
+    pub pc: usize,
+    /// Method in the form `Class/method` in which the error happened
+    pub method: String,
+    /// Active frames from the innermost to the outermost, the first entry is the failing one
+    pub backtrace: Vec<StackTraceEntry>,
+}
+
+impl VmError {
+    /// Error without active frames, like the ones found while loading a program
+    pub fn at(kind: ErrorKind, method: String, pc: usize) -> VmError {
+        VmError {
+            kind,
+            stage: Stage::Load,
+            pc,
+            method,
+            backtrace: Vec::new(),
+        }
+    }
+
+    /// Error of a punk file that couldn't be read, it doesn't belong to any method
+    pub fn file(msg: String) -> VmError {
+        VmError::at(ErrorKind::MalformedFile(msg), String::new(), 0)
+    }
+
+    /// Error registering the native method with the name
+    pub fn register(kind: ErrorKind, method: String) -> VmError {
+        VmError {
+            stage: Stage::Register,
+            ..VmError::at(kind, method, 0)
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::StackUnderflow => write!(f, "No element on the operator stack"),
+            ErrorKind::TypeMismatch(msg) => write!(f, "{}", msg),
+            ErrorKind::UnknownClass(class) => write!(f, "The class {} couldn't be found", class),
+            ErrorKind::UnknownMethod(method) => write!(f, "The method {} couldn't be found", method),
+            ErrorKind::UnknownField(field) => write!(f, "The field {} doesn't exist", field),
+            ErrorKind::UnknownLabel(label) => write!(f, "The label {} doesn't exist", label),
+            ErrorKind::DuplicateLabel(label) => write!(f, "The label {} is defined more than once", label),
+            ErrorKind::UnknownObject(object) => write!(f, "The object {} doesn't exist", object),
+            ErrorKind::NullReference => write!(f, "A null reference was used as an object"),
+            ErrorKind::UninitializedLocal(i) => write!(f, "There's no local variable in {}", i),
+            ErrorKind::ArithmeticError(msg) => write!(f, "Arithmetic error, {}", msg),
+            ErrorKind::InvalidInstruction(msg) => write!(f, "{}", msg),
+            ErrorKind::InvalidDescriptor(desc) => write!(f, "The descriptor {} is not valid", desc),
+            ErrorKind::InvalidPc(pc) => write!(f, "At pc = {} there's no instruction", pc),
+            ErrorKind::InvalidReturn(msg) => write!(f, "Invalid return, {}", msg),
+            ErrorKind::InconsistentStack(msg) => write!(f, "The stack differs between the paths that reach the instruction, {}", msg),
+            ErrorKind::NoFrames => write!(f, "There are no more frames"),
+            ErrorKind::IndexOutOfBounds(index, len) => write!(f, "The index {} is out of the bounds of an array of length {}", index, len),
+            ErrorKind::NegativeArraySize(len) => write!(f, "An array can't have a negative length: {}", len),
+            ErrorKind::NativeError(msg) => write!(f, "{}", msg),
+            ErrorKind::InheritanceCycle(class) => write!(f, "The class {} inherits from itself", class),
+            ErrorKind::IncompatibleOverride(msg) => write!(f, "Incompatible override, {}", msg),
+            ErrorKind::InvalidCall(msg) => write!(f, "Invalid call, {}", msg),
+            ErrorKind::InvalidInterface(msg) => write!(f, "Invalid interface, {}", msg),
+            ErrorKind::UnimplementedMethod(msg) => write!(f, "Unimplemented method, {}", msg),
+            ErrorKind::ClassCast(from, to) => write!(f, "{} can't be cast to {}", from, to),
+            ErrorKind::Thrown(handle) => write!(f, "The exception {} was thrown", handle),
+            ErrorKind::UncaughtException(class, msg) if msg.is_empty() => write!(f, "Uncaught exception {}", class),
+            ErrorKind::UncaughtException(class, msg) => write!(f, "Uncaught exception {}: {}", class, msg),
+            ErrorKind::MalformedFile(msg) => write!(f, "Malformed punk file, {}", msg),
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.stage {
+            Stage::Load if self.method.is_empty() => return write!(f, "The program couldn't be loaded: {}", self.kind),
+            Stage::Load => return write!(f, "At pc: {}, in {}, the program couldn't be loaded: {}", self.pc, self.method, self.kind),
+            Stage::Register => return write!(f, "The native {} couldn't be registered: {}", self.method, self.kind),
+            Stage::Run => ()
+        }
+        write!(f, "At pc: {}, in {}, the execution throw an error: {}", self.pc, self.method, self.kind)?;
+        for entry in self.backtrace.iter() {
+            write!(f, "\n    at {} (pc: {})", entry.method, entry.pc)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for VmError {}
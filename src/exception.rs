@@ -0,0 +1,93 @@
+//! Exceptions built into the machine. They're classes like the ones of the program, added when it's loaded
+//! unless the program defines a class with the same name:
+//!
+//! ```text
+//! Exception                   .field message S, getMessage ()S
+//!     ArithmeticError         division by zero, or an overflow in a program that traps
+//!     NullReferenceError      a null reference used as an object
+//!     IndexOutOfBoundsError   an array accessed out of its length
+//!     NegativeArraySizeError  an array created with a negative length
+//!     UnknownMethodError      a method that the class of the object doesn't define
+//!     UnknownFieldError       a field that the object doesn't have
+//!     NativeError             a native method that couldn't do its work
+//!     ClassCastError          an object cast to a class it isn't an instance of
+//! ```
+//!
+//! When an instruction fails with one of these errors and a handler catches it, the machine creates the
+//! exception with the description of the error as its `message`. Errors that only a malformed program
+//! causes, like a stack underflow, can't be caught.
+
+use crate::punkfile::assembler;
+use crate::punkfile::class::Class;
+use crate::error::ErrorKind;
+use std::sync::OnceLock;
+
+/// Class every exception inherits from
+pub const EXCEPTION: &str = "Exception";
+
+const SOURCE: &str = "
+.class Exception
+.field message S
+.method constructor ()V
+    RETURN
+.method getMessage ()S
+    LOAD 0
+    GETFIELD message
+    RETURN
+
+.class ArithmeticError
+.super Exception
+
+.class NullReferenceError
+.super Exception
+
+.class IndexOutOfBoundsError
+.super Exception
+
+.class NegativeArraySizeError
+.super Exception
+
+.class UnknownMethodError
+.super Exception
+
+.class UnknownFieldError
+.super Exception
+
+.class NativeError
+.super Exception
+
+.class ClassCastError
+.super Exception
+
+.main
+    RETURN
+";
+
+/// Classes of the built-in exceptions
+pub fn builtins() -> &'static [Class] {
+    static CLASSES: OnceLock<Vec<Class>> = OnceLock::new();
+    CLASSES.get_or_init(|| {
+        let punk_file = assembler::assemble(SOURCE).expect("The built-in exceptions are valid");
+        punk_file.classes.into_iter()
+            .map(|mut cls| {
+                cls.builtin = true;
+                cls
+            })
+            .collect()
+    })
+}
+
+/// Built-in exception that an instruction failing with the error throws, None if the error can't be caught
+pub fn class_of(kind: &ErrorKind) -> Option<&'static str> {
+    match kind {
+        ErrorKind::ArithmeticError(_) => Some("ArithmeticError"),
+        ErrorKind::NullReference => Some("NullReferenceError"),
+        ErrorKind::IndexOutOfBounds(..) => Some("IndexOutOfBoundsError"),
+        ErrorKind::NegativeArraySize(_) => Some("NegativeArraySizeError"),
+        ErrorKind::UnknownMethod(_) => Some("UnknownMethodError"),
+        ErrorKind::UnknownField(_) => Some("UnknownFieldError"),
+        ErrorKind::NativeError(_) => Some("NativeError"),
+        ErrorKind::ClassCast(..) => Some("ClassCastError"),
+        _ => None
+    }
+}
@@ -1,138 +1,615 @@
 use crate::memory::vpk_stack::{StackVM, Frame, Type, RetType};
 use crate::memory::objects::Objects;
-use std::collections::HashMap;
-use crate::punkfile::punk_file::PunkFile;
+use crate::punkfile::punk_file::{PunkFile, Overflow};
 use crate::memory::instructions::Instructions;
+use crate::native::Natives;
+use crate::error::ErrorKind;
+use crate::exception::EXCEPTION;
+use std::fmt;
 
-#[allow(non_camel_case_types)]
+/// Operand that names a label, a method or a class. The index is filled when the code is linked:
+/// the pc of the label, the position of the method in `Instructions::methods` or the position of
+/// the class in `PunkFile::classes`, also the class of a field named as CLASS/FIELD
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub index: usize,
+}
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 #[derive(Debug, Clone)]
 pub enum ByteCode {
     MUL,
     DIV,
     SUB,
+    REM,
+    NEG,
+    AND,
+    OR,
+    XOR,
+    SHL,
+    SHR,
+    LADD,
+    LSUB,
+    LMUL,
+    LDIV,
+    LREM,
+    LNEG,
+    DADD,
+    DSUB,
+    DMUL,
+    DDIV,
+    DREM,
+    DNEG,
+    /// Compares two longs, pushes -1, 0 or 1 if the first is smaller, equal or greater than the second
+    LCMP,
+    /// Compares two doubles like `LCMP`, pushing -1 if any of them is NaN
+    DCMPL,
+    /// Compares two doubles like `LCMP`, pushing 1 if any of them is NaN
+    DCMPG,
+    I2L,
+    I2D,
+    L2I,
+    L2D,
+    D2I,
+    D2L,
     POP,
     IADD,
     SADD,
     NULL,
     PRINT,
     RETURN,
+    /// Throws the exception at the top of the stack
+    THROW,
     LABEL(String),
-    GOTO(String),
+    GOTO(Symbol),
     LOAD(usize),
     STORE(usize),
+    /// Adds the delta to the integer in the local variable
+    INC(usize, i32),
     CONST(String),
-    IF_EQ(String),
-    IF_CMPLT(String),
-    IF_CMPEQ(String),
-    NEW { class: String },
-    GETFIELD { field: String },
-    PUTFIELD { field: String },
-    METHODCALL { method: String },
+    IF_EQ(Symbol),
+    IF_NE(Symbol),
+    IF_LT(Symbol),
+    IF_GE(Symbol),
+    IF_GT(Symbol),
+    IF_LE(Symbol),
+    IF_CMPLT(Symbol),
+    IF_CMPEQ(Symbol),
+    IF_CMPNE(Symbol),
+    IF_CMPGE(Symbol),
+    IF_CMPGT(Symbol),
+    IF_CMPLE(Symbol),
+    /// `constructor` is the index of the method that initializes the objects of the class
+    NEW { class: Symbol, constructor: usize },
+    /// Reads the field of the object, named as FIELD or as CLASS/FIELD to get a field hidden by a subclass.
+    /// `slot` is the position of a field named with its class in the layout of the objects, or the cache of the
+    /// instruction in `Instructions` for a field named without it
+    GETFIELD { field: Symbol, slot: usize },
+    /// Writes the field of the object, named like in `GETFIELD`
+    PUTFIELD { field: Symbol, slot: usize },
+    /// Reads the static field named as CLASS/FIELD, declared by the class or by one of its parents.
+    /// `slot` is its position among the static fields of the class that declares it
+    GETSTATIC { field: Symbol, slot: usize },
+    /// Writes the static field, named like in `GETSTATIC`
+    PUTSTATIC { field: Symbol, slot: usize },
+    /// Calls the method with the receiver below the arguments, or without it if the method is static.
+    /// `slot` is the position of the method in the virtual method tables, or in the interface tables for the methods of an interface
+    METHODCALL { method: Symbol, slot: usize },
+    /// Calls a static method, there's no receiver
+    INVOKESTATIC { method: Symbol },
+    /// Calls the method that the class of the receiver defines or inherits, found in its virtual method table at `slot`,
+    /// or in its table for the interface if the method belongs to one
+    INVOKEVIRTUAL { method: Symbol, slot: usize },
+    /// Calls the method of the named class no matter the class of the receiver, for constructors and the methods of a parent
+    INVOKESPECIAL { method: Symbol },
+    /// Replaces the reference on the stack by whether it's an instance of the class, its subclasses or
+    /// the classes implementing it
+    INSTANCEOF { class: Symbol },
+    /// Fails if the reference on the stack isn't null or an instance of the class, it stays on the stack
+    CHECKCAST { class: Symbol },
+    /// Creates an array with elements of the given type
+    NEWARRAY(RetType),
+    ALOAD,
+    ASTORE,
+    ARRAYLENGTH,
 }
 
-pub fn mul(frame: &mut Frame) -> Result<(), &'static str> {
-    let x = frame.pop();
-    let y = frame.pop();
+/// Writes the instruction as it appears in the punk files
+impl fmt::Display for ByteCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ByteCode::MUL => write!(f, "MUL"),
+            ByteCode::DIV => write!(f, "DIV"),
+            ByteCode::SUB => write!(f, "SUB"),
+            ByteCode::REM => write!(f, "REM"),
+            ByteCode::NEG => write!(f, "NEG"),
+            ByteCode::AND => write!(f, "AND"),
+            ByteCode::OR => write!(f, "OR"),
+            ByteCode::XOR => write!(f, "XOR"),
+            ByteCode::SHL => write!(f, "SHL"),
+            ByteCode::SHR => write!(f, "SHR"),
+            ByteCode::LADD => write!(f, "LADD"),
+            ByteCode::LSUB => write!(f, "LSUB"),
+            ByteCode::LMUL => write!(f, "LMUL"),
+            ByteCode::LDIV => write!(f, "LDIV"),
+            ByteCode::LREM => write!(f, "LREM"),
+            ByteCode::LNEG => write!(f, "LNEG"),
+            ByteCode::DADD => write!(f, "DADD"),
+            ByteCode::DSUB => write!(f, "DSUB"),
+            ByteCode::DMUL => write!(f, "DMUL"),
+            ByteCode::DDIV => write!(f, "DDIV"),
+            ByteCode::DREM => write!(f, "DREM"),
+            ByteCode::DNEG => write!(f, "DNEG"),
+            ByteCode::LCMP => write!(f, "LCMP"),
+            ByteCode::DCMPL => write!(f, "DCMPL"),
+            ByteCode::DCMPG => write!(f, "DCMPG"),
+            ByteCode::I2L => write!(f, "I2L"),
+            ByteCode::I2D => write!(f, "I2D"),
+            ByteCode::L2I => write!(f, "L2I"),
+            ByteCode::L2D => write!(f, "L2D"),
+            ByteCode::D2I => write!(f, "D2I"),
+            ByteCode::D2L => write!(f, "D2L"),
+            ByteCode::POP => write!(f, "POP"),
+            ByteCode::IADD => write!(f, "IADD"),
+            ByteCode::SADD => write!(f, "SADD"),
+            ByteCode::NULL => write!(f, "NULL"),
+            ByteCode::PRINT => write!(f, "PRINT"),
+            ByteCode::RETURN => write!(f, "RETURN"),
+            ByteCode::THROW => write!(f, "THROW"),
+            ByteCode::LABEL(label) => write!(f, "LABEL {}", label),
+            ByteCode::GOTO(label) => write!(f, "GOTO {}", label.name),
+            ByteCode::LOAD(var) => write!(f, "LOAD {}", var),
+            ByteCode::STORE(var) => write!(f, "STORE {}", var),
+            ByteCode::INC(var, delta) => write!(f, "INC {} {}", var, delta),
+            ByteCode::CONST(cst) => write!(f, "CONST {}", cst),
+            ByteCode::IF_EQ(label) | ByteCode::IF_NE(label) | ByteCode::IF_LT(label) | ByteCode::IF_GE(label)
+            | ByteCode::IF_GT(label) | ByteCode::IF_LE(label) | ByteCode::IF_CMPLT(label) | ByteCode::IF_CMPEQ(label)
+            | ByteCode::IF_CMPNE(label) | ByteCode::IF_CMPGE(label) | ByteCode::IF_CMPGT(label) | ByteCode::IF_CMPLE(label) =>
+                write!(f, "{} {}", self.mnemonic(), label.name),
+            ByteCode::NEW {class, ..} => write!(f, "NEW {}", class.name),
+            ByteCode::INSTANCEOF {class} => write!(f, "INSTANCEOF {}", class.name),
+            ByteCode::CHECKCAST {class} => write!(f, "CHECKCAST {}", class.name),
+            ByteCode::GETFIELD {field, ..} => write!(f, "GETFIELD {}", field.name),
+            ByteCode::PUTFIELD {field, ..} => write!(f, "PUTFIELD {}", field.name),
+            ByteCode::GETSTATIC {field, ..} => write!(f, "GETSTATIC {}", field.name),
+            ByteCode::PUTSTATIC {field, ..} => write!(f, "PUTSTATIC {}", field.name),
+            ByteCode::METHODCALL {method, ..} | ByteCode::INVOKESTATIC {method} | ByteCode::INVOKEVIRTUAL {method, ..}
+            | ByteCode::INVOKESPECIAL {method} => write!(f, "{} {}", self.mnemonic(), method.name),
+            ByteCode::NEWARRAY(elem) => write!(f, "NEWARRAY {}", elem),
+            ByteCode::ALOAD => write!(f, "ALOAD"),
+            ByteCode::ASTORE => write!(f, "ASTORE"),
+            ByteCode::ARRAYLENGTH => write!(f, "ARRAYLENGTH"),
+        }
+    }
+}
+
+impl ByteCode {
+    /// Name of the instruction without its operands
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            ByteCode::MUL => "MUL",
+            ByteCode::DIV => "DIV",
+            ByteCode::SUB => "SUB",
+            ByteCode::REM => "REM",
+            ByteCode::NEG => "NEG",
+            ByteCode::AND => "AND",
+            ByteCode::OR => "OR",
+            ByteCode::XOR => "XOR",
+            ByteCode::SHL => "SHL",
+            ByteCode::SHR => "SHR",
+            ByteCode::LADD => "LADD",
+            ByteCode::LSUB => "LSUB",
+            ByteCode::LMUL => "LMUL",
+            ByteCode::LDIV => "LDIV",
+            ByteCode::LREM => "LREM",
+            ByteCode::LNEG => "LNEG",
+            ByteCode::DADD => "DADD",
+            ByteCode::DSUB => "DSUB",
+            ByteCode::DMUL => "DMUL",
+            ByteCode::DDIV => "DDIV",
+            ByteCode::DREM => "DREM",
+            ByteCode::DNEG => "DNEG",
+            ByteCode::LCMP => "LCMP",
+            ByteCode::DCMPL => "DCMPL",
+            ByteCode::DCMPG => "DCMPG",
+            ByteCode::I2L => "I2L",
+            ByteCode::I2D => "I2D",
+            ByteCode::L2I => "L2I",
+            ByteCode::L2D => "L2D",
+            ByteCode::D2I => "D2I",
+            ByteCode::D2L => "D2L",
+            ByteCode::POP => "POP",
+            ByteCode::IADD => "IADD",
+            ByteCode::SADD => "SADD",
+            ByteCode::NULL => "NULL",
+            ByteCode::PRINT => "PRINT",
+            ByteCode::RETURN => "RETURN",
+            ByteCode::THROW => "THROW",
+            ByteCode::LABEL(_) => "LABEL",
+            ByteCode::GOTO(_) => "GOTO",
+            ByteCode::LOAD(_) => "LOAD",
+            ByteCode::STORE(_) => "STORE",
+            ByteCode::INC(..) => "INC",
+            ByteCode::CONST(_) => "CONST",
+            ByteCode::IF_EQ(_) => "IF_EQ",
+            ByteCode::IF_NE(_) => "IF_NE",
+            ByteCode::IF_LT(_) => "IF_LT",
+            ByteCode::IF_GE(_) => "IF_GE",
+            ByteCode::IF_GT(_) => "IF_GT",
+            ByteCode::IF_LE(_) => "IF_LE",
+            ByteCode::IF_CMPLT(_) => "IF_CMPLT",
+            ByteCode::IF_CMPEQ(_) => "IF_CMPEQ",
+            ByteCode::IF_CMPNE(_) => "IF_CMPNE",
+            ByteCode::IF_CMPGE(_) => "IF_CMPGE",
+            ByteCode::IF_CMPGT(_) => "IF_CMPGT",
+            ByteCode::IF_CMPLE(_) => "IF_CMPLE",
+            ByteCode::NEW {..} => "NEW",
+            ByteCode::GETFIELD {..} => "GETFIELD",
+            ByteCode::PUTFIELD {..} => "PUTFIELD",
+            ByteCode::GETSTATIC {..} => "GETSTATIC",
+            ByteCode::PUTSTATIC {..} => "PUTSTATIC",
+            ByteCode::METHODCALL {..} => "METHODCALL",
+            ByteCode::INVOKESTATIC {..} => "INVOKESTATIC",
+            ByteCode::INVOKEVIRTUAL {..} => "INVOKEVIRTUAL",
+            ByteCode::INVOKESPECIAL {..} => "INVOKESPECIAL",
+            ByteCode::INSTANCEOF {..} => "INSTANCEOF",
+            ByteCode::CHECKCAST {..} => "CHECKCAST",
+            ByteCode::NEWARRAY(_) => "NEWARRAY",
+            ByteCode::ALOAD => "ALOAD",
+            ByteCode::ASTORE => "ASTORE",
+            ByteCode::ARRAYLENGTH => "ARRAYLENGTH",
+        }
+    }
+
+    /// Label the instruction jumps to, if it's a jump
+    pub fn jump(&self) -> Option<&Symbol> {
+        match self {
+            ByteCode::GOTO(label) | ByteCode::IF_EQ(label) | ByteCode::IF_NE(label) | ByteCode::IF_LT(label)
+            | ByteCode::IF_GE(label) | ByteCode::IF_GT(label) | ByteCode::IF_LE(label) | ByteCode::IF_CMPLT(label)
+            | ByteCode::IF_CMPEQ(label) | ByteCode::IF_CMPNE(label) | ByteCode::IF_CMPGE(label)
+            | ByteCode::IF_CMPGT(label) | ByteCode::IF_CMPLE(label) => Some(label),
+            _ => None
+        }
+    }
+
+    pub fn jump_mut(&mut self) -> Option<&mut Symbol> {
+        match self {
+            ByteCode::GOTO(label) | ByteCode::IF_EQ(label) | ByteCode::IF_NE(label) | ByteCode::IF_LT(label)
+            | ByteCode::IF_GE(label) | ByteCode::IF_GT(label) | ByteCode::IF_LE(label) | ByteCode::IF_CMPLT(label)
+            | ByteCode::IF_CMPEQ(label) | ByteCode::IF_CMPNE(label) | ByteCode::IF_CMPGE(label)
+            | ByteCode::IF_CMPGT(label) | ByteCode::IF_CMPLE(label) => Some(label),
+            _ => None
+        }
+    }
+
+    /// Method the instruction calls, if it's a call
+    pub fn call(&self) -> Option<&Symbol> {
+        match self {
+            ByteCode::METHODCALL {method, ..} | ByteCode::INVOKESTATIC {method} | ByteCode::INVOKEVIRTUAL {method, ..}
+            | ByteCode::INVOKESPECIAL {method} => Some(method),
+            _ => None
+        }
+    }
+
+    pub fn call_mut(&mut self) -> Option<&mut Symbol> {
+        match self {
+            ByteCode::METHODCALL {method, ..} | ByteCode::INVOKESTATIC {method} | ByteCode::INVOKEVIRTUAL {method, ..}
+            | ByteCode::INVOKESPECIAL {method} => Some(method),
+            _ => None
+        }
+    }
+}
+
+impl Symbol {
+    /// Symbol that still has to be linked
+    pub fn new(name: String) -> Symbol {
+        Symbol {
+            name,
+            index: usize::MAX,
+        }
+    }
+}
+
+/// Result of an integer operation, `checked` is None when the result doesn't fit in an integer
+fn arith(overflow: Overflow, checked: Option<i32>, wrapped: i32, op: &str) -> Result<i32, ErrorKind> {
+    match (checked, overflow) {
+        (Some(x), _) => Ok(x),
+        (None, Overflow::Wrap) => Ok(wrapped),
+        (None, Overflow::Trap) => Err(ErrorKind::ArithmeticError(format!("the result of {} overflows", op)))
+    }
+}
+
+fn division_by_zero() -> ErrorKind {
+    ErrorKind::ArithmeticError("division by zero".to_string())
+}
+
+pub fn mul(frame: &mut Frame, overflow: Overflow) -> Result<(), ErrorKind> {
+    let x = frame.pop()?;
+    let y = frame.pop()?;
 
     match (x, y) {
         (Type::Integer(xx), Type::Integer(yy)) => {
-            let t = Type::Integer(xx * yy);
+            let t = Type::Integer(arith(overflow, xx.checked_mul(yy), xx.wrapping_mul(yy), "MUL")?);
             frame.push(t);
             Ok(())
         },
-        _ => Err("Only the multiplication of integer is supported")
+        _ => Err(ErrorKind::TypeMismatch("Only the multiplication of integer is supported".to_string()))
     }
 }
 
-pub fn div(frame: &mut Frame) -> Result<(), &'static str> {
-    let y = frame.pop();
-    let x = frame.pop();
+pub fn div(frame: &mut Frame, overflow: Overflow) -> Result<(), ErrorKind> {
+    let y = frame.pop()?;
+    let x = frame.pop()?;
 
     match (x, y) {
+        (Type::Integer(_), Type::Integer(0)) => Err(division_by_zero()),
         (Type::Integer(xx), Type::Integer(yy)) => {
-            let t = Type::Integer(xx / yy);
+            let t = Type::Integer(arith(overflow, xx.checked_div(yy), xx.wrapping_div(yy), "DIV")?);
             frame.push(t);
             Ok(())
         },
-        _ => Err("Only the division of integer is supported")
+        _ => Err(ErrorKind::TypeMismatch("Only the division of integer is supported".to_string()))
     }
 }
 
-pub fn sub(frame: &mut Frame) -> Result<(), &'static str> {
-    let y = frame.pop();
-    let x = frame.pop();
+pub fn sub(frame: &mut Frame, overflow: Overflow) -> Result<(), ErrorKind> {
+    let y = frame.pop()?;
+    let x = frame.pop()?;
 
     match (x, y) {
         (Type::Integer(xx), Type::Integer(yy)) => {
-            let t = Type::Integer(xx - yy);
+            let t = Type::Integer(arith(overflow, xx.checked_sub(yy), xx.wrapping_sub(yy), "SUB")?);
             frame.push(t);
             Ok(())
         },
-        _ => Err("Only the subtraction of integer is supported")
+        _ => Err(ErrorKind::TypeMismatch("Only the subtraction of integer is supported".to_string()))
     }
 }
 
-pub fn pop(frame: &mut Frame) -> Result<(), &'static str> {
-    frame.pop();
+/// Remainder of the division, it has the sign of v1. It never overflows
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *    STACK    *
+/// *-------------*            *-------------*
+/// *     v1      *            *             *
+/// *     v2      *    --->    *   v1 % v2   *
+/// *-------------*            *-------------*
+/// ```
+pub fn rem(frame: &mut Frame) -> Result<(), ErrorKind> {
+    let y = frame.pop()?;
+    let x = frame.pop()?;
+
+    match (x, y) {
+        (Type::Integer(_), Type::Integer(0)) => Err(division_by_zero()),
+        (Type::Integer(xx), Type::Integer(yy)) => {
+            frame.push(Type::Integer(xx.wrapping_rem(yy)));
+            Ok(())
+        },
+        _ => Err(ErrorKind::TypeMismatch("Only the remainder of integer is supported".to_string()))
+    }
+}
+
+pub fn neg(frame: &mut Frame, overflow: Overflow) -> Result<(), ErrorKind> {
+    match frame.pop()? {
+        Type::Integer(x) => {
+            frame.push(Type::Integer(arith(overflow, x.checked_neg(), x.wrapping_neg(), "NEG")?));
+            Ok(())
+        },
+        _ => Err(ErrorKind::TypeMismatch("Only the negation of integer is supported".to_string()))
+    }
+}
+
+/// Applies the bitwise operation to v1 and v2. The shifts only use the lowest 5 bits of v2
+/// and `SHR` keeps the sign of v1
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *    STACK    *
+/// *-------------*            *-------------*
+/// *     v1      *            *             *
+/// *     v2      *    --->    *  v1 op v2   *
+/// *-------------*            *-------------*
+/// ```
+pub fn bitwise(frame: &mut Frame, op: &ByteCode) -> Result<(), ErrorKind> {
+    let y = frame.pop()?;
+    let x = frame.pop()?;
+
+    let (xx, yy) = match (x, y) {
+        (Type::Integer(xx), Type::Integer(yy)) => (xx, yy),
+        _ => return Err(ErrorKind::TypeMismatch(format!("{} only supports integers", op.mnemonic())))
+    };
+    let t = match op {
+        ByteCode::AND => xx & yy,
+        ByteCode::OR => xx | yy,
+        ByteCode::XOR => xx ^ yy,
+        ByteCode::SHL => xx.wrapping_shl(yy as u32),
+        ByteCode::SHR => xx.wrapping_shr(yy as u32),
+        _ => return Err(ErrorKind::InvalidInstruction(format!("{} is not a bitwise operation", op)))
+    };
+    frame.push(Type::Integer(t));
     Ok(())
 }
 
-pub fn null(frame: &mut Frame) -> Result<(), &'static str> {
-    frame.push(Type::Object("Null".to_string()));
+/// Applies the arithmetic operation to the longs v1 and v2, or only to v2 if it's `LNEG`
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *    STACK    *
+/// *-------------*            *-------------*
+/// *     v1      *            *             *
+/// *     v2      *    --->    *  v1 op v2   *
+/// *-------------*            *-------------*
+/// ```
+pub fn long_arith(frame: &mut Frame, op: &ByteCode, overflow: Overflow) -> Result<(), ErrorKind> {
+    let long = |v: Type| match v {
+        Type::Long(x) => Ok(x),
+        _ => Err(ErrorKind::TypeMismatch(format!("{} only supports longs", op.mnemonic())))
+    };
+    let checked = |checked: Option<i64>, wrapped: i64| match (checked, overflow) {
+        (Some(x), _) => Ok(x),
+        (None, Overflow::Wrap) => Ok(wrapped),
+        (None, Overflow::Trap) => Err(ErrorKind::ArithmeticError(format!("the result of {} overflows", op.mnemonic())))
+    };
+    let yy = long(frame.pop()?)?;
+    if let ByteCode::LNEG = op {
+        frame.push(Type::Long(checked(yy.checked_neg(), yy.wrapping_neg())?));
+        return Ok(())
+    }
+    let xx = long(frame.pop()?)?;
+    let t = match op {
+        ByteCode::LADD => checked(xx.checked_add(yy), xx.wrapping_add(yy))?,
+        ByteCode::LSUB => checked(xx.checked_sub(yy), xx.wrapping_sub(yy))?,
+        ByteCode::LMUL => checked(xx.checked_mul(yy), xx.wrapping_mul(yy))?,
+        ByteCode::LDIV | ByteCode::LREM if yy == 0 => return Err(division_by_zero()),
+        ByteCode::LDIV => checked(xx.checked_div(yy), xx.wrapping_div(yy))?,
+        ByteCode::LREM => xx.wrapping_rem(yy),
+        _ => return Err(ErrorKind::InvalidInstruction(format!("{} is not an operation of longs", op)))
+    };
+    frame.push(Type::Long(t));
     Ok(())
 }
 
-pub fn iadd(frame: &mut Frame) -> Result<(), &'static str> {
-    let x = frame.pop();
-    let y = frame.pop();
+/// Applies the arithmetic operation to the doubles v1 and v2, or only to v2 if it's `DNEG`. They follow IEEE 754,
+/// dividing by zero gives an infinity or NaN
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *    STACK    *
+/// *-------------*            *-------------*
+/// *     v1      *            *             *
+/// *     v2      *    --->    *  v1 op v2   *
+/// *-------------*            *-------------*
+/// ```
+pub fn double_arith(frame: &mut Frame, op: &ByteCode) -> Result<(), ErrorKind> {
+    let double = |v: Type| match v {
+        Type::Double(x) => Ok(x),
+        _ => Err(ErrorKind::TypeMismatch(format!("{} only supports doubles", op.mnemonic())))
+    };
+    let yy = double(frame.pop()?)?;
+    if let ByteCode::DNEG = op {
+        frame.push(Type::Double(-yy));
+        return Ok(())
+    }
+    let xx = double(frame.pop()?)?;
+    let t = match op {
+        ByteCode::DADD => xx + yy,
+        ByteCode::DSUB => xx - yy,
+        ByteCode::DMUL => xx * yy,
+        ByteCode::DDIV => xx / yy,
+        ByteCode::DREM => xx % yy,
+        _ => return Err(ErrorKind::InvalidInstruction(format!("{} is not an operation of doubles", op)))
+    };
+    frame.push(Type::Double(t));
+    Ok(())
+}
 
-    match (x, y) {
-        (Type::Integer(xx), Type::Integer(yy)) => {
-            let t = Type::Integer(xx + yy);
-            frame.push(t);
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *    STACK    *
+/// *-------------*            *-------------*
+/// *     v1      *            *             *
+/// *     v2      *    --->    *  -1, 0, 1   *
+/// *-------------*            *-------------*
+/// ```
+pub fn lcmp(frame: &mut Frame) -> Result<(), ErrorKind> {
+    let v2 = frame.pop()?;
+    let v1 = frame.pop()?;
+    match (v1, v2) {
+        (Type::Long(x1), Type::Long(x2)) => {
+            frame.push(Type::Integer(x1.cmp(&x2) as i32));
             Ok(())
         },
-        _ => Err("Only the addition of integer is supported")
+        _ => Err(ErrorKind::TypeMismatch("LCMP only supports longs".to_string()))
     }
 }
 
-pub fn sadd(frame: &mut Frame) -> Result<(), &'static str> {
-    let y = frame.pop();
-    let x = frame.pop();
-
-    match (x, y) {
-        (Type::String(xx), Type::String(yy)) => {
-            let t = Type::String(format!("{}{}", xx, yy));
-            frame.push(t);
-            Ok(())
-        },
-        (Type::String(xx), Type::Integer(yy)) => {
-            let t = Type::String(format!("{}{}", xx, yy));
-            frame.push(t);
+/// Like `lcmp` for doubles, `nan` is pushed when they can't be ordered
+pub fn dcmp(frame: &mut Frame, nan: i32) -> Result<(), ErrorKind> {
+    let v2 = frame.pop()?;
+    let v1 = frame.pop()?;
+    match (v1, v2) {
+        (Type::Double(x1), Type::Double(x2)) => {
+            frame.push(Type::Integer(x1.partial_cmp(&x2).map_or(nan, |o| o as i32)));
             Ok(())
         },
+        _ => Err(ErrorKind::TypeMismatch("Comparing doubles only supports doubles".to_string()))
+    }
+}
+
+/// Converts the value on the top of the stack. Longs are truncated to integers, and doubles are rounded towards
+/// zero and saturated to the limits of the type, NaN becomes 0
+pub fn convert(frame: &mut Frame, op: &ByteCode) -> Result<(), ErrorKind> {
+    let t = match (op, frame.pop()?) {
+        (ByteCode::I2L, Type::Integer(x)) => Type::Long(x as i64),
+        (ByteCode::I2D, Type::Integer(x)) => Type::Double(x as f64),
+        (ByteCode::L2I, Type::Long(x)) => Type::Integer(x as i32),
+        (ByteCode::L2D, Type::Long(x)) => Type::Double(x as f64),
+        (ByteCode::D2I, Type::Double(x)) => Type::Integer(x as i32),
+        (ByteCode::D2L, Type::Double(x)) => Type::Long(x as i64),
+        (_, v) => return Err(ErrorKind::TypeMismatch(format!("{} can't convert {:?}", op.mnemonic(), v)))
+    };
+    frame.push(t);
+    Ok(())
+}
+
+pub fn pop(frame: &mut Frame) -> Result<(), ErrorKind> {
+    frame.pop()?;
+    Ok(())
+}
+
+pub fn null(frame: &mut Frame) -> Result<(), ErrorKind> {
+    frame.push(Type::Null);
+    Ok(())
+}
+
+pub fn iadd(frame: &mut Frame, overflow: Overflow) -> Result<(), ErrorKind> {
+    let x = frame.pop()?;
+    let y = frame.pop()?;
+
+    match (x, y) {
         (Type::Integer(xx), Type::Integer(yy)) => {
-            let t = Type::String(format!("{}{}", xx, yy));
+            let t = Type::Integer(arith(overflow, xx.checked_add(yy), xx.wrapping_add(yy), "IADD")?);
             frame.push(t);
             Ok(())
         },
-        (Type::Integer(xx), Type::String(yy)) => {
+        _ => Err(ErrorKind::TypeMismatch("Only the addition of integer is supported".to_string()))
+    }
+}
+
+pub fn sadd(frame: &mut Frame) -> Result<(), ErrorKind> {
+    let y = frame.pop()?;
+    let x = frame.pop()?;
+
+    let text = |v: Type| match v {
+        Type::String(s) => Some(s),
+        v => number_text(&v)
+    };
+    match (text(x), text(y)) {
+        (Some(xx), Some(yy)) => {
             let t = Type::String(format!("{}{}", xx, yy));
             frame.push(t);
             Ok(())
         },
-        _ => Err("Can not construct an string with an object or boolean")
+        _ => Err(ErrorKind::TypeMismatch("Can not construct an string with an object or boolean".to_string()))
     }
 }
 
-pub fn print(frame: &mut Frame) -> Result<(), &'static str> {
-    let x = frame.pop();
+/// How a number is written when it's printed or concatenated, doubles always show a decimal point or an exponent
+fn number_text(value: &Type) -> Option<String> {
+    match value {
+        Type::Integer(x) => Some(x.to_string()),
+        Type::Long(x) => Some(x.to_string()),
+        Type::Double(x) => Some(format!("{:?}", x)),
+        _ => None
+    }
+}
+
+pub fn print(frame: &mut Frame) -> Result<(), ErrorKind> {
+    let x = frame.pop()?;
 
     match x {
-        Type::Integer(x) => {println!("{}", x); Ok(())},
+        Type::Integer(_) | Type::Long(_) | Type::Double(_) => {println!("{}", number_text(&x).unwrap_or_default()); Ok(())},
         Type::String(x) => {println!("{}", x); Ok(())},
         Type::Boolean(b) => {println!("{}", b); Ok(())}
-        _ => Err("Printing an object is not supported"),
+        _ => Err(ErrorKind::TypeMismatch("Printing an object is not supported".to_string())),
     }
 }
 
@@ -141,57 +618,259 @@ Will return true if the execution of the program has to stop because there are n
 in the other way will return false.
 It will report an error if necessary
 */
-pub fn ret(stack: &mut StackVM, frame: &mut Frame) -> Result<bool, &'static str> {
+pub fn ret(stack: &mut StackVM, frame: &mut Frame) -> Result<bool, ErrorKind> {
     if stack.is_empty() {
         Ok(true)
     }
     else {
         let ret_type = frame.get_ret_type();
-        if ret_type != RetType::Void {
-            let ret = frame.pop();
-            let caller_frame = stack.get_frame_mut();
+        let ret = if ret_type != RetType::Void { Some(frame.pop()?) } else { None };
+        let mut caller_frame = stack.pop_frame()?;
+        if let Some(ret) = ret {
             caller_frame.push(ret)
         }
-        stack.ret_pc();
+        *frame = caller_frame;
+        stack.ret_pc()?;
         Ok(false)
     }
 }
 
-pub fn new(objects: &mut Objects, frame: &mut Frame, object: &String, fields: HashMap<String, Type>) -> Result<(), &'static str> {
-    let reff = objects.new_object(object, fields);
+/// The exception is handed to the machine, which unwinds the frames until the handler that catches it.
+/// Only objects of `Exception` or its subclasses can be thrown
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *    STACK    *
+/// *-------------*            *-------------*
+/// *   exc.ref   *    --->    *             *
+/// *-------------*            *-------------*
+/// ```
+pub fn throw(frame: &mut Frame, punk_file: &PunkFile, objects: &Objects) -> Result<(), ErrorKind> {
+    let exception = match frame.pop()? {
+        Type::Object(obj) => obj,
+        Type::Null => return Err(ErrorKind::NullReference),
+        _ => return Err(ErrorKind::TypeMismatch("Only objects can be thrown".to_string()))
+    };
+    let class = &punk_file.classes[objects.get(exception)?.class].this;
+    if !punk_file.is_subclass(class, EXCEPTION) {
+        return Err(ErrorKind::TypeMismatch(format!("{} is not an exception", class)))
+    }
+    Err(ErrorKind::Thrown(exception))
+}
+
+/// Creates the object and returns its handle, the machine calls the constructor after it
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *    STACK    *
+/// *-------------*            *-------------*
+/// *             *    --->    *   obj.ref   *
+/// *-------------*            *-------------*
+/// ```
+pub fn new(objects: &mut Objects, frame: &mut Frame, class: usize, fields: Vec<Type>) -> Result<usize, ErrorKind> {
+    let reff = objects.new_object(class, fields);
     frame.push(Type::Object(reff));
+    Ok(reff)
+}
+
+/// Null and arrays aren't instances of any class
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *    STACK    *
+/// *-------------*            *-------------*
+/// *   obj.ref   *    --->    *   boolean   *
+/// *-------------*            *-------------*
+/// ```
+pub fn instanceof(frame: &mut Frame, ins: &Instructions, objects: &Objects, class: usize) -> Result<(), ErrorKind> {
+    let result = match frame.pop()? {
+        Type::Object(obj) => ins.is_instance(objects.get(obj)?.class, class),
+        Type::Array(_) | Type::Null => false,
+        _ => return Err(ErrorKind::TypeMismatch("A object reference was expected".to_string()))
+    };
+    frame.push(Type::Boolean(result));
     Ok(())
 }
 
-pub fn goto(stack: &mut StackVM, new_pc: usize) -> Result<(), &'static str> {
-    stack.new_pc(new_pc);
+/// Null can be cast to any class, `punk_file` gives the names of the classes when the cast fails
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *    STACK    *
+/// *-------------*            *-------------*
+/// *   obj.ref   *    --->    *   obj.ref   *
+/// *-------------*            *-------------*
+/// ```
+pub fn checkcast(frame: &mut Frame, punk_file: &PunkFile, ins: &Instructions, objects: &Objects, class: usize) -> Result<(), ErrorKind> {
+    let name = &punk_file.classes[class].this;
+    let obj = match frame.pop()? {
+        Type::Object(obj) => obj,
+        Type::Null => {
+            frame.push(Type::Null);
+            return Ok(())
+        },
+        Type::Array(_) => return Err(ErrorKind::ClassCast("array".to_string(), name.clone())),
+        _ => return Err(ErrorKind::TypeMismatch("A object reference was expected".to_string()))
+    };
+    let from = objects.get(obj)?.class;
+    if !ins.is_instance(from, class) {
+        return Err(ErrorKind::ClassCast(punk_file.classes[from].this.clone(), name.clone()))
+    }
+    frame.push(Type::Object(obj));
+    Ok(())
+}
+
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *    STACK    *
+/// *-------------*            *-------------*
+/// *    length   *    --->    *   arr.ref   *
+/// *-------------*            *-------------*
+/// ```
+pub fn newarray(objects: &mut Objects, frame: &mut Frame, elem: &RetType) -> Result<(), ErrorKind> {
+    let len = match frame.pop()? {
+        Type::Integer(len) if len < 0 => return Err(ErrorKind::NegativeArraySize(len)),
+        Type::Integer(len) => len as usize,
+        _ => return Err(ErrorKind::TypeMismatch("The length of an array must be an integer".to_string()))
+    };
+    let reff = objects.new_array(elem.clone(), len);
+    frame.push(Type::Array(reff));
+    Ok(())
+}
+
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *    STACK    *
+/// *-------------*            *-------------*
+/// *   arr.ref   *            *             *
+/// *    index    *    --->    *    value    *
+/// *-------------*            *-------------*
+/// ```
+pub fn aload(frame: &mut Frame, objects: &Objects) -> Result<(), ErrorKind> {
+    let index = array_index(frame)?;
+    let array = objects.get_array(array_ref(frame)?)?;
+    match element(index, array.values.len()) {
+        Some(i) => frame.push(array.values[i].clone()),
+        None => return Err(ErrorKind::IndexOutOfBounds(index, array.values.len()))
+    }
+    Ok(())
+}
+
+/// The value has to be of the type of the elements of the array
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *    STACK    *
+/// *-------------*            *-------------*
+/// *   arr.ref   *            *             *
+/// *    index    *            *             *
+/// *    value    *    --->    *             *
+/// *-------------*            *-------------*
+/// ```
+pub fn astore(frame: &mut Frame, objects: &mut Objects) -> Result<(), ErrorKind> {
+    let value = frame.pop()?;
+    let index = array_index(frame)?;
+    let array = objects.get_array_mut(array_ref(frame)?)?;
+    if !array.elem.accepts(&value) {
+        return Err(ErrorKind::TypeMismatch(format!("{:?} can't be stored in an array of {}", value, array.elem)))
+    }
+    match element(index, array.values.len()) {
+        Some(i) => array.values[i] = value,
+        None => return Err(ErrorKind::IndexOutOfBounds(index, array.values.len()))
+    }
+    Ok(())
+}
+
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *    STACK    *
+/// *-------------*            *-------------*
+/// *   arr.ref   *    --->    *    length   *
+/// *-------------*            *-------------*
+/// ```
+pub fn arraylength(frame: &mut Frame, objects: &Objects) -> Result<(), ErrorKind> {
+    let array = objects.get_array(array_ref(frame)?)?;
+    frame.push(Type::Integer(array.values.len() as i32));
     Ok(())
 }
 
-pub fn load(stack: &mut Frame, local: usize) -> Result<(), &'static str> {
-    stack.load_var(local);
+fn array_ref(frame: &mut Frame) -> Result<usize, ErrorKind> {
+    match frame.pop()? {
+        Type::Array(arr) => Ok(arr),
+        Type::Null => Err(ErrorKind::NullReference),
+        _ => Err(ErrorKind::TypeMismatch("A array reference was expected".to_string()))
+    }
+}
+
+fn array_index(frame: &mut Frame) -> Result<i32, ErrorKind> {
+    match frame.pop()? {
+        Type::Integer(i) => Ok(i),
+        _ => Err(ErrorKind::TypeMismatch("The index of an array must be an integer".to_string()))
+    }
+}
+
+/// Position of the element if the index is inside the bounds
+fn element(index: i32, len: usize) -> Option<usize> {
+    if index >= 0 && (index as usize) < len { Some(index as usize) } else { None }
+}
+
+pub fn goto(stack: &mut StackVM, new_pc: usize) -> Result<(), ErrorKind> {
+    stack.new_pc(new_pc);
     Ok(())
 }
 
-pub fn store(stack: &mut Frame, local: usize) -> Result<(), &'static str> {
-    stack.store_var(local);
+pub fn load(stack: &mut Frame, local: usize) -> Result<(), ErrorKind> {
+    stack.load_var(local)
+}
+
+pub fn store(stack: &mut Frame, local: usize) -> Result<(), ErrorKind> {
+    stack.store_var(local)
+}
+
+pub fn inc(stack: &mut Frame, local: usize, delta: i32, overflow: Overflow) -> Result<(), ErrorKind> {
+    stack.load_var(local)?;
+    match stack.pop()? {
+        Type::Integer(x) => stack.push(Type::Integer(arith(overflow, x.checked_add(delta), x.wrapping_add(delta), "INC")?)),
+        _ => return Err(ErrorKind::TypeMismatch(format!("INC needs an integer in the local variable {}", local)))
+    }
+    stack.store_var(local)
+}
+
+pub fn cnst(stack: &mut Frame, con: &str) -> Result<(), ErrorKind> {
+    stack.push(parse_const(con));
     Ok(())
 }
 
-pub fn cnst(stack: &mut Frame, con: &String) -> Result<(), &'static str> {
-    let t = match con.parse::<bool>() {
+/// Value of the literal of a CONST instruction: a boolean, an integer, a long written with a `L` suffix like `10L`,
+/// a double written with a decimal point or an exponent like `3.14` or `1e-3`, or otherwise a string
+pub fn parse_const(con: &str) -> Type {
+    match con.parse::<bool>() {
         Ok(b) => Type::Boolean(b),
         Err(_) => match con.parse::<i32>() {
             Ok(i) => Type::Integer(i),
-            Err(_) => Type::String(con.clone()),
+            Err(_) => match (con.strip_suffix('L').map(|l| (l, l.parse::<i64>())), double_literal(con)) {
+                (Some((_, Ok(x))), _) => Type::Long(x),
+                (_, Some(x)) => Type::Double(x),
+                _ => Type::String(con.to_string()),
+            }
         }
+    }
+}
+
+/// Value of a literal made of an optional `-`, digits and at least a fraction or an exponent
+fn double_literal(con: &str) -> Option<f64> {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let unsigned = con.strip_prefix('-').unwrap_or(con);
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((m, e)) => (m, Some(e.strip_prefix(['-', '+']).unwrap_or(e))),
+        None => (unsigned, None)
     };
-    stack.push(t);
-    Ok(())
+    let (int, fraction) = match mantissa.split_once('.') {
+        Some((i, f)) => (i, Some(f)),
+        None => (mantissa, None)
+    };
+    let valid = digits(int) && fraction.is_none_or(digits) && exponent.is_none_or(digits)
+        && (fraction.is_some() || exponent.is_some());
+    if valid { con.parse::<f64>().ok() } else { None }
 }
 
-pub fn if_eq(frame: &mut Frame) -> Result<bool, &'static str> {
-    let v = frame.pop();
+pub fn if_eq(frame: &mut Frame) -> Result<bool, ErrorKind> {
+    let v = frame.pop()?;
     match v {
         Type::Integer(x) => {
             if x == 0 {
@@ -199,19 +878,30 @@ pub fn if_eq(frame: &mut Frame) -> Result<bool, &'static str> {
             }
             Ok(false)
         }
-        _ => Err("Can't compare when the value is not a integer")
+        _ => Err(ErrorKind::TypeMismatch("Can't compare when the value is not a integer".to_string()))
+    }
+}
+
+/// Compares the integer on the top of the stack with zero, `IF_NE`, `IF_LT`, `IF_GE`, `IF_GT` and `IF_LE` jump
+/// when `cmp` returns true
+pub fn if_zero(frame: &mut Frame, cmp: fn(i32) -> bool) -> Result<bool, ErrorKind> {
+    match frame.pop()? {
+        Type::Integer(x) => Ok(cmp(x)),
+        _ => Err(ErrorKind::TypeMismatch("Can't compare when the value is not a integer".to_string()))
     }
 }
 
+/// ```text
 /// *-------------*
 /// *    STACK    *
 /// *-------------*
 /// *     v1      *
 /// *     v2      *
 /// *-------------*
-pub fn if_cmpeq(frame: &mut Frame) -> Result<bool, &'static str> {
-    let v2 = frame.pop();
-    let v1 = frame.pop();
+/// ```
+pub fn if_cmpeq(frame: &mut Frame) -> Result<bool, ErrorKind> {
+    let v2 = frame.pop()?;
+    let v1 = frame.pop()?;
     match (v1, v2) {
         (Type::Integer(x1), Type::Integer(x2)) => {
             if x1 == x2 {
@@ -225,19 +915,52 @@ pub fn if_cmpeq(frame: &mut Frame) -> Result<bool, &'static str> {
             }
             Ok(false)
         },
-        _ => Err("Can't compare when the value is not a integer or boolean")
+        _ => Err(ErrorKind::TypeMismatch("Can't compare when the value is not a integer or boolean".to_string()))
+    }
+}
+
+/// ```text
+/// *-------------*
+/// *    STACK    *
+/// *-------------*
+/// *     v1      *
+/// *     v2      *
+/// *-------------*
+/// ```
+pub fn if_cmpne(frame: &mut Frame) -> Result<bool, ErrorKind> {
+    Ok(!if_cmpeq(frame)?)
+}
+
+/// Compares the two integers on the top of the stack, `IF_CMPGE`, `IF_CMPGT` and `IF_CMPLE` jump when `cmp` returns
+/// true for v1 and v2
+/// ```text
+/// *-------------*
+/// *    STACK    *
+/// *-------------*
+/// *     v1      *
+/// *     v2      *
+/// *-------------*
+/// ```
+pub fn if_cmp(frame: &mut Frame, cmp: fn(i32, i32) -> bool) -> Result<bool, ErrorKind> {
+    let v2 = frame.pop()?;
+    let v1 = frame.pop()?;
+    match (v1, v2) {
+        (Type::Integer(x1), Type::Integer(x2)) => Ok(cmp(x1, x2)),
+        _ => Err(ErrorKind::TypeMismatch("Can't compare when the value is not a integer".to_string()))
     }
 }
 
+/// ```text
 /// *-------------*
 /// *    STACK    *
 /// *-------------*
 /// *     v1      *
 /// *     v2      *
 /// *-------------*
-pub fn if_cmplt(frame: &mut Frame) -> Result<bool, &'static str> {
-    let v2 = frame.pop();
-    let v1 = frame.pop();
+/// ```
+pub fn if_cmplt(frame: &mut Frame) -> Result<bool, ErrorKind> {
+    let v2 = frame.pop()?;
+    let v1 = frame.pop()?;
     match (v1, v2) {
         (Type::Integer(x1), Type::Integer(x2)) => {
             if x1 < x2 {
@@ -245,44 +968,129 @@ pub fn if_cmplt(frame: &mut Frame) -> Result<bool, &'static str> {
             }
             Ok(false)
         }
-        _ => Err("Can't compare when the value is not a integer")
+        _ => Err(ErrorKind::TypeMismatch("Can't compare when the value is not a integer".to_string()))
     }
 }
 
+/// ```text
 /// *-------------*            *-------------*
 /// *    STACK    *            *    STACK    *
 /// *-------------*            *-------------*
 /// *    obj.ref  *    --->    *    value    *
 /// *-------------*            *-------------*
-pub fn getfield(stack: &mut Frame, objects: &Objects, field: &String) -> Result<(), &'static str> {
-    let object= match stack.pop() {
+/// ```
+pub fn getfield(stack: &mut Frame, objects: &Objects, ins: &Instructions, field: &Symbol, slot: usize) -> Result<(), ErrorKind> {
+    let object= match stack.pop()? {
         Type::Object(obj) => obj,
-        _ => return Err("A object reference was expected")
+        Type::Null => return Err(ErrorKind::NullReference),
+        _ => return Err(ErrorKind::TypeMismatch("A object reference was expected".to_string()))
     };
-    let field = objects.get_field(object, field);
-    println!("DEBUG: GETFIELD {:?}", field);
+    let slot = field_slot(objects, ins, object, field, slot)?;
+    let field = objects.get_field(object, slot)?;
     stack.push(field);
     Ok(())
 }
 
+/// ```text
 /// *-------------*            *-------------*
 /// *    STACK    *            *    STACK    *
 /// *-------------*            *-------------*
 /// *    obj.ref  *            *             *
 /// *    value    *    --->    *             *
 /// *-------------*            *-------------*
-pub fn putfield(stack: &mut Frame, objects: &mut Objects, field: &String) -> Result<(), &'static str> {
-    let value = stack.pop();
-    println!("DEBUG: PUTFIELD {:?}", value);
-    let object= match stack.pop() {
+/// ```
+pub fn putfield(stack: &mut Frame, objects: &mut Objects, ins: &Instructions, field: &Symbol, slot: usize) -> Result<(), ErrorKind> {
+    let value = stack.pop()?;
+    let object= match stack.pop()? {
         Type::Object(obj) => obj,
-        _ => return Err("A object reference was expected")
+        Type::Null => return Err(ErrorKind::NullReference),
+        _ => return Err(ErrorKind::TypeMismatch("A object reference was expected".to_string()))
     };
-    objects.set_field(object, field, value);
+    let slot = field_slot(objects, ins, object, field, slot)?;
+    objects.set_field(object, slot, value)
+}
+
+/// Slot of the field in the layout of the class of the object
+fn field_slot(objects: &Objects, ins: &Instructions, object: usize, field: &Symbol, slot: usize) -> Result<usize, ErrorKind> {
+    match ins.field_slot(objects.get(object)?.class, field, slot) {
+        Some(s) => Ok(s),
+        None => Err(ErrorKind::UnknownField(format!("{} in object {}", field.name, object)))
+    }
+}
+
+/// `statics` are the static fields of the class that declares the field
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *    STACK    *
+/// *-------------*            *-------------*
+/// *             *    --->    *    value    *
+/// *-------------*            *-------------*
+/// ```
+pub fn getstatic(frame: &mut Frame, statics: &[Type], slot: usize) -> Result<(), ErrorKind> {
+    match statics.get(slot) {
+        Some(v) => frame.push(v.clone()),
+        None => return Err(ErrorKind::UnknownField(format!("static slot {}", slot)))
+    }
     Ok(())
 }
 
-/// A new frame will be created and all the parameters will be stored in local variables in the order of the signature
+/// `statics` are the static fields of the class that declares the field
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *    STACK    *
+/// *-------------*            *-------------*
+/// *    value    *    --->    *             *
+/// *-------------*            *-------------*
+/// ```
+pub fn putstatic(frame: &mut Frame, statics: &mut [Type], slot: usize) -> Result<(), ErrorKind> {
+    let value = frame.pop()?;
+    match statics.get_mut(slot) {
+        Some(v) => *v = value,
+        None => return Err(ErrorKind::UnknownField(format!("static slot {}", slot)))
+    }
+    Ok(())
+}
+
+/// Calls the method with `invokevirtual`, or with `invokestatic` if the method is static or native
+pub fn methodcall(stack: &mut StackVM, frame: &mut Frame, objects: &Objects, ins: &Instructions, natives: &Natives, method: usize, slot: usize) -> Result<(), ErrorKind> {
+    if ins.get_method(method).is_static {
+        invokestatic(stack, frame, ins, natives, method)
+    }
+    else {
+        invokevirtual(stack, frame, objects, ins, method, slot)
+    }
+}
+
+/// A new frame will be created and the arguments will be stored in local variables in the order of the signature.
+/// Native methods run right away and the execution continues after the call
+/// ```text
+/// *-------------*            *-------------*
+/// *    STACK    *            *     VARS    *
+/// *-------------*            *-------------*
+/// *    arg0     *            *    arg0     * 0
+/// *    arg1     *            *    arg1     * 1
+/// *    ....     *    --->    *    ....     * ...
+/// *    argN     *            *    argN     * N
+/// *-------------*            *-------------*
+/// ```
+pub fn invokestatic(stack: &mut StackVM, frame: &mut Frame, ins: &Instructions, natives: &Natives, method: usize) -> Result<(), ErrorKind> {
+    let target = ins.get_method(method);
+    let mut vars = pop_args(frame, target.desc.args.len())?;
+    if let Some(native) = target.native {
+        vars.reverse();
+        if let Some(value) = natives.call(native, &vars)? {
+            frame.push(value);
+        }
+        stack.inc_pc();
+        return Ok(())
+    }
+    invoke(stack, frame, ins, method, None, vars)
+}
+
+/// A new frame will be created and the receiver and the arguments will be stored in local variables in the order
+/// of the signature. `method` is the method named by the instruction, the one that runs is the one at `slot` in
+/// the virtual method table of the class of the receiver
+/// ```text
 /// *-------------*            *-------------*
 /// *    STACK    *            *     VARS    *
 /// *-------------*            *-------------*
@@ -292,57 +1100,59 @@ pub fn putfield(stack: &mut Frame, objects: &mut Objects, field: &String) -> Res
 /// *    ....     *    --->    *    ....     * ...
 /// *    argN     *            *    argN     * N
 /// *-------------*            *-------------*
-pub fn methodcall(stack: &mut StackVM, punk_file: &PunkFile, ins: &Instructions, class: &str, method: &str) -> Result<(), &'static str> {
-    let signature = match punk_file.find_class(class) {
-        Some(c) => {
-            match c.find_method(method) {
-                Some(m) => m.desc.clone(),
-                None => return Err("The method couldn't be found")
-            }
-        },
-        None => return Err("The class couldn't be found")
+/// ```
+pub fn invokevirtual(stack: &mut StackVM, frame: &mut Frame, objects: &Objects, ins: &Instructions, method: usize, slot: usize) -> Result<(), ErrorKind> {
+    let vars = pop_args(frame, ins.get_method(method).desc.args.len())?;
+    let obj_ref = frame.pop()?;
+
+    let class = match obj_ref {
+        Type::Object(obj) => objects.get(obj)?.class,
+        Type::Null => return Err(ErrorKind::NullReference),
+        _ => return Err(ErrorKind::TypeMismatch("Expected a object reference. Stack malformed".to_string()))
     };
-    let v: Vec<&str> = signature.split(|c| c == '(' || c == ')').collect();
-    let n_args: usize = v[1].len();
-    let mut new_frame: Frame = Frame::new();
-    new_frame.set_ret_type(RetType::get_type(v[2]));
-    let caller_frame = stack.get_frame_mut();
+    let method = match ins.dispatch(class, method, slot) {
+        Some(m) => m,
+        None => return Err(ErrorKind::UnknownMethod(ins.get_method(method).name.clone()))
+    };
+
+    invoke(stack, frame, ins, method, Some(obj_ref), vars)
+}
+
+/// Same as `invokevirtual` but it runs the method named by the instruction, whatever the class of the receiver is
+pub fn invokespecial(stack: &mut StackVM, frame: &mut Frame, ins: &Instructions, method: usize) -> Result<(), ErrorKind> {
+    let vars = pop_args(frame, ins.get_method(method).desc.args.len())?;
+    let obj_ref = frame.pop()?;
+    match obj_ref {
+        Type::Object(_) => invoke(stack, frame, ins, method, Some(obj_ref), vars),
+        Type::Null => Err(ErrorKind::NullReference),
+        _ => Err(ErrorKind::TypeMismatch("Expected a object reference. Stack malformed".to_string()))
+    }
+}
+
+/// Pops the arguments of a call, the last one first
+fn pop_args(frame: &mut Frame, n_args: usize) -> Result<Vec<Type>, ErrorKind> {
     let mut vars = Vec::new();
     for _ in 0..n_args {
-        vars.push(caller_frame.pop())
+        vars.push(frame.pop()?)
     }
-    let obj_ref = caller_frame.pop();
-    if !obj_ref.is_object() {return Err("Expected a object reference. Stack malformed")}
+    Ok(vars)
+}
 
-    let mut cls_name: String;
-    match obj_ref.clone() {
-        Type::Object(name) => {
-            cls_name = name;
-            cls_name.retain(|c| !c.is_numeric())
-        }
-        _ => return Err("Expected a object reference. Stack malformed")
-    };
-    let mut new_pc = usize::max_value();
-    while new_pc == usize::max_value() {
-        //println!("DEBUG: new_pc {} | cls_name: {} | method: {}", new_pc, cls_name, method);
-        match ins.get_method_pc(format!("{}/{}", cls_name, method).as_str()) {
-            None => {
-                match punk_file.find_class(cls_name.as_str()) {
-                    None => return Err("No parent class found trying to get the pc method"),
-                    Some(cls) => cls_name = cls.super_cls.clone(),
-                }
-            },
-            Some(pc) => new_pc = pc,
-        }
+/// Pushes the frame of the method with the receiver, static methods have none, and the arguments, which are
+/// popped in reverse order, as its local variables and jumps to its code
+pub fn invoke(stack: &mut StackVM, frame: &mut Frame, ins: &Instructions, method: usize, obj_ref: Option<Type>, mut vars: Vec<Type>) -> Result<(), ErrorKind> {
+    let m = ins.get_method(method);
+    let mut new_frame: Frame = Frame::new();
+    new_frame.set_ret_type(m.desc.ret.clone());
+    new_frame.set_method(method);
+    if let Some(obj_ref) = obj_ref {
+        new_frame.push_var(obj_ref);
     }
-
-    new_frame.push_var(obj_ref);
-    for _ in 0..n_args {
-        new_frame.push_var(vars.pop().unwrap())
+    while let Some(var) = vars.pop() {
+        new_frame.push_var(var)
     }
-    stack.push_frame(new_frame);
-    stack.methodcall_pc(new_pc);
+    let caller_frame = std::mem::replace(frame, new_frame);
+    stack.push_frame(caller_frame);
+    stack.methodcall_pc(m.pc);
     Ok(())
 }
-
-
@@ -0,0 +1,18 @@
+#[macro_use]
+extern crate serde_derive;
+
+pub mod punkfile;
+pub mod isa;
+pub mod memory;
+pub mod vm;
+pub mod error;
+pub mod verifier;
+pub mod disassembler;
+pub mod debugger;
+pub mod trace;
+pub mod profile;
+pub mod native;
+pub mod exception;
+
+pub use crate::vm::{Vm, State};
+pub use crate::error::{VmError, ErrorKind, Stage};
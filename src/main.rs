@@ -1,270 +1,209 @@
-#[macro_use]
-extern crate serde_derive;
-
-mod punkfile;
-mod isa;
-mod memory;
-
-use crate::punkfile::punk_file::PunkFile;
-use crate::memory::objects::Objects;
-use crate::memory::vpk_stack::{StackVM, Frame, Type};
-use crate::memory::instructions::Instructions;
-use crate::isa::bytecode::ByteCode;
-use crate::isa::bytecode;
-use std::collections::HashMap;
+extern crate vpm;
+
+use vpm::punkfile::punk_file::PunkFile;
+use vpm::punkfile::{assembler, binary};
+use vpm::{Vm, verifier, disassembler};
+use vpm::debugger::Debugger;
+use vpm::trace::{Tracer, TraceFormat};
+use vpm::profile::Profiler;
 use std::env::args;
+use std::fs;
+use std::io;
+use std::process::exit;
+
+const USAGE: &str = "Usage: vpm [--verify] [--trace] [--trace-format=text|json] [--profile] [--profile-collapsed=<file>]
+           [--profile-sample=<instructions>] <some_file.json|some_file.pasm|some_file.punk>
+       vpm disasm <some_file.json|some_file.pasm|some_file.punk>
+       vpm debug <some_file.json|some_file.pasm|some_file.punk>
+       vpm convert <input.json|input.pasm|input.punk> <output.json|output.punk>";
+
+/// What to do with the punk file
+#[derive(Default, PartialEq)]
+enum Command {
+    #[default]
+    Run,
+    /// Print the loaded program in the assembler syntax
+    Disasm,
+    /// Write the punk file in the format of the output file
+    Convert,
+    /// Run the program in the interactive debugger
+    Debug,
+}
+
+/// Options given in the command line
+#[derive(Default)]
+struct Options {
+    command: Command,
+    file: String,
+    /// Where convert writes the punk file
+    output: String,
+    /// Check the program with the verifier before running it
+    verify: bool,
+    /// Write every executed instruction to the standard error
+    trace: Option<TraceFormat>,
+    /// Print where the program spent its time once it ends
+    profile: bool,
+    /// File where the profile is written in the collapsed stack format
+    profile_collapsed: Option<String>,
+    /// Instructions between two samples of the calls, None doesn't take samples
+    profile_sample: Option<u64>,
+}
 
 fn main() {
-    // Data structures
-    let mut objects: Objects = Objects::new();
-    let mut stack: StackVM = StackVM::new();
-    let mut instructions: Instructions = Default::default();
-    let file_path = match args().nth(1) {
-        Some(path) => path,
-        None => panic!("Usage: vpk <some_file.json>")
+    let options = match parse_args(args().skip(1)) {
+        Ok(o) => o,
+        Err(msg) => {
+            eprintln!("{}\n{}", msg, USAGE);
+            exit(2)
+        }
+    };
+    let pk: PunkFile = match load(options.file.as_str()) {
+        Ok(pk) => pk,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            exit(1)
+        }
     };
-    let pk: PunkFile = PunkFile::from_file(file_path.as_str());
-
-    // Initialize code structure
-    for cls in pk.classes.iter() {
-        let cls_name = &cls.this;
 
-        for m in cls.methods.iter() {
-            let mthd_name = &m.name;
-            let name = format!("{}/{}", cls_name, mthd_name);
-            let mut code = m.code.to_owned();
-            instructions.new_method(name, &mut code)
+    if options.command == Command::Convert {
+        if let Err(msg) = save(&pk, options.output.as_str()) {
+            eprintln!("{}", msg);
+            exit(1)
         }
+        return
     }
-    let mut main_code = pk.main.code.to_owned();
-    instructions.new_method(String::from("AppMain"), &mut main_code);
-
-    // Initialize the stack with the main frame
-    stack.push_frame(Frame::new());
-
-    // Set the pc to the first instruction of the main code
-    stack.new_pc(instructions.get_method_pc("AppMain").unwrap());
 
-    // Execute code
-    let mut pc: usize;
-    let mut ins: &ByteCode;
-    let mut frame = stack.pop_frame();
-    loop {
-        pc = stack.get_pc();
-        ins = instructions.get_ins(pc);
-
-        match ins {
-            ByteCode::MUL => {
-                match bytecode::mul(&mut frame) {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::DIV => {
-                match bytecode::div(&mut frame) {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::SUB => {
-                match bytecode::sub(&mut frame) {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::POP => {
-                match bytecode::pop(&mut frame) {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::IADD => {
-                match bytecode::iadd(&mut frame) {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::SADD => {
-                match bytecode::sadd(&mut frame) {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
+    if options.command == Command::Disasm || options.command == Command::Debug {
+        match Vm::new(pk) {
+            Ok(vm) if options.command == Command::Disasm => print!("{}", disassembler::disassemble(&vm)),
+            Ok(vm) => {
+                let stdin = io::stdin();
+                if let Err(err) = Debugger::new(vm).run(stdin.lock(), io::stdout()) {
+                    eprintln!("{}", err);
+                    exit(1)
                 }
             },
-            ByteCode::NULL => {
-                match bytecode::null(&mut frame) {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
-                }
+            Err(err) => {
+                eprintln!("{}", err);
+                exit(1)
             }
-            ByteCode::PRINT => {
-                match bytecode::print(&mut frame) {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::RETURN => {
-                match bytecode::ret(&mut stack, &mut frame) {
-                    Ok(b) => {
-                        if b {
-                            // If there's no more frames the execution of the program should be done
-                            break;
-                        }
-                    },
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::NEW {class} => {
-                // Search the class in the constant pool
-                let cls = match pk.find_class(class) {
-                    Some(c) => c,
-                    None => report_error(pc, format!("The class {} couldn't be find", class).as_str())
-                };
-
-                let mut fields: HashMap<String, Type> = HashMap::new();
-                // Check if has parent
-                if cls.super_cls != "" {
-                    let parent = match pk.find_class(&cls.super_cls) {
-                        Some(c) => c,
-                        None => report_error(pc, format!("The parent class {} of class {} couldn't be find", cls.super_cls ,class).as_str())
-                    };
-                    for f in parent.fields.iter() {
-                        fields.insert(f.name.clone(), f.get_type());
-                    }
-                }
-                for f in cls.fields.iter() {
-                    fields.insert(f.name.clone(), f.get_type());
-                }
-
-                // Create object
-                match bytecode::new(&mut objects, &mut frame, class, fields){
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
-                }
+        }
+        return
+    }
 
-                // Call constructor
-                let reference = frame.pop();
-                frame.push(reference.clone());
-                frame.push(reference);
-                stack.push_frame(frame.clone());
-                let constructor_pc = instructions.get_method_pc(format!("{}/{}", class, "constructor").as_str());
-                match bytecode::methodcall(&mut stack, &pk, &instructions, class, "constructor") {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg),
-                }
-            },
-            ByteCode::GOTO(label) => {
-                let label_pc = instructions.get_label_pc(label);
-                match bytecode::goto(&mut stack, label_pc) {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::LOAD(var) => {
-                match bytecode::load(&mut frame, var.clone()) {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::STORE(var) => {
-                match bytecode::store(&mut frame, var.clone()) {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::CONST(cst) => {
-                match bytecode::cnst(&mut frame, cst) {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::IF_EQ(label) => {
-                let label_pc = instructions.get_label_pc(label);
-                match bytecode::if_eq(&mut frame) {
-                    Ok(b) => {
-                        if b {
-                            stack.new_pc(label_pc)
-                        }
-                        else {
-                            stack.inc_pc();
-                        }
-                    },
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::IF_CMPLT(label) => {
-                let label_pc = instructions.get_label_pc(label);
-                match bytecode::if_cmplt(&mut frame) {
-                    Ok(b) => {
-                        if b {
-                            stack.new_pc(label_pc)
-                        }
-                        else {
-                            stack.inc_pc();
-                        }
-                    },
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::IF_CMPEQ(label) => {
-                let label_pc = instructions.get_label_pc(label);
-                match bytecode::if_cmpeq(&mut frame) {
-                    Ok(b) => {
-                        if b {
-                            stack.new_pc(label_pc)
-                        }
-                        else {
-                            stack.inc_pc();
-                        }
-                    },
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::GETFIELD {field} => {
-                match bytecode::getfield(&mut frame, &objects, field) {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::PUTFIELD {field} => {
-                match bytecode::putfield(&mut frame, &mut objects, field) {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            ByteCode::METHODCALL {method} => {
-                stack.push_frame(frame.clone());
-                let method_pc = instructions.get_method_pc(method.as_str());
-                let v: Vec<&str> = method.split('/').collect();
-                let class = v[0];
-                let method_name = v[1];
-                match bytecode::methodcall(&mut stack, &pk, &instructions, class, method_name) {
-                    Ok(()) => {},
-                    Err(msg) => report_error(pc, msg)
-                }
-            },
-            _ => ()
-        };
+    if options.verify {
+        if let Err(errors) = verifier::verify(&pk) {
+            for err in errors.iter() {
+                eprintln!("{}", err);
+            }
+            exit(1)
+        }
+    }
 
-        match ins {
-            ByteCode::GOTO(_) | ByteCode::IF_EQ(_) | ByteCode::IF_CMPEQ(_) | ByteCode::IF_CMPLT(_) | ByteCode::METHODCALL {method: _} | ByteCode::RETURN | ByteCode::NEW {class: _} => (),
-            _ => {
-                stack.inc_pc();
+    let mut vm = match Vm::new(pk) {
+        Ok(vm) => vm,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1)
+        }
+    };
+    vm.set_tracer(options.trace.map(Tracer::stderr));
+    if options.profile {
+        vm.set_profiler(Some(options.profile_sample.map_or_else(Profiler::new, Profiler::sampling)));
+    }
+    let result = vm.run();
+    if let Some(profiler) = vm.profiler() {
+        eprint!("{}", profiler.report(vm.instructions()));
+        if let Some(path) = &options.profile_collapsed {
+            if let Err(err) = fs::write(path, profiler.collapsed(vm.instructions())) {
+                eprintln!("{}: {}", path, err);
             }
-        };
+        }
+    }
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        exit(1)
+    }
+}
 
-        match ins {
-            ByteCode::METHODCALL {method: _} | ByteCode::RETURN | ByteCode::NEW {class: _} => {
-                frame = stack.pop_frame()
-            },
-            _ => ()
+/// Reads the punk file, the format is chosen by the extension of the file
+fn load(path: &str) -> Result<PunkFile, String> {
+    if path.ends_with(".pasm") {
+        let source = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(err) => return Err(format!("{}: {}", path, err))
         };
-
-        //println!("DEBUG: {:#?}", frame)
-
+        return assembler::assemble(source.as_str()).map_err(|err| format!("{}:{}", path, err))
+    }
+    if path.ends_with(".punk") {
+        let bytes = match fs::read(path) {
+            Ok(b) => b,
+            Err(err) => return Err(format!("{}: {}", path, err))
+        };
+        return binary::read(bytes.as_slice()).map_err(|err| format!("{}: {}", path, err))
     }
+    PunkFile::from_file(path).map_err(|err| err.to_string())
+}
 
+/// Writes the punk file, the format is chosen by the extension of the file
+fn save(pk: &PunkFile, path: &str) -> Result<(), String> {
+    let bytes = if path.ends_with(".punk") {
+        binary::write(pk).map_err(|err| format!("{}: {}", path, err))?
+    }
+    else if path.ends_with(".json") {
+        pk.to_json().into_bytes()
+    }
+    else {
+        return Err(format!("{}: the output must be a .json or a .punk file", path))
+    };
+    fs::write(path, bytes).map_err(|err| format!("{}: {}", path, err))
 }
 
-fn report_error(pc: usize, msg: &str) -> ! {
-    panic!("At pc: {}, the execution throw an error: {}", pc, msg)
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut options: Options = Default::default();
+    let mut args = args.peekable();
+    match args.peek().map(String::as_str) {
+        Some("disasm") => options.command = Command::Disasm,
+        Some("convert") => options.command = Command::Convert,
+        Some("debug") => options.command = Command::Debug,
+        _ => ()
+    }
+    if options.command != Command::Run {
+        args.next();
+    }
+    for arg in args {
+        match arg.as_str() {
+            "--verify" | "--trace" | "--profile" if options.command != Command::Run => return Err(format!("{} can only be used when running a program", arg)),
+            flag if ["--trace-format=", "--profile-collapsed=", "--profile-sample="].iter().any(|o| flag.starts_with(o)) && options.command != Command::Run =>
+                return Err(format!("{} can only be used when running a program", flag)),
+            "--verify" => options.verify = true,
+            "--trace" => options.trace = options.trace.or(Some(TraceFormat::Text)),
+            "--trace-format=text" => options.trace = Some(TraceFormat::Text),
+            "--trace-format=json" => options.trace = Some(TraceFormat::Json),
+            flag if flag.starts_with("--trace-format=") => return Err(format!("Unknown trace format {}", &flag["--trace-format=".len()..])),
+            "--profile" => options.profile = true,
+            flag if flag.starts_with("--profile-collapsed=") => {
+                options.profile = true;
+                options.profile_collapsed = Some(flag["--profile-collapsed=".len()..].to_string());
+            },
+            flag if flag.starts_with("--profile-sample=") => {
+                options.profile = true;
+                options.profile_sample = match flag["--profile-sample=".len()..].parse::<u64>() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => return Err(format!("Invalid sampling interval {}", &flag["--profile-sample=".len()..]))
+                };
+            },
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            _ if options.file.is_empty() => options.file = arg,
+            _ if options.command == Command::Convert && options.output.is_empty() => options.output = arg,
+            _ => return Err(format!("Unexpected argument {}", arg)),
+        }
+    }
+    if options.file.is_empty() {
+        return Err(String::from("No file given"))
+    }
+    if options.command == Command::Convert && options.output.is_empty() {
+        return Err(String::from("No output file given"))
+    }
+    Ok(options)
 }
@@ -1,54 +1,600 @@
+use std::cell::Cell;
 use std::collections::HashMap;
-use crate::isa::bytecode::ByteCode;
+use crate::isa::bytecode::{ByteCode, Symbol};
+use crate::punkfile::code::Code;
+use crate::error::{ErrorKind, VmError};
+use crate::punkfile::descriptor::Descriptor;
+use crate::punkfile::punk_file::PunkFile;
+use crate::native::Natives;
+use crate::memory::vpk_stack::{Type, RetType};
+use crate::vm::CLINIT;
+
+/// A method whose code lives in `Instructions::code`
+#[derive(Debug)]
+pub struct Method {
+    /// Name in the form CLASS/METHOD
+    pub name: String,
+    pub class: String,
+    /// Position of the class in `PunkFile::classes` once linked, None for the main code and the natives
+    pub class_index: Option<usize>,
+    /// Position where the code of the method starts
+    pub pc: usize,
+    pub desc: Descriptor,
+    // Map where K -> label | V -> pc of the label
+    pub labels: HashMap<String, usize>,
+    /// Position in the table of natives if the method is implemented in Rust, it has no code then
+    pub native: Option<usize>,
+    /// Exception table of the method, in the order they're tried
+    pub handlers: Vec<Handler>,
+    /// Static methods, and natives, are called without a receiver
+    pub is_static: bool,
+    /// Methods of an interface, they don't have code and the classes implementing the interface define them
+    pub is_abstract: bool,
+}
+
+/// Entry of the exception table of a method, its labels point to the pcs in `Instructions::code`
+#[derive(Debug)]
+pub struct Handler {
+    pub start: Symbol,
+    pub end: Symbol,
+    pub handler: Symbol,
+    pub class: String,
+}
+
+/// Instance methods of a class by slot. A class starts with the slots of its parent and its overrides replace
+/// them, so a method name has the same slot in a class and in all its subclasses
+#[derive(Debug, Clone, Default)]
+pub struct VTable {
+    /// Names of the methods, without the class
+    pub names: Vec<String>,
+    /// Methods as positions in `Instructions::methods`
+    pub methods: Vec<usize>,
+    /// Class that added each slot, as its position in `PunkFile::classes`. Two classes whose slot was added by
+    /// the same class inherit it from that class, so the slot holds the same method or an override of it
+    pub owners: Vec<usize>,
+    /// Interface table, for each interface that the class implements its position in `PunkFile::classes` and the
+    /// methods that implement its methods, in the order the interface declares them
+    pub interfaces: Vec<(usize, Vec<usize>)>,
+    /// The class, its parents and the interfaces it implements, as positions in `PunkFile::classes`, sorted
+    pub supertypes: Vec<usize>,
+}
+
+/// Fields of the objects of a class by slot. A class starts with the slots of its parent and adds one for each
+/// field it declares, also when a parent declares a field with the same name, so a field has the same slot in
+/// the class that declares it and in all its subclasses
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    /// Class that declares the field of each slot, as its position in `PunkFile::classes`, and its name
+    pub fields: Vec<(usize, String)>,
+    /// Initial value of each slot
+    pub values: Vec<Type>,
+    // Map where K -> field | V -> slot, a field of the class hides the fields of its parents with the same name
+    index: HashMap<String, usize>,
+}
 
 #[derive(Default)]
 pub struct Instructions {
-    // Map where K -> ClassMethod | V -> position where code starts
-    pub methods: HashMap<String, usize>,
-    pub labels: HashMap<String, usize>,
+    pub methods: Vec<Method>,
+    /// Virtual method table of each class, by position in `PunkFile::classes`
+    pub vtables: Vec<VTable>,
+    /// Field layout of each class, by position in `PunkFile::classes`
+    pub layouts: Vec<Layout>,
+    /// Static fields that each class declares, by position in `PunkFile::classes`
+    pub statics: Vec<Layout>,
+    /// Cache of each GETFIELD and PUTFIELD of a field named without its class, with the class of the last object
+    /// it accessed and the slot of the field in it
+    field_caches: Vec<Cell<(usize, usize)>>,
+    // Map where K -> ClassMethod | V -> position of the method in methods
+    method_index: HashMap<String, usize>,
     pub code: Vec<ByteCode>,
 }
 
+impl VTable {
+    /// Slot of the method with the name, without the class
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+}
+
+impl Layout {
+    /// Slot of the field with the name that the class sees, the one declared closest to it
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        self.index.get(name).copied()
+    }
+
+    fn push(&mut self, class: usize, name: &str, value: Type) {
+        self.index.insert(name.to_string(), self.fields.len());
+        self.fields.push((class, name.to_string()));
+        self.values.push(value);
+    }
+}
+
 impl Instructions {
-    pub fn new_method(&mut self, name: String, code: &mut Vec<ByteCode>) {
+    /// Appends the code of the method and its exception table. Labels are only visible inside the method that
+    /// defines them, it fails if a label is defined twice. Code that doesn't belong to any class has an empty class
+    pub fn new_method(&mut self, class: &str, method: &Code) -> Result<(), VmError> {
+        let full_name = if class.is_empty() { method.name.clone() } else { format!("{}/{}", class, method.name) };
         let method_pc = self.code.len();
-        self.methods.insert(name, method_pc);
-        self.get_labels(method_pc, code);
-        self.code.append(code);
+        let desc = match Descriptor::parse(&method.desc) {
+            Ok(d) => d,
+            Err(kind) => return Err(VmError::at(kind, full_name, method_pc))
+        };
+        let labels = Instructions::get_labels(&full_name, method_pc, &method.code)?;
+        let mut handlers = Vec::new();
+        for e in method.exceptions.iter() {
+            let label = |name: &str| match labels.get(name) {
+                Some(pc) => Ok(Symbol { name: name.to_string(), index: *pc }),
+                None => Err(VmError::at(ErrorKind::UnknownLabel(name.to_string()), full_name.clone(), method_pc))
+            };
+            handlers.push(Handler {
+                start: label(&e.start)?,
+                end: label(&e.end)?,
+                handler: label(&e.handler)?,
+                class: e.class.clone(),
+            });
+        }
+        self.method_index.insert(full_name.clone(), self.methods.len());
+        self.methods.push(Method {
+            name: full_name,
+            class: class.to_string(),
+            class_index: None,
+            pc: method_pc,
+            desc,
+            labels,
+            native: None,
+            handlers,
+            is_static: method.is_static,
+            is_abstract: method.is_abstract,
+        });
+        self.code.extend(method.code.iter().cloned());
+        Ok(())
+    }
+
+    /// Adds the native methods that don't have a method with code with the same name.
+    /// It has to be called after adding the methods with code, calling it again adds the new natives
+    pub fn new_natives(&mut self, natives: &Natives) {
+        for (i, native) in natives.iter().enumerate() {
+            if let Some(index) = self.method_index.get(&native.name) {
+                // A native registered again may have changed its descriptor
+                let method = &mut self.methods[*index];
+                if method.native == Some(i) {
+                    method.desc = native.desc.clone();
+                }
+                continue
+            }
+            let class = native.name.split('/').next().unwrap_or("");
+            self.method_index.insert(native.name.clone(), self.methods.len());
+            self.methods.push(Method {
+                name: native.name.clone(),
+                class: class.to_string(),
+                class_index: None,
+                pc: self.code.len(),
+                desc: native.desc.clone(),
+                labels: HashMap::new(),
+                native: Some(i),
+                handlers: Vec::new(),
+                is_static: true,
+                is_abstract: false,
+            });
+        }
+    }
+
+    /// Builds the virtual method table of every class. It has to be called once the methods of the classes are
+    /// added, it fails if a class inherits from itself or if a method overrides another one with a different descriptor
+    pub fn build_vtables(&mut self, punk_file: &PunkFile) -> Result<(), VmError> {
+        let mut vtables = vec![None; punk_file.classes.len()];
+        for class in 0..punk_file.classes.len() {
+            self.build_vtable(punk_file, class, &mut vtables, &mut Vec::new())?;
+        }
+        self.vtables = vtables.into_iter().flatten().collect();
+        Ok(())
+    }
+
+    /// Builds the table of the class after the ones of its parents, `visiting` holds the classes whose table is
+    /// being built
+    fn build_vtable(&self, punk_file: &PunkFile, class: usize, vtables: &mut Vec<Option<VTable>>, visiting: &mut Vec<usize>) -> Result<(), VmError> {
+        if vtables[class].is_some() {
+            return Ok(())
+        }
+        let cls = &punk_file.classes[class];
+        let error = |kind| VmError::at(kind, cls.this.clone(), 0);
+        if visiting.contains(&class) {
+            return Err(error(ErrorKind::InheritanceCycle(cls.this.clone())))
+        }
+        let mut vtable = if cls.super_cls.is_empty() {
+            VTable::default()
+        }
+        else {
+            let parent = match punk_file.classes.iter().position(|c| c.this == cls.super_cls) {
+                Some(p) => p,
+                None => return Err(error(ErrorKind::UnknownClass(cls.super_cls.clone())))
+            };
+            visiting.push(class);
+            self.build_vtable(punk_file, parent, vtables, visiting)?;
+            visiting.pop();
+            vtables[parent].clone().unwrap()
+        };
+
+        for m in cls.methods.iter() {
+            let index = self.get_method_index(format!("{}/{}", cls.this, m.name).as_str()).unwrap();
+            let method = &self.methods[index];
+            match vtable.slot(&m.name) {
+                Some(slot) => {
+                    let overridden = &self.methods[vtable.methods[slot]];
+                    if method.is_static || method.desc != overridden.desc {
+                        let modifier = if method.is_static { "static " } else { "" };
+                        let msg = format!("{}{} {} can't override {}", modifier, method.name, m.desc, overridden.name);
+                        return Err(VmError::at(ErrorKind::IncompatibleOverride(msg), method.name.clone(), method.pc))
+                    }
+                    vtable.methods[slot] = index;
+                },
+                None if !method.is_static => {
+                    vtable.names.push(m.name.clone());
+                    vtable.methods.push(index);
+                    vtable.owners.push(class);
+                },
+                None => ()
+            }
+        }
+        vtables[class] = Some(vtable);
+        Ok(())
     }
 
-    pub fn get_ins(&self, pc: usize) -> &ByteCode {
+    /// Checks the interfaces and the classes implementing them and builds the interface tables and the supertypes
+    /// of each class. An interface doesn't
+    /// have a parent nor fields and all its methods are abstract, a class has to implement the methods of every
+    /// interface it implements, directly, through its parents or through other interfaces, with the same descriptor.
+    /// It has to be called after `build_vtables`
+    pub fn check_interfaces(&mut self, punk_file: &PunkFile) -> Result<(), VmError> {
+        for cls in punk_file.classes.iter() {
+            let error = |kind| VmError::at(kind, cls.this.clone(), 0);
+            if cls.is_interface {
+                if !cls.super_cls.is_empty() {
+                    return Err(error(ErrorKind::InvalidInterface(format!("{} can't have a parent", cls.this))))
+                }
+                if !cls.fields.is_empty() {
+                    return Err(error(ErrorKind::InvalidInterface(format!("{} can't have fields", cls.this))))
+                }
+                if let Some(m) = cls.methods.iter().find(|m| !m.is_abstract || m.is_static) {
+                    return Err(error(ErrorKind::InvalidInterface(format!("{}/{} has to be abstract", cls.this, m.name))))
+                }
+            }
+            else if let Some(m) = cls.methods.iter().find(|m| m.is_abstract) {
+                return Err(error(ErrorKind::InvalidInterface(format!("{}/{} is abstract but {} isn't an interface", cls.this, m.name, cls.this))))
+            }
+            for name in cls.interfaces.iter() {
+                match punk_file.find_class(name) {
+                    Some(i) if i.is_interface => (),
+                    Some(_) => return Err(error(ErrorKind::InvalidInterface(format!("{} isn't an interface", name)))),
+                    None => return Err(error(ErrorKind::UnknownClass(name.clone())))
+                }
+            }
+        }
+        for (class, cls) in punk_file.classes.iter().enumerate() {
+            let error = |kind| VmError::at(kind, cls.this.clone(), 0);
+            let interfaces = Instructions::interfaces_of(punk_file, class);
+            if cls.is_interface {
+                if interfaces.contains(&class) {
+                    return Err(error(ErrorKind::InheritanceCycle(cls.this.clone())))
+                }
+                continue
+            }
+            let mut supertypes = interfaces.clone();
+            let mut parent = Some(class);
+            while let Some(p) = parent {
+                // build_vtables has checked that there are no cycles
+                supertypes.push(p);
+                parent = punk_file.classes.iter().position(|c| c.this == punk_file.classes[p].super_cls);
+            }
+            supertypes.sort_unstable();
+            supertypes.dedup();
+            self.vtables[class].supertypes = supertypes;
+            let vtable = &self.vtables[class];
+            let mut itable = Vec::new();
+            for i in interfaces {
+                let mut methods = Vec::new();
+                for m in punk_file.classes[i].methods.iter() {
+                    let expected = &self.methods[self.get_method_index(format!("{}/{}", punk_file.classes[i].this, m.name).as_str()).unwrap()];
+                    match vtable.slot(&m.name) {
+                        Some(slot) if self.methods[vtable.methods[slot]].desc == expected.desc => methods.push(vtable.methods[slot]),
+                        _ => {
+                            let msg = format!("{} doesn't implement {} {}", cls.this, expected.name, m.desc);
+                            return Err(error(ErrorKind::UnimplementedMethod(msg)))
+                        }
+                    }
+                }
+                itable.push((i, methods));
+            }
+            self.vtables[class].interfaces = itable;
+        }
+        Ok(())
+    }
+
+    /// Interfaces that the class implements, as positions in `PunkFile::classes`, directly, through its parents
+    /// or through the interfaces they implement. The class itself is only included if it's an interface that
+    /// implements itself
+    fn interfaces_of(punk_file: &PunkFile, class: usize) -> Vec<usize> {
+        let find = |name: &str| punk_file.classes.iter().position(|c| c.this == name);
+        let mut pending = Vec::new();
+        let mut cls = Some(class);
+        while let Some(c) = cls {
+            pending.extend(punk_file.classes[c].interfaces.iter().filter_map(|i| find(i)));
+            cls = find(&punk_file.classes[c].super_cls);
+        }
+        let mut interfaces = Vec::new();
+        while let Some(i) = pending.pop() {
+            if !interfaces.contains(&i) {
+                interfaces.push(i);
+                pending.extend(punk_file.classes[i].interfaces.iter().filter_map(|i| find(i)));
+            }
+        }
+        interfaces
+    }
+
+    /// Builds the field layout of every class and the table of its static fields. It has to be called after
+    /// `build_vtables`, which rejects the classes that inherit from themselves or from a class that doesn't exist
+    pub fn build_layouts(&mut self, punk_file: &PunkFile) {
+        let parent = |class: usize| punk_file.classes.iter().position(|c| c.this == punk_file.classes[class].super_cls);
+        self.layouts = (0..punk_file.classes.len()).map(|class| {
+            let mut chain = vec![class];
+            while let Some(p) = parent(chain[chain.len() - 1]) {
+                chain.push(p);
+            }
+            let mut layout = Layout::default();
+            for c in chain.into_iter().rev() {
+                for f in punk_file.classes[c].fields.iter().filter(|f| !f.is_static) {
+                    layout.push(c, &f.name, f.get_type());
+                }
+            }
+            layout
+        }).collect();
+        self.statics = punk_file.classes.iter().enumerate().map(|(class, cls)| {
+            let mut statics = Layout::default();
+            for f in cls.fields.iter().filter(|f| f.is_static) {
+                statics.push(class, &f.name, f.get_type());
+            }
+            statics
+        }).collect();
+    }
+
+    /// Rewrites the operands of the jumps, method calls, field accesses and object creations with the pc, method,
+    /// class or slot they refer to, so running the code doesn't need to search anything by its name.
+    /// It has to be called once all the methods are added
+    pub fn link(&mut self, punk_file: &PunkFile) -> Result<(), VmError> {
+        for method in self.methods.iter_mut() {
+            if let Some(h) = method.handlers.iter().find(|h| punk_file.find_class(&h.class).is_none()) {
+                return Err(VmError::at(ErrorKind::UnknownClass(h.class.clone()), method.name.clone(), method.pc))
+            }
+            if method.native.is_none() {
+                method.class_index = punk_file.classes.iter().position(|c| c.this == method.class);
+            }
+            let initializer = method.class_index.is_some() && method.name.ends_with(&format!("/{}", CLINIT));
+            if initializer && (!method.is_static || !method.desc.args.is_empty() || method.desc.ret != RetType::Void) {
+                return Err(VmError::at(ErrorKind::InvalidCall(format!("{} has to be static ()V", method.name)), method.name.clone(), method.pc))
+            }
+        }
+        let mut code = std::mem::take(&mut self.code);
+        for (i, method) in self.methods.iter().enumerate() {
+            let end = match self.methods.get(i + 1) {
+                Some(m) => m.pc,
+                None => code.len()
+            };
+            for (pc, ins) in (method.pc..end).zip(code[method.pc..end].iter_mut()) {
+                let error = |kind| VmError::at(kind, method.name.clone(), pc);
+                if let Some(label) = ins.jump_mut() {
+                    label.index = match method.labels.get(&label.name) {
+                        Some(label_pc) => *label_pc,
+                        None => return Err(error(ErrorKind::UnknownLabel(label.name.clone())))
+                    }
+                }
+                if let Some(target) = ins.call_mut() {
+                    let v: Vec<&str> = target.name.split('/').collect();
+                    if v.len() != 2 {
+                        return Err(error(ErrorKind::UnknownMethod(target.name.clone())))
+                    }
+                    if v[1] == CLINIT {
+                        return Err(error(ErrorKind::InvalidCall(format!("{} is only called by the machine", target.name))))
+                    }
+                    target.index = match self.resolve_method(punk_file, v[0], v[1]) {
+                        Some(index) => index,
+                        None => return Err(error(ErrorKind::UnknownMethod(target.name.clone())))
+                    }
+                }
+                match ins {
+                    // The methods of an interface have the slot of their position in it, the methods of a class
+                    // the one in the virtual method table of the class that declares them
+                    ByteCode::METHODCALL {method: target, slot} | ByteCode::INVOKEVIRTUAL {method: target, slot} if !self.methods[target.index].is_static => {
+                        let method = &self.methods[target.index];
+                        let (class, name) = (method.class_index.unwrap(), &method.name[method.class.len() + 1..]);
+                        *slot = if method.is_abstract {
+                            punk_file.classes[class].methods.iter().position(|m| m.name == name).unwrap()
+                        }
+                        else {
+                            self.vtables[class].slot(name).unwrap()
+                        };
+                    },
+                    // The slot of a field named without its class depends on the object, these instructions get a
+                    // cache instead. Linking again keeps the cache, the layouts don't change
+                    ByteCode::GETFIELD {field, slot} | ByteCode::PUTFIELD {field, slot} if !field.name.contains('/') && *slot == usize::MAX => {
+                        *slot = self.field_caches.len();
+                        self.field_caches.push(Cell::new((usize::MAX, usize::MAX)));
+                    },
+                    ByteCode::GETFIELD {field, slot} | ByteCode::PUTFIELD {field, slot} => {
+                        // The fields named without their class already have their cache
+                        if let Some((class, name)) = field.name.split_once('/') {
+                            field.index = match punk_file.classes.iter().position(|c| c.this == class) {
+                                Some(index) => index,
+                                None => return Err(error(ErrorKind::UnknownClass(class.to_string())))
+                            };
+                            *slot = match self.layouts[field.index].slot(name) {
+                                Some(s) => s,
+                                None => return Err(error(ErrorKind::UnknownField(field.name.clone())))
+                            };
+                        }
+                    },
+                    ByteCode::GETSTATIC {field, slot} | ByteCode::PUTSTATIC {field, slot} => {
+                        let (class, name) = match field.name.split_once('/') {
+                            Some(f) => f,
+                            None => return Err(error(ErrorKind::UnknownField(field.name.clone())))
+                        };
+                        let (declaring, s) = match self.resolve_static(punk_file, class, name) {
+                            Ok(Some(found)) => found,
+                            Ok(None) => return Err(error(ErrorKind::UnknownField(field.name.clone()))),
+                            Err(kind) => return Err(error(kind))
+                        };
+                        field.index = declaring;
+                        *slot = s;
+                    },
+                    ByteCode::INVOKESTATIC {method: target} if !self.methods[target.index].is_static =>
+                        return Err(error(ErrorKind::InvalidCall(format!("{} is not static", target.name)))),
+                    ByteCode::INVOKEVIRTUAL {method: target, ..} | ByteCode::INVOKESPECIAL {method: target} if self.methods[target.index].is_static =>
+                        return Err(error(ErrorKind::InvalidCall(format!("{} is static", target.name)))),
+                    ByteCode::INVOKESPECIAL {method: target} if self.methods[target.index].is_abstract =>
+                        return Err(error(ErrorKind::InvalidCall(format!("{} is abstract", target.name)))),
+                    ByteCode::INSTANCEOF {class} | ByteCode::CHECKCAST {class} => {
+                        class.index = match punk_file.classes.iter().position(|c| c.this == class.name) {
+                            Some(index) => index,
+                            None => return Err(error(ErrorKind::UnknownClass(class.name.clone())))
+                        };
+                    },
+                    ByteCode::NEW {class, constructor} => {
+                        class.index = match punk_file.classes.iter().position(|c| c.this == class.name) {
+                            Some(index) => index,
+                            None => return Err(error(ErrorKind::UnknownClass(class.name.clone())))
+                        };
+                        if punk_file.classes[class.index].is_interface {
+                            return Err(error(ErrorKind::InvalidInterface(format!("{} is an interface, it can't be created", class.name))))
+                        }
+                        *constructor = match self.resolve_method(punk_file, &class.name, "constructor") {
+                            Some(index) => index,
+                            None => return Err(error(ErrorKind::UnknownMethod(format!("{}/constructor", class.name))))
+                        };
+                        if self.methods[*constructor].is_static {
+                            return Err(error(ErrorKind::InvalidCall(format!("{}/constructor is static", class.name))))
+                        }
+                    },
+                    _ => ()
+                }
+            }
+        }
+        self.code = code;
+        Ok(())
+    }
+
+    pub fn get_ins(&self, pc: usize) -> Result<&ByteCode, ErrorKind> {
         match self.code.get(pc) {
-            Some(i) => i,
-            None => panic!("At pc = {} there's no instruction", pc)
+            Some(i) => Ok(i),
+            None => Err(ErrorKind::InvalidPc(pc))
         }
     }
 
+    pub fn get_method(&self, index: usize) -> &Method {
+        &self.methods[index]
+    }
+
     /// name should be CLASS/METHOD
-    pub fn get_method_pc(&self, name: &str) -> Option<usize> {
-        match self.methods.get(name) {
-            Some(m) => Some(m.clone()),
-            None => None
+    pub fn get_method_index(&self, name: &str) -> Option<usize> {
+        self.method_index.get(name).copied()
+    }
+
+    /// Searches the method in the class and, if it's not defined there, in its parents and then in the
+    /// interfaces they implement
+    pub fn resolve_method(&self, punk_file: &PunkFile, class: &str, method: &str) -> Option<usize> {
+        let mut cls_name = class;
+        loop {
+            if let Some(index) = self.get_method_index(format!("{}/{}", cls_name, method).as_str()) {
+                return Some(index)
+            }
+            match punk_file.find_class(cls_name) {
+                Some(cls) if !cls.super_cls.is_empty() => cls_name = cls.super_cls.as_str(),
+                _ => break
+            }
         }
+        let class = punk_file.classes.iter().position(|c| c.this == class)?;
+        Instructions::interfaces_of(punk_file, class).into_iter()
+            .find_map(|i| self.get_method_index(format!("{}/{}", punk_file.classes[i].this, method).as_str()))
     }
 
-    fn get_labels(&mut self, base_pc: usize, code: &Vec<ByteCode>) {
-        let mut pc = base_pc;
-        for c in code {
-            match c {
-                ByteCode::LABEL(label) => {
-                    self.labels.insert(label.clone(), pc);
-                },
-                _ => ()
+    /// Class that declares the static field, searching it from the class through its parents, and its slot
+    /// among the static fields of that class
+    fn resolve_static(&self, punk_file: &PunkFile, class: &str, field: &str) -> Result<Option<(usize, usize)>, ErrorKind> {
+        let mut index = match punk_file.classes.iter().position(|c| c.this == class) {
+            Some(i) => i,
+            None => return Err(ErrorKind::UnknownClass(class.to_string()))
+        };
+        loop {
+            if let Some(slot) = self.statics[index].slot(field) {
+                return Ok(Some((index, slot)))
+            }
+            // The parents exist and don't form a cycle, the virtual method tables checked it
+            let parent = &punk_file.classes[index].super_cls;
+            index = match punk_file.classes.iter().position(|c| c.this == *parent) {
+                Some(i) => i,
+                None => return Ok(None)
             };
-            pc += 1;
         }
     }
 
-    pub fn get_label_pc(&self, label: &String) -> usize {
-        match self.labels.get(label.as_str()) {
-            Some(pc) => pc.clone(),
-            None => panic!("The label {} no exist", label)
+    /// If the objects of the class are instances of the other class or interface
+    pub fn is_instance(&self, class: usize, of: usize) -> bool {
+        self.vtables[class].supertypes.binary_search(&of).is_ok()
+    }
+
+    /// Method that the instance of the class runs for a virtual call to `method` with the slot that `link` gave
+    /// to the call: in the virtual method table of the class or, for the methods of an interface, in its table
+    /// for the interface. None if the class doesn't have the method
+    pub fn dispatch(&self, class: usize, method: usize, slot: usize) -> Option<usize> {
+        let target = &self.methods[method];
+        let declaring = target.class_index?;
+        let vtable = &self.vtables[class];
+        if target.is_abstract {
+            return vtable.interfaces.iter().find(|(i, _)| *i == declaring).map(|(_, methods)| methods[slot])
+        }
+        match (vtable.owners.get(slot), self.vtables[declaring].owners.get(slot)) {
+            (Some(owner), Some(expected)) if owner == expected => Some(vtable.methods[slot]),
+            _ => None
         }
     }
-}
\ No newline at end of file
+
+    /// Slot of the field in an object of the class. A field named as CLASS/FIELD is the one the named class sees,
+    /// the object has to have it, which is when the same class declares the slot in both. Otherwise it's the one
+    /// the class of the object sees, `slot` is then the cache of the instruction and the field is only searched
+    /// by its name when the class isn't the one of the last object. None if the object doesn't have the field
+    pub fn field_slot(&self, class: usize, field: &Symbol, slot: usize) -> Option<usize> {
+        let layout = &self.layouts[class];
+        if field.index == usize::MAX {
+            let cache = &self.field_caches[slot];
+            return match cache.get() {
+                (cached, s) if cached == class => Some(s),
+                _ => {
+                    let s = layout.slot(&field.name)?;
+                    cache.set((class, s));
+                    Some(s)
+                }
+            }
+        }
+        match (layout.fields.get(slot), self.layouts[field.index].fields.get(slot)) {
+            (Some((owner, _)), Some((expected, _))) if owner == expected => Some(slot),
+            _ => None
+        }
+    }
+
+    /// pc of the handler of the method that catches an exception of the class thrown at pc
+    pub fn find_handler(&self, punk_file: &PunkFile, method: usize, pc: usize, class: &str) -> Option<usize> {
+        self.methods[method].handlers.iter()
+            .find(|h| h.start.index <= pc && pc < h.end.index && punk_file.is_subclass(class, &h.class))
+            .map(|h| h.handler.index)
+    }
+
+    fn get_labels(method: &str, base_pc: usize, code: &[ByteCode]) -> Result<HashMap<String, usize>, VmError> {
+        let mut labels = HashMap::new();
+        for (pc, c) in (base_pc..).zip(code.iter()) {
+            if let ByteCode::LABEL(label) = c {
+                if labels.insert(label.clone(), pc).is_some() {
+                    return Err(VmError::at(ErrorKind::DuplicateLabel(label.clone()), method.to_string(), pc))
+                }
+            }
+        }
+        Ok(labels)
+    }
+}
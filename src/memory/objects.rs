@@ -1,46 +1,206 @@
-use std::collections::HashMap;
-use crate::memory::vpk_stack::Type;
+use crate::memory::vpk_stack::{Type, RetType};
+use crate::error::ErrorKind;
 
+/// Number of live objects that triggers the first collection
+pub const DEFAULT_GC_THRESHOLD: usize = 1024;
+
+/// An instance of a class living in the heap
+#[derive(Debug)]
+pub struct Object {
+    /// Position of the class of the object in `PunkFile::classes`
+    pub class: usize,
+    /// Values of the fields by their slot in the layout of the class
+    pub fields: Vec<Type>,
+}
+
+/// An array living in the heap, all its elements have the same type
+#[derive(Debug)]
+pub struct Array {
+    pub elem: RetType,
+    pub values: Vec<Type>,
+}
+
+/// What a handle points to
+#[derive(Debug)]
+pub enum HeapValue {
+    Object(Object),
+    Array(Array),
+}
+
+/// Statistics of the heap and the garbage collector
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeapStats {
+    /// Objects and arrays currently in the heap, reachable or not
+    pub live: usize,
+    /// Slots of the heap, live objects plus the free slots waiting to be reused
+    pub capacity: usize,
+    /// Objects allocated since the machine started
+    pub allocated: usize,
+    /// Objects freed by the collector since the machine started
+    pub freed: usize,
+    /// Times the collector has run
+    pub collections: usize,
+}
+
+/// The heap, objects and arrays are referenced by their handle which is their position in it.
+/// Unreachable values are freed by a mark and sweep collector and their slots reused
 pub struct Objects {
-    objects: HashMap<String, HashMap<String, Type>>
+    objects: Vec<Option<HeapValue>>,
+    free: Vec<usize>,
+    /// When there are this many live objects the next allocation should collect first
+    threshold: usize,
+    min_threshold: usize,
+    stats: HeapStats,
 }
 
 impl Objects {
     pub fn new() -> Objects {
-        let mut h = HashMap::new();
-        h.insert("Null".to_string(), HashMap::new());
         Objects {
-            objects: h
+            objects: Vec::new(),
+            free: Vec::new(),
+            threshold: DEFAULT_GC_THRESHOLD,
+            min_threshold: DEFAULT_GC_THRESHOLD,
+            stats: Default::default(),
+        }
+    }
+
+    pub fn get(&self, object: usize) -> Result<&Object, ErrorKind> {
+        match self.objects.get(object) {
+            Some(Some(HeapValue::Object(obj))) => Ok(obj),
+            Some(Some(HeapValue::Array(_))) => Err(ErrorKind::TypeMismatch(format!("The handle {} is an array, not an object", object))),
+            _ => Err(ErrorKind::UnknownObject(object))
         }
     }
 
-    pub fn get_field(&self, object: String, field: &String) -> Type {
-        match self.objects.get(object.as_str()) {
-            Some(cls) => match cls.get(field.as_str()) {
-                Some(v) => return v.clone(),
-                None => panic!("The field {} in {} doesn't exist", field, object)
+    pub fn get_array(&self, array: usize) -> Result<&Array, ErrorKind> {
+        match self.objects.get(array) {
+            Some(Some(HeapValue::Array(arr))) => Ok(arr),
+            Some(Some(HeapValue::Object(_))) => Err(ErrorKind::TypeMismatch(format!("The handle {} is an object, not an array", array))),
+            _ => Err(ErrorKind::UnknownObject(array))
+        }
+    }
+
+    pub fn get_array_mut(&mut self, array: usize) -> Result<&mut Array, ErrorKind> {
+        match self.objects.get_mut(array) {
+            Some(Some(HeapValue::Array(arr))) => Ok(arr),
+            Some(Some(HeapValue::Object(_))) => Err(ErrorKind::TypeMismatch(format!("The handle {} is an object, not an array", array))),
+            _ => Err(ErrorKind::UnknownObject(array))
+        }
+    }
+
+    pub fn get_field(&self, object: usize, slot: usize) -> Result<Type, ErrorKind> {
+        match self.get(object)?.fields.get(slot) {
+            Some(v) => Ok(v.clone()),
+            None => Err(ErrorKind::UnknownField(format!("slot {} in object {}", slot, object)))
+        }
+    }
+
+    pub fn set_field(&mut self, object: usize, slot: usize, value: Type) -> Result<(), ErrorKind> {
+        let obj = match self.objects.get_mut(object) {
+            Some(Some(HeapValue::Object(obj))) => obj,
+            Some(Some(HeapValue::Array(_))) => return Err(ErrorKind::TypeMismatch(format!("The handle {} is an array, not an object", object))),
+            _ => return Err(ErrorKind::UnknownObject(object))
+        };
+        match obj.fields.get_mut(slot) {
+            Some(v) => *v = value,
+            None => return Err(ErrorKind::UnknownField(format!("slot {} in object {}", slot, object)))
+        }
+        Ok(())
+    }
+
+    /// Creates an object of the class and returns its handle
+    pub fn new_object(&mut self, class: usize, fields: Vec<Type>) -> usize {
+        self.alloc(HeapValue::Object(Object { class, fields }))
+    }
+
+    /// Creates an array of `len` elements with the default value of their type and returns its handle
+    pub fn new_array(&mut self, elem: RetType, len: usize) -> usize {
+        let values = vec![elem.default_value(); len];
+        self.alloc(HeapValue::Array(Array { elem, values }))
+    }
+
+    fn alloc(&mut self, value: HeapValue) -> usize {
+        let obj = Some(value);
+        self.stats.allocated += 1;
+        self.stats.live += 1;
+        match self.free.pop() {
+            Some(handle) => {
+                self.objects[handle] = obj;
+                handle
             },
-            None => panic!("The object doesn't exist")
+            None => {
+                self.objects.push(obj);
+                self.stats.capacity = self.objects.len();
+                self.objects.len() - 1
+            }
         }
     }
 
-    pub fn set_field(&mut self, object: String, field: &String, value: Type) {
-        match self.objects.get_mut(object.as_str()) {
-            Some(cls) => {
-                match cls.get_mut(field.as_str()) {
-                    Some(v) => *v = value,
-                    None => panic!("Couldn't find the field {} in object {}", field, object)
-                }
+    /// Whether the heap has grown enough that a collection should run before allocating
+    pub fn should_collect(&self) -> bool {
+        self.stats.live >= self.threshold
+    }
+
+    /// Sets the number of live objects that triggers a collection. After each collection the
+    /// threshold grows to twice the surviving objects but never goes below this value
+    pub fn set_threshold(&mut self, threshold: usize) {
+        self.threshold = threshold;
+        self.min_threshold = threshold;
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        self.stats.clone()
+    }
+
+    /// Marks every value reachable from the roots following the fields of the objects and the elements
+    /// of the arrays, and frees the rest.
+    /// Returns how many objects have been freed
+    pub fn collect<'a, I>(&mut self, roots: I) -> usize
+        where I: IntoIterator<Item = &'a Type>
+    {
+        // Mark
+        let mut marked = vec![false; self.objects.len()];
+        let mut pending: Vec<usize> = roots.into_iter().filter_map(Objects::handle).collect();
+        while let Some(handle) = pending.pop() {
+            if handle >= marked.len() || marked[handle] {
+                continue
+            }
+            marked[handle] = true;
+            match self.objects.get(handle) {
+                Some(Some(HeapValue::Object(obj))) => pending.extend(obj.fields.iter().filter_map(Objects::handle)),
+                Some(Some(HeapValue::Array(arr))) => pending.extend(arr.values.iter().filter_map(Objects::handle)),
+                _ => ()
+            }
+        }
+
+        // Sweep
+        let mut freed = 0;
+        for (handle, slot) in self.objects.iter_mut().enumerate() {
+            if slot.is_some() && !marked[handle] {
+                *slot = None;
+                self.free.push(handle);
+                freed += 1;
             }
-            None => panic!("The object doesn't exist")
         }
+
+        self.stats.live -= freed;
+        self.stats.freed += freed;
+        self.stats.collections += 1;
+        self.threshold = std::cmp::max(self.min_threshold, self.stats.live * 2);
+        freed
     }
 
-    pub fn new_object(&mut self, object: &String, fields: HashMap<String, Type>) -> String {
-        let n = self.objects.keys().filter(|k| k.contains(object)).count();
-        let obj_name = format!("{}{}", object, n);
-        self.objects.insert(obj_name.clone(), fields);
-        obj_name
+    fn handle(value: &Type) -> Option<usize> {
+        match value {
+            Type::Object(handle) | Type::Array(handle) => Some(*handle),
+            _ => None
+        }
     }
 
-}
\ No newline at end of file
+}
+
+impl Default for Objects {
+    fn default() -> Objects {
+        Objects::new()
+    }
+}
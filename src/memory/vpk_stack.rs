@@ -1,25 +1,40 @@
-#[derive(Eq, PartialEq, Clone, Debug)]
+use crate::error::ErrorKind;
+use std::fmt;
+
+#[derive(PartialEq, Clone, Debug, Serialize)]
 pub enum Type {
     Integer(i32),
+    Long(i64),
+    Double(f64),
     String(String),
-    Object(String),
-    Boolean(bool)
+    /// Handle of an object in the heap
+    Object(usize),
+    /// Handle of an array in the heap
+    Array(usize),
+    Boolean(bool),
+    /// Reference that doesn't point to any object
+    Null,
 }
 
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub enum RetType {
     Integer,
+    Long,
+    Double,
     String,
     Object,
     Boolean,
-    Void
+    Void,
+    /// Array of the given type, written `[` followed by the type of the elements
+    Array(Box<RetType>),
 }
 
 #[derive(Clone, Debug)]
 pub struct Frame {
-    local_vars: Vec<Type>,
+    local_vars: Vec<Option<Type>>,
     stack: Vec<Type>,
-    ret_type: RetType
+    ret_type: RetType,
+    method: usize,
 }
 
 #[derive(Debug)]
@@ -31,22 +46,64 @@ pub struct StackVM {
 
 impl Type {
     pub fn is_object(&self) -> bool {
-        match self {
-            &Type::Object(_) => true,
-            _ => false,
-        }
+        matches!(self, Type::Object(_) | Type::Null)
     }
 }
 
 impl RetType {
-    pub fn get_type(s: &str) -> RetType {
+    pub fn get_type(s: &str) -> Result<RetType, ErrorKind> {
         match s {
-            "I" => RetType::Integer,
-            "S" => RetType::String,
-            "O" => RetType::Object,
-            "V" => RetType::Void,
-            "B" => RetType::Boolean,
-            _ => panic!("The type {} is not a valid return type", s)
+            "I" => Ok(RetType::Integer),
+            "L" => Ok(RetType::Long),
+            "D" => Ok(RetType::Double),
+            "S" => Ok(RetType::String),
+            "O" => Ok(RetType::Object),
+            "V" => Ok(RetType::Void),
+            "B" => Ok(RetType::Boolean),
+            _ if s.starts_with('[') => match RetType::get_type(&s[1..]) {
+                Ok(RetType::Void) | Err(_) => Err(ErrorKind::InvalidDescriptor(s.to_string())),
+                Ok(t) => Ok(RetType::Array(Box::new(t)))
+            },
+            _ => Err(ErrorKind::InvalidDescriptor(s.to_string()))
+        }
+    }
+
+    /// Value of a variable of this type that hasn't been given one, like the elements of a new array
+    pub fn default_value(&self) -> Type {
+        match self {
+            RetType::Integer => Type::Integer(0),
+            RetType::Long => Type::Long(0),
+            RetType::Double => Type::Double(0.0),
+            RetType::Boolean => Type::Boolean(false),
+            _ => Type::Null
+        }
+    }
+
+    /// Whether the value can be held by a variable of this type
+    pub fn accepts(&self, value: &Type) -> bool {
+        matches!((self, value),
+            (RetType::Integer, Type::Integer(_))
+            | (RetType::Long, Type::Long(_))
+            | (RetType::Double, Type::Double(_))
+            | (RetType::Boolean, Type::Boolean(_))
+            | (RetType::String, Type::String(_)) | (RetType::String, Type::Null)
+            | (RetType::Object, Type::Object(_)) | (RetType::Object, Type::Null)
+            | (RetType::Array(_), Type::Array(_)) | (RetType::Array(_), Type::Null))
+    }
+}
+
+/// Writes the type as in the descriptors
+impl fmt::Display for RetType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RetType::Integer => write!(f, "I"),
+            RetType::Long => write!(f, "L"),
+            RetType::Double => write!(f, "D"),
+            RetType::String => write!(f, "S"),
+            RetType::Object => write!(f, "O"),
+            RetType::Boolean => write!(f, "B"),
+            RetType::Void => write!(f, "V"),
+            RetType::Array(t) => write!(f, "[{}", t),
         }
     }
 }
@@ -56,56 +113,59 @@ impl Frame {
         Frame {
             local_vars: Vec::new(),
             stack: Vec::new(),
-            /** Used to set the behaviour of the return function */
-            ret_type: RetType::Void
+            // Used to set the behaviour of the return function
+            ret_type: RetType::Void,
+            method: 0,
         }
     }
     /**
     Will get the top value of the operator stack and store it in the local variable
     */
-    pub fn store_var(&mut self, i: usize) {
-        let index = i;
-        if self.local_vars.len() < index {
-            let res = index - self.local_vars.len();
-            self.local_vars.reserve(res);
+    pub fn store_var(&mut self, i: usize) -> Result<(), ErrorKind> {
+        let top = self.pop()?;
+        if self.local_vars.len() <= i {
+            self.local_vars.resize(i + 1, None);
         }
-
-        let top = match self.stack.pop() {
-            Some(x) => x,
-            None => panic!("There's no elements on the stack"),
-        };
-        self.local_vars.insert(i, top)
+        self.local_vars[i] = Some(top);
+        Ok(())
     }
 
     /**
     Will get the local variable in index i and push it at the top of the operator stack
     */
-    pub fn load_var(&mut self, i: usize) {
-        let index = i;
-        let var = match self.local_vars.get(index) {
-            Some(x) => x.clone(),
-            None => panic!("There's no localVariable in {}", i)
+    pub fn load_var(&mut self, i: usize) -> Result<(), ErrorKind> {
+        let var = match self.local_vars.get(i) {
+            Some(Some(x)) => x.clone(),
+            _ => return Err(ErrorKind::UninitializedLocal(i))
         };
 
-        self.stack.push(var)
+        self.stack.push(var);
+        Ok(())
     }
     /**
     Will push variables into the variables Vec, used for the methodcalls
     */
     pub fn push_var(&mut self, var: Type) {
-        self.local_vars.push(var)
+        self.local_vars.push(Some(var))
     }
 
     /**
     Will pop a value from the operator stack and return it
     */
-    pub fn pop(&mut self) -> Type {
+    pub fn pop(&mut self) -> Result<Type, ErrorKind> {
         match self.stack.pop() {
-            Some(x) => x,
-            None => panic!("No element to return")
+            Some(x) => Ok(x),
+            None => Err(ErrorKind::StackUnderflow)
         }
     }
 
+    /**
+    Will pop a value from the operator stack if there's any
+    */
+    pub fn try_pop(&mut self) -> Option<Type> {
+        self.stack.pop()
+    }
+
     /**
     Will push the value t into the operator stack
     */
@@ -113,6 +173,34 @@ impl Frame {
         self.stack.push(t)
     }
 
+    /**
+    Will return every value held by the frame, local variables and operator stack
+    */
+    pub fn values(&self) -> impl Iterator<Item = &Type> {
+        self.local_vars.iter().flatten().chain(self.stack.iter())
+    }
+
+    /**
+    Will return the local variables, None for the ones never stored
+    */
+    pub fn locals(&self) -> &[Option<Type>] {
+        &self.local_vars
+    }
+
+    /**
+    Will remove every value of the operator stack
+    */
+    pub fn clear_operands(&mut self) {
+        self.stack.clear()
+    }
+
+    /**
+    Will return the operator stack, the top is the last value
+    */
+    pub fn operands(&self) -> &[Type] {
+        &self.stack
+    }
+
     /**
     Will set the return type of the frame
     */
@@ -126,6 +214,26 @@ impl Frame {
     pub fn get_ret_type(&self) -> RetType {
         self.ret_type.clone()
     }
+
+    /**
+    Will set the method, as its position in Instructions::methods, that is executed in this frame
+    */
+    pub fn set_method(&mut self, method: usize) {
+        self.method = method;
+    }
+
+    /**
+    Will return the method, as its position in Instructions::methods, that is executed in this frame
+    */
+    pub fn get_method(&self) -> usize {
+        self.method
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Frame {
+        Frame::new()
+    }
 }
 
 impl StackVM {
@@ -141,30 +249,44 @@ impl StackVM {
         self.stack.push(f)
     }
 
-    pub fn pop_frame(&mut self) -> Frame {
+    pub fn pop_frame(&mut self) -> Result<Frame, ErrorKind> {
         match self.stack.pop() {
-            Some(f) => f,
-            None => panic!("There are no more frames to pop")
+            Some(f) => Ok(f),
+            None => Err(ErrorKind::NoFrames)
         }
     }
 
-    pub fn get_frame_mut(&mut self) -> &mut Frame {
+    pub fn get_frame_mut(&mut self) -> Result<&mut Frame, ErrorKind> {
         match self.stack.last_mut() {
-            Some(x) => x,
-            None => panic!("There are no frames to get")
+            Some(x) => Ok(x),
+            None => Err(ErrorKind::NoFrames)
         }
     }
 
+    /// Suspended frames from the outermost to the innermost
+    pub fn frames(&self) -> impl Iterator<Item = &Frame> {
+        self.stack.iter()
+    }
+
+    /// Suspended frames from the innermost to the outermost, each one with the pc of the call it's waiting on
+    pub fn call_stack(&self) -> Vec<(&Frame, usize)> {
+        self.stack.iter().rev()
+            .zip(self.ret_addr.iter().rev())
+            .map(|(f, ret)| (f, ret - 1))
+            .collect()
+    }
+
     pub fn methodcall_pc(&mut self, new_pc: usize) {
         self.ret_addr.push(self.pc+1);
         self.pc = new_pc
     }
 
-    pub fn ret_pc(&mut self) {
+    pub fn ret_pc(&mut self) -> Result<(), ErrorKind> {
         match self.ret_addr.pop() {
             Some(x) => self.pc = x,
-            None => panic!("There's no return address in the stack")
+            None => return Err(ErrorKind::NoFrames)
         }
+        Ok(())
     }
 
     pub fn inc_pc(&mut self) {
@@ -179,7 +301,18 @@ impl StackVM {
         self.pc = n_pc
     }
 
+    /// Number of suspended frames
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.stack.is_empty()
     }
 }
+
+impl Default for StackVM {
+    fn default() -> StackVM {
+        StackVM::new()
+    }
+}
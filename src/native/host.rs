@@ -0,0 +1,232 @@
+//! Conversions that let Rust functions with typed arguments be registered as native methods:
+//!
+//! ```text
+//! vm.register_native("Host/lookup", "(S)I", |key: String| -> Result<i32, String> { ... })?;
+//! ```
+//!
+//! The arguments are converted with `FromPunk` and the returned value with `IntoPunk`. A function that returns
+//! `Err` fails the `METHODCALL` with the message of the error.
+
+use crate::native::NativeFn;
+use crate::memory::vpk_stack::{Type, RetType};
+use crate::punkfile::descriptor::Descriptor;
+use crate::error::ErrorKind;
+use std::fmt::Display;
+
+/// Rust value that can be built from a value of the machine
+pub trait FromPunk: Sized {
+    fn from_punk(value: &Type) -> Result<Self, ErrorKind>;
+    /// Whether a descriptor argument of this type can be converted
+    fn accepts(t: &RetType) -> bool;
+}
+
+/// Rust value that can be returned to the machine, None for `V` methods
+pub trait IntoPunk {
+    fn into_punk(self) -> Result<Option<Type>, ErrorKind>;
+    /// Whether the value can be returned by a method with this return type
+    fn returns(t: &RetType) -> bool;
+}
+
+/// Function that can be registered as a native method, implemented for functions of up to 6 arguments
+pub trait HostFn<Args> {
+    /// Whether the arguments and the returned value match the descriptor
+    fn matches(desc: &Descriptor) -> bool;
+    fn into_native(self) -> NativeFn;
+}
+
+impl FromPunk for i32 {
+    fn from_punk(value: &Type) -> Result<i32, ErrorKind> {
+        match value {
+            Type::Integer(x) => Ok(*x),
+            v => Err(ErrorKind::TypeMismatch(format!("Expected an integer but got {:?}", v)))
+        }
+    }
+
+    fn accepts(t: &RetType) -> bool {
+        *t == RetType::Integer
+    }
+}
+
+impl FromPunk for i64 {
+    fn from_punk(value: &Type) -> Result<i64, ErrorKind> {
+        match value {
+            Type::Long(x) => Ok(*x),
+            v => Err(ErrorKind::TypeMismatch(format!("Expected a long but got {:?}", v)))
+        }
+    }
+
+    fn accepts(t: &RetType) -> bool {
+        *t == RetType::Long
+    }
+}
+
+impl FromPunk for f64 {
+    fn from_punk(value: &Type) -> Result<f64, ErrorKind> {
+        match value {
+            Type::Double(x) => Ok(*x),
+            v => Err(ErrorKind::TypeMismatch(format!("Expected a double but got {:?}", v)))
+        }
+    }
+
+    fn accepts(t: &RetType) -> bool {
+        *t == RetType::Double
+    }
+}
+
+impl FromPunk for bool {
+    fn from_punk(value: &Type) -> Result<bool, ErrorKind> {
+        match value {
+            Type::Boolean(b) => Ok(*b),
+            v => Err(ErrorKind::TypeMismatch(format!("Expected a boolean but got {:?}", v)))
+        }
+    }
+
+    fn accepts(t: &RetType) -> bool {
+        *t == RetType::Boolean
+    }
+}
+
+impl FromPunk for String {
+    fn from_punk(value: &Type) -> Result<String, ErrorKind> {
+        match value {
+            Type::String(s) => Ok(s.clone()),
+            Type::Null => Err(ErrorKind::NullReference),
+            v => Err(ErrorKind::TypeMismatch(format!("Expected a string but got {:?}", v)))
+        }
+    }
+
+    fn accepts(t: &RetType) -> bool {
+        *t == RetType::String
+    }
+}
+
+/// Any value, as the machine holds it
+impl FromPunk for Type {
+    fn from_punk(value: &Type) -> Result<Type, ErrorKind> {
+        Ok(value.clone())
+    }
+
+    fn accepts(_: &RetType) -> bool {
+        true
+    }
+}
+
+impl IntoPunk for () {
+    fn into_punk(self) -> Result<Option<Type>, ErrorKind> {
+        Ok(None)
+    }
+
+    fn returns(t: &RetType) -> bool {
+        *t == RetType::Void
+    }
+}
+
+impl IntoPunk for i32 {
+    fn into_punk(self) -> Result<Option<Type>, ErrorKind> {
+        Ok(Some(Type::Integer(self)))
+    }
+
+    fn returns(t: &RetType) -> bool {
+        *t == RetType::Integer
+    }
+}
+
+impl IntoPunk for i64 {
+    fn into_punk(self) -> Result<Option<Type>, ErrorKind> {
+        Ok(Some(Type::Long(self)))
+    }
+
+    fn returns(t: &RetType) -> bool {
+        *t == RetType::Long
+    }
+}
+
+impl IntoPunk for f64 {
+    fn into_punk(self) -> Result<Option<Type>, ErrorKind> {
+        Ok(Some(Type::Double(self)))
+    }
+
+    fn returns(t: &RetType) -> bool {
+        *t == RetType::Double
+    }
+}
+
+impl IntoPunk for bool {
+    fn into_punk(self) -> Result<Option<Type>, ErrorKind> {
+        Ok(Some(Type::Boolean(self)))
+    }
+
+    fn returns(t: &RetType) -> bool {
+        *t == RetType::Boolean
+    }
+}
+
+impl IntoPunk for String {
+    fn into_punk(self) -> Result<Option<Type>, ErrorKind> {
+        Ok(Some(Type::String(self)))
+    }
+
+    fn returns(t: &RetType) -> bool {
+        *t == RetType::String
+    }
+}
+
+/// Any value, `Natives::call` checks that it matches the descriptor
+impl IntoPunk for Type {
+    fn into_punk(self) -> Result<Option<Type>, ErrorKind> {
+        Ok(Some(self))
+    }
+
+    fn returns(t: &RetType) -> bool {
+        *t != RetType::Void
+    }
+}
+
+/// The error of the host becomes a `NativeError` with its message
+impl<T: IntoPunk, E: Display> IntoPunk for Result<T, E> {
+    fn into_punk(self) -> Result<Option<Type>, ErrorKind> {
+        match self {
+            Ok(v) => v.into_punk(),
+            Err(err) => Err(ErrorKind::NativeError(err.to_string()))
+        }
+    }
+
+    fn returns(t: &RetType) -> bool {
+        T::returns(t)
+    }
+}
+
+macro_rules! host_fn {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg),*> HostFn<($($arg,)*)> for F
+            where F: Fn($($arg),*) -> R + 'static, R: IntoPunk, $($arg: FromPunk),*
+        {
+            #[allow(unused_mut)]
+            fn matches(desc: &Descriptor) -> bool {
+                let mut args = desc.args.iter();
+                $(
+                    if !args.next().map_or(false, $arg::accepts) {
+                        return false
+                    }
+                )*
+                args.next().is_none() && R::returns(&desc.ret)
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn into_native(self) -> NativeFn {
+                Box::new(move |args: &[Type]| {
+                    let mut args = args.iter();
+                    (self)($($arg::from_punk(args.next().ok_or(ErrorKind::StackUnderflow)?)?),*).into_punk()
+                })
+            }
+        }
+    };
+}
+
+host_fn!();
+host_fn!(A);
+host_fn!(A, B);
+host_fn!(A, B, C);
+host_fn!(A, B, C, D);
+host_fn!(A, B, C, D, E);
+host_fn!(A, B, C, D, E, G);
@@ -0,0 +1,17 @@
+use crate::native::{Natives, standard};
+use std::io::{self, BufRead};
+
+pub fn register(natives: &mut Natives) {
+    // Returns an empty string once the input has ended
+    standard(natives, "IO/readLine", "()S", || {
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(_) => {
+                let len = line.trim_end_matches(&['\n', '\r'][..]).len();
+                line.truncate(len);
+                Ok(line)
+            },
+            Err(err) => Err(format!("The input couldn't be read: {}", err))
+        }
+    });
+}
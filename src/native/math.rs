@@ -0,0 +1,19 @@
+use crate::native::{Natives, standard};
+
+pub fn register(natives: &mut Natives) {
+    standard(natives, "Math/abs", "(I)I", |x: i32| match x.checked_abs() {
+        Some(v) => Ok(v),
+        None => Err(format!("The absolute value of {} doesn't fit in an integer", x))
+    });
+    standard(natives, "Math/min", "(II)I", |x: i32, y: i32| std::cmp::min(x, y));
+    standard(natives, "Math/max", "(II)I", |x: i32, y: i32| std::cmp::max(x, y));
+    standard(natives, "Math/pow", "(II)I", |base: i32, exp: i32| {
+        if exp < 0 {
+            return Err(format!("The exponent {} is negative", exp))
+        }
+        match base.checked_pow(exp as u32) {
+            Some(v) => Ok(v),
+            None => Err(format!("{} to the power of {} doesn't fit in an integer", base, exp))
+        }
+    });
+}
@@ -0,0 +1,117 @@
+//! Methods implemented in Rust. They're static methods, called with `INVOKESTATIC Class/method` or
+//! `METHODCALL Class/method` without an object: only the arguments of the descriptor are popped from the
+//! operator stack and the returned value, if the method isn't `V`, is pushed. A method with bytecode always
+//! wins over a native with the same name.
+//!
+//! The standard table has these classes:
+//! - `IO`: `readLine ()S`, the line without its end, an empty string once the input has ended
+//! - `String`: `length (S)I`, `substring (SII)S`, `charAt (SI)S`, `parseInt (S)I`
+//! - `Math`: `abs (I)I`, `min (II)I`, `max (II)I`, `pow (II)I`
+//! - `System`: `time ()L`, `millis ()L`, longs so they don't wrap around
+
+mod io;
+mod math;
+mod string;
+mod system;
+pub mod host;
+
+pub use self::host::{FromPunk, IntoPunk, HostFn};
+
+use crate::memory::vpk_stack::{Type, RetType};
+use crate::punkfile::descriptor::Descriptor;
+use crate::error::ErrorKind;
+
+/// Receives the arguments in the order of the descriptor and returns the value of the method, None if it's `V`
+pub type NativeFn = Box<dyn Fn(&[Type]) -> Result<Option<Type>, ErrorKind>>;
+
+pub struct Native {
+    /// Name in the form CLASS/METHOD
+    pub name: String,
+    pub desc: Descriptor,
+    func: NativeFn,
+}
+
+/// Table of the native methods, a native is referenced by its position in it
+#[derive(Default)]
+pub struct Natives {
+    natives: Vec<Native>,
+}
+
+impl Natives {
+    /// Table without any method
+    pub fn new() -> Natives {
+        Default::default()
+    }
+
+    /// Table with the standard library
+    pub fn standard() -> Natives {
+        let mut natives = Natives::new();
+        io::register(&mut natives);
+        math::register(&mut natives);
+        string::register(&mut natives);
+        system::register(&mut natives);
+        natives
+    }
+
+    /// Adds the method, it fails if the descriptor is malformed. A method registered twice replaces the previous one
+    pub fn register<F>(&mut self, name: &str, desc: &str, func: F) -> Result<(), ErrorKind>
+        where F: Fn(&[Type]) -> Result<Option<Type>, ErrorKind> + 'static
+    {
+        let native = Native {
+            name: name.to_string(),
+            desc: Descriptor::parse(desc)?,
+            func: Box::new(func),
+        };
+        match self.natives.iter().position(|n| n.name == name) {
+            Some(i) => self.natives[i] = native,
+            None => self.natives.push(native)
+        }
+        Ok(())
+    }
+
+    /// Adds a Rust function with typed arguments, it fails if the descriptor is malformed or the arguments and
+    /// the returned value of the function don't match it
+    pub fn register_host<F, Args>(&mut self, name: &str, desc: &str, func: F) -> Result<(), ErrorKind>
+        where F: HostFn<Args>
+    {
+        if !F::matches(&Descriptor::parse(desc)?) {
+            return Err(ErrorKind::TypeMismatch(format!("The descriptor {} doesn't match the arguments and the returned value of {}", desc, name)))
+        }
+        let native = func.into_native();
+        self.register(name, desc, move |args| native(args))
+    }
+
+    pub fn get(&self, index: usize) -> &Native {
+        &self.natives[index]
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Native> {
+        self.natives.iter().find(|n| n.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Native> {
+        self.natives.iter()
+    }
+
+    /// Calls the method and checks that it returns what its descriptor says
+    pub fn call(&self, index: usize, args: &[Type]) -> Result<Option<Type>, ErrorKind> {
+        let native = self.get(index);
+        let value = (native.func)(args)?;
+        let valid = match (&native.desc.ret, &value) {
+            (RetType::Void, None) => true,
+            (t, Some(v)) => t.accepts(v),
+            _ => false
+        };
+        if !valid {
+            return Err(ErrorKind::InvalidReturn(format!("The native method {} returned {:?} but its descriptor says {}", native.name, value, native.desc.ret)))
+        }
+        Ok(value)
+    }
+}
+
+/// Adds a method of the standard library, their descriptors always match the functions
+fn standard<F, Args>(natives: &mut Natives, name: &str, desc: &str, func: F)
+    where F: HostFn<Args>
+{
+    natives.register_host(name, desc, func).expect("The standard native methods match their descriptors")
+}
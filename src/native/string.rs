@@ -0,0 +1,23 @@
+use crate::native::{Natives, standard};
+
+/// Positions are counted in characters, not bytes
+pub fn register(natives: &mut Natives) {
+    standard(natives, "String/length", "(S)I", |s: String| s.chars().count() as i32);
+    // Characters from begin up to, but not including, end
+    standard(natives, "String/substring", "(SII)S", |s: String, begin: i32, end: i32| {
+        let len = s.chars().count() as i32;
+        if begin < 0 || end > len || begin > end {
+            return Err(format!("The range {}..{} is out of a string of length {}", begin, end, len))
+        }
+        Ok(s.chars().skip(begin as usize).take((end - begin) as usize).collect::<String>())
+    });
+    // There's no character type, the character is returned as a string
+    standard(natives, "String/charAt", "(SI)S", |s: String, i: i32| match s.chars().nth(i as usize) {
+        Some(c) if i >= 0 => Ok(c.to_string()),
+        _ => Err(format!("The index {} is out of a string of length {}", i, s.chars().count()))
+    });
+    standard(natives, "String/parseInt", "(S)I", |s: String| match s.trim().parse::<i32>() {
+        Ok(x) => Ok(x),
+        Err(_) => Err(format!("{:?} is not an integer", s))
+    });
+}
@@ -0,0 +1,12 @@
+use crate::native::{Natives, standard};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+pub fn register(natives: &mut Natives) {
+    // Seconds since the Unix epoch, a long so it doesn't wrap in 2038
+    standard(natives, "System/time", "()L", || {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) as i64
+    });
+    // Milliseconds since the table was created, to measure how long something takes
+    let start = Instant::now();
+    standard(natives, "System/millis", "()L", move || start.elapsed().as_millis() as i64);
+}
@@ -0,0 +1,198 @@
+//! Profiler that counts the instructions executed by each method and each kind of instruction, and measures
+//! how long each method runs. A method's inclusive cost counts everything executed from when it's called until
+//! it returns. Its exclusive cost only counts its own instructions. Recursive calls are counted once in the
+//! inclusive totals. It can also take a sample of the chain of calls every given number of instructions.
+
+use crate::memory::instructions::Instructions;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Totals of a method
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MethodProfile {
+    pub calls: u64,
+    pub inclusive: u64,
+    pub exclusive: u64,
+    pub inclusive_time: Duration,
+    pub exclusive_time: Duration,
+}
+
+/// A method that hasn't returned yet
+struct Active {
+    method: usize,
+    start: Instant,
+    /// Time spent in the methods it called
+    children: Duration,
+}
+
+#[derive(Default)]
+pub struct Profiler {
+    /// Methods as positions in `Instructions::methods`
+    methods: HashMap<usize, MethodProfile>,
+    opcodes: HashMap<&'static str, u64>,
+    /// Instructions executed by each chain of calls, from the outermost method
+    stacks: HashMap<Vec<usize>, u64>,
+    active: Vec<Active>,
+    /// Instructions executed by the innermost method since the chain of calls last changed
+    pending: u64,
+    total: u64,
+    /// Instructions between two samples, 0 doesn't take samples
+    interval: u64,
+    /// Samples taken of each chain of calls, from the outermost method
+    samples: HashMap<Vec<usize>, u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Default::default()
+    }
+
+    /// Profiler that also samples the chain of calls once every `interval` instructions
+    pub fn sampling(interval: u64) -> Profiler {
+        Profiler { interval, ..Default::default() }
+    }
+
+    /// The instruction has been executed by the innermost active method
+    pub fn instruction(&mut self, mnemonic: &'static str) {
+        *self.opcodes.entry(mnemonic).or_insert(0) += 1;
+        self.pending += 1;
+        self.total += 1;
+        if self.interval != 0 && self.total.is_multiple_of(self.interval) {
+            let stack: Vec<usize> = self.active.iter().map(|a| a.method).collect();
+            *self.samples.entry(stack).or_insert(0) += 1;
+        }
+    }
+
+    /// The method has been called
+    pub fn enter(&mut self, method: usize) {
+        self.flush();
+        self.methods.entry(method).or_default().calls += 1;
+        self.active.push(Active { method, start: Instant::now(), children: Duration::default() });
+    }
+
+    /// The innermost active method has returned
+    pub fn exit(&mut self) {
+        self.flush();
+        let active = match self.active.pop() {
+            Some(a) => a,
+            None => return
+        };
+        let elapsed = active.start.elapsed();
+        let recursive = self.active.iter().any(|a| a.method == active.method);
+        let profile = self.methods.entry(active.method).or_default();
+        if !recursive {
+            profile.inclusive_time += elapsed;
+        }
+        profile.exclusive_time += elapsed.saturating_sub(active.children);
+        if let Some(caller) = self.active.last_mut() {
+            caller.children += elapsed;
+        }
+    }
+
+    /// Returns from every active method, the program has ended
+    pub fn finish(&mut self) {
+        while !self.active.is_empty() {
+            self.exit();
+        }
+    }
+
+    /// Totals of every method that has been called, by position in `Instructions::methods`
+    pub fn methods(&self) -> HashMap<usize, MethodProfile> {
+        let mut methods = self.methods.clone();
+        for (stack, count) in self.stacks.iter() {
+            let mut seen: Vec<usize> = Vec::new();
+            for method in stack.iter() {
+                if !seen.contains(method) {
+                    seen.push(*method);
+                    methods.entry(*method).or_default().inclusive += count;
+                }
+            }
+            if let Some(last) = stack.last() {
+                methods.entry(*last).or_default().exclusive += count;
+            }
+        }
+        methods
+    }
+
+    /// Times each kind of instruction has been executed
+    pub fn opcodes(&self) -> &HashMap<&'static str, u64> {
+        &self.opcodes
+    }
+
+    /// Samples in which each method was the innermost one, by position in `Instructions::methods`
+    pub fn samples(&self) -> HashMap<usize, u64> {
+        let mut samples = HashMap::new();
+        for (stack, count) in self.samples.iter() {
+            if let Some(last) = stack.last() {
+                *samples.entry(*last).or_insert(0) += count;
+            }
+        }
+        samples
+    }
+
+    /// Report of the methods and instructions sorted by the instructions they executed
+    pub fn report(&self, ins: &Instructions) -> String {
+        let mut methods: Vec<(String, MethodProfile)> = self.methods().into_iter()
+            .map(|(m, p)| (ins.get_method(m).name.clone(), p))
+            .collect();
+        methods.sort_by(|(n1, p1), (n2, p2)| p2.inclusive.cmp(&p1.inclusive).then(p2.exclusive.cmp(&p1.exclusive)).then(n1.cmp(n2)));
+        let mut opcodes: Vec<(&str, u64)> = self.opcodes.iter().map(|(o, c)| (*o, *c)).collect();
+        opcodes.sort_by(|(o1, c1), (o2, c2)| c2.cmp(c1).then(o1.cmp(o2)));
+
+        let mut out = format!("Executed instructions: {}\n\n", self.total);
+        out.push_str(format!("{:<32} {:>8} {:>12} {:>12} {:>12} {:>12}\n",
+                             "Method", "Calls", "Inclusive", "Exclusive", "Incl. time", "Excl. time").as_str());
+        for (name, p) in methods {
+            out.push_str(format!("{:<32} {:>8} {:>12} {:>12} {:>12} {:>12}\n", name, p.calls, p.inclusive, p.exclusive,
+                                 format!("{:.3}ms", millis(p.inclusive_time)), format!("{:.3}ms", millis(p.exclusive_time))).as_str());
+        }
+        out.push_str(format!("\n{:<32} {:>8} {:>8}\n", "Instruction", "Count", "%").as_str());
+        for (opcode, count) in opcodes {
+            let percent = if self.total == 0 { 0.0 } else { count as f64 * 100.0 / self.total as f64 };
+            out.push_str(format!("{:<32} {:>8} {:>7.2}%\n", opcode, count, percent).as_str());
+        }
+        if self.interval != 0 {
+            let mut samples: Vec<(String, u64)> = self.samples().into_iter()
+                .map(|(m, c)| (ins.get_method(m).name.clone(), c))
+                .collect();
+            samples.sort_by(|(n1, c1), (n2, c2)| c2.cmp(c1).then(n1.cmp(n2)));
+            let total: u64 = samples.iter().map(|(_, c)| c).sum();
+            out.push_str(format!("\nSamples every {} instructions: {}\n", self.interval, total).as_str());
+            out.push_str(format!("{:<32} {:>8} {:>8}\n", "Method", "Samples", "%").as_str());
+            for (name, count) in samples {
+                out.push_str(format!("{:<32} {:>8} {:>7.2}%\n", name, count, count as f64 * 100.0 / total as f64).as_str());
+            }
+        }
+        out
+    }
+
+    /// Instructions executed by each chain of calls in the collapsed stack format read by flame graph tools:
+    /// the methods from the outermost separated by `;` followed by the count
+    pub fn collapsed(&self, ins: &Instructions) -> String {
+        let mut lines: Vec<String> = self.stacks.iter()
+            .filter(|(_, count)| **count > 0)
+            .map(|(stack, count)| {
+                let names: Vec<&str> = stack.iter().map(|m| ins.get_method(*m).name.as_str()).collect();
+                format!("{} {}", names.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        let mut out = lines.join("\n");
+        out.push('\n');
+        out
+    }
+
+    /// Adds the pending instructions to the current chain of calls
+    fn flush(&mut self) {
+        if self.pending == 0 {
+            return
+        }
+        let stack: Vec<usize> = self.active.iter().map(|a| a.method).collect();
+        *self.stacks.entry(stack).or_insert(0) += self.pending;
+        self.pending = 0;
+    }
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
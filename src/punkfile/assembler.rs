@@ -0,0 +1,371 @@
+//! Textual assembly syntax for punk files. A program looks like:
+//!
+//! ```text
+//! ; Comments start with a semicolon and run until the end of the line
+//! .overflow trap
+//! .class Animal
+//! .field name S Unknown
+//! .method constructor ()V
+//!     RETURN
+//! .method speak ()S
+//!     LOAD 0
+//!     GETFIELD name
+//!     RETURN
+//! .method static create ()O
+//!     NEW Animal
+//!     RETURN
+//!
+//! .class Dog
+//! .super Animal
+//! .method constructor ()V
+//!     RETURN
+//!
+//! .main
+//! .catch NullReferenceError start end failed
+//!     LABEL start
+//!     NEW Dog
+//!     METHODCALL Dog/speak
+//!     PRINT
+//!     CONST "Hello; world"
+//!     PRINT
+//!     LABEL end
+//!     RETURN
+//!     LABEL failed
+//!     METHODCALL Exception/getMessage
+//!     PRINT
+//!     RETURN
+//! ```
+//!
+//! Every instruction is written as in the JSON punk files, one per line. The code of a method, or of the
+//! `.main` section, runs until the next directive. The value of a field and the literal of a `CONST` are the
+//! rest of the line, they can be written between double quotes to keep semicolons or spaces, the escapes
+//! `\"`, `\\`, `\n` and `\t` are understood inside quotes. `.overflow` chooses whether the integer arithmetic
+//! wraps (`wrap`, the default) or fails (`trap`) when a result doesn't fit in an integer. `.method static` declares
+//! a method without a receiver and `.field static` a field of the class instead of its objects, a static method
+//! named `<clinit>` initializes the class. `.interface` starts an interface instead of a class, its methods are
+//! declared with `.method abstract` and have no code, and `.implements` adds an interface to the current class.
+//! `.catch <class> <start> <end> <handler>` adds an entry to the exception table of the method, or `.main`
+//! section, it's in.
+
+use crate::punkfile::punk_file::{PunkFile, Overflow};
+use crate::punkfile::class::Class;
+use crate::punkfile::code::{Code, ExceptionHandler};
+use crate::punkfile::field::Field;
+use crate::punkfile::main::Main;
+use crate::punkfile::descriptor::Descriptor;
+use crate::memory::vpk_stack::RetType;
+use crate::isa::bytecode::ByteCode;
+use std::fmt;
+
+/// Error found while assembling, line and column start at 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+/// Where the instructions that are found go
+enum Section {
+    None,
+    Method,
+    Main,
+}
+
+/// A word of a line and the position where it starts
+struct Token<'a> {
+    text: &'a str,
+    /// Byte offset inside the line
+    offset: usize,
+}
+
+struct Line<'a> {
+    number: usize,
+    /// Line without the comment
+    text: &'a str,
+    tokens: Vec<Token<'a>>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Builds the punk file described by the source
+pub fn assemble(source: &str) -> Result<PunkFile, ParseError> {
+    let mut classes: Vec<Class> = Vec::new();
+    let mut main: Option<Main> = None;
+    let mut overflow: Option<Overflow> = None;
+    let mut section = Section::None;
+    let mut last_line = 0;
+
+    for (i, raw) in source.lines().enumerate() {
+        let line = Line::new(i + 1, raw);
+        last_line = line.number;
+        let first = match line.tokens.first() {
+            Some(t) => t,
+            None => continue
+        };
+
+        if first.text.starts_with('.') {
+            match first.text {
+                ".class" => {
+                    line.expect_operands(1)?;
+                    classes.push(Class {
+                        this: line.tokens[1].text.to_string(),
+                        ..Default::default()
+                    });
+                    section = Section::None;
+                },
+                ".interface" => {
+                    line.expect_operands(1)?;
+                    classes.push(Class {
+                        this: line.tokens[1].text.to_string(),
+                        is_interface: true,
+                        ..Default::default()
+                    });
+                    section = Section::None;
+                },
+                ".implements" => {
+                    line.expect_operands(1)?;
+                    let cls = line.current_class(&mut classes, first)?;
+                    cls.interfaces.push(line.tokens[1].text.to_string());
+                },
+                ".super" => {
+                    line.expect_operands(1)?;
+                    let cls = line.current_class(&mut classes, first)?;
+                    cls.super_cls = line.tokens[1].text.to_string();
+                },
+                ".field" => {
+                    let is_static = line.tokens.get(1).map(|t| t.text) == Some("static");
+                    let first_operand = if is_static { 2 } else { 1 };
+                    if line.tokens.len() < first_operand + 2 {
+                        return Err(line.error(first, "Expected .field [static] <name> <descriptor> [value]"))
+                    }
+                    let (name, desc) = (&line.tokens[first_operand], &line.tokens[first_operand + 1]);
+                    if RetType::get_type(desc.text).is_err() {
+                        return Err(line.error(desc, format!("{} is not a valid field descriptor", desc.text).as_str()))
+                    }
+                    let value = match line.tokens.get(first_operand + 2) {
+                        Some(t) => line.literal(t)?,
+                        None => String::new()
+                    };
+                    let field = Field {
+                        name: name.text.to_string(),
+                        desc: desc.text.to_string(),
+                        value,
+                        is_static,
+                    };
+                    line.current_class(&mut classes, first)?.fields.push(field);
+                },
+                ".method" => {
+                    let modifier = line.tokens.get(1).map(|t| t.text);
+                    let (is_static, is_abstract) = (modifier == Some("static"), modifier == Some("abstract"));
+                    let first_operand = if is_static || is_abstract { 2 } else { 1 };
+                    line.expect_operands(first_operand + 1)?;
+                    let (name, desc) = (&line.tokens[first_operand], &line.tokens[first_operand + 1]);
+                    if let Err(err) = Descriptor::parse(desc.text) {
+                        return Err(line.error(desc, err.to_string().as_str()))
+                    }
+                    let method = Code {
+                        name: name.text.to_string(),
+                        desc: desc.text.to_string(),
+                        is_static,
+                        is_abstract,
+                        code: Vec::new(),
+                        exceptions: Vec::new(),
+                    };
+                    line.current_class(&mut classes, first)?.methods.push(method);
+                    // Abstract methods don't have code
+                    section = if is_abstract { Section::None } else { Section::Method };
+                },
+                ".catch" => {
+                    line.expect_operands(4)?;
+                    let handler = ExceptionHandler {
+                        class: line.tokens[1].text.to_string(),
+                        start: line.tokens[2].text.to_string(),
+                        end: line.tokens[3].text.to_string(),
+                        handler: line.tokens[4].text.to_string(),
+                    };
+                    match section {
+                        Section::Method => classes.last_mut().unwrap().methods.last_mut().unwrap().exceptions.push(handler),
+                        Section::Main => main.as_mut().unwrap().exceptions.push(handler),
+                        Section::None => return Err(line.error(first, ".catch must be inside a .method or the .main section")),
+                    }
+                },
+                ".overflow" => {
+                    line.expect_operands(1)?;
+                    if overflow.is_some() {
+                        return Err(line.error(first, "The .overflow directive is used more than once"))
+                    }
+                    overflow = match line.tokens[1].text {
+                        "wrap" => Some(Overflow::Wrap),
+                        "trap" => Some(Overflow::Trap),
+                        o => return Err(line.error(&line.tokens[1], format!("{} is not wrap or trap", o).as_str()))
+                    };
+                },
+                ".main" => {
+                    line.expect_operands(0)?;
+                    if main.is_some() {
+                        return Err(line.error(first, "The .main section is defined more than once"))
+                    }
+                    main = Some(Default::default());
+                    section = Section::Main;
+                },
+                d => return Err(line.error(first, format!("Unknown directive {}", d).as_str()))
+            }
+            continue
+        }
+
+        let ins = line.instruction()?;
+        match section {
+            Section::Method => classes.last_mut().unwrap().methods.last_mut().unwrap().code.push(ins),
+            Section::Main => main.as_mut().unwrap().code.push(ins),
+            Section::None => return Err(line.error(first, "Instructions must be inside a .method or the .main section")),
+        }
+    }
+
+    match main {
+        Some(main) => {
+            let mut punk_file = PunkFile::new(classes, main);
+            punk_file.overflow = overflow.unwrap_or_default();
+            Ok(punk_file)
+        },
+        None => Err(ParseError { line: last_line + 1, column: 1, message: String::from("The .main section is missing") })
+    }
+}
+
+impl<'a> Line<'a> {
+    fn new(number: usize, raw: &'a str) -> Line<'a> {
+        let text = strip_comment(raw);
+        let mut tokens = Vec::new();
+        let mut start = None;
+        for (i, c) in text.char_indices() {
+            match (c.is_whitespace(), start) {
+                (true, Some(s)) => {
+                    tokens.push(Token { text: &text[s..i], offset: s });
+                    start = None;
+                },
+                (false, None) => start = Some(i),
+                _ => ()
+            }
+        }
+        if let Some(s) = start {
+            tokens.push(Token { text: &text[s..], offset: s });
+        }
+        Line { number, text, tokens }
+    }
+
+    fn error(&self, token: &Token, message: &str) -> ParseError {
+        ParseError {
+            line: self.number,
+            column: self.text[..token.offset].chars().count() + 1,
+            message: message.to_string(),
+        }
+    }
+
+    fn expect_operands(&self, n: usize) -> Result<(), ParseError> {
+        if self.tokens.len() != n + 1 {
+            let directive = &self.tokens[0];
+            return Err(self.error(directive, format!("{} expects {} operands", directive.text, n).as_str()))
+        }
+        Ok(())
+    }
+
+    fn current_class<'c>(&self, classes: &'c mut [Class], directive: &Token) -> Result<&'c mut Class, ParseError> {
+        match classes.last_mut() {
+            Some(c) => Ok(c),
+            None => Err(self.error(directive, format!("{} must be inside a .class", directive.text).as_str()))
+        }
+    }
+
+    /// Text from the token until the end of the line, without the quotes if it's quoted
+    fn literal(&self, token: &Token) -> Result<String, ParseError> {
+        let rest = self.text[token.offset..].trim_end();
+        if !rest.starts_with('"') {
+            return Ok(rest.to_string())
+        }
+        unquote(rest).map_err(|msg| self.error(token, msg))
+    }
+
+    fn instruction(&self) -> Result<ByteCode, ParseError> {
+        let mnemonic = &self.tokens[0];
+        if mnemonic.text == "CONST" {
+            if let Some(operand) = self.tokens.get(1) {
+                return Ok(ByteCode::CONST(self.literal(operand)?))
+            }
+        }
+        let mut split_inst = self.text.split_whitespace();
+        split_inst.next();
+        match Code::parse_ins(&mut split_inst, mnemonic.text) {
+            Ok(ins) => Ok(ins),
+            Err(err) => Err(self.error(mnemonic, err.to_string().as_str()))
+        }
+    }
+}
+
+/// Writes the value so the assembler reads it back as is, quoting it only when needed
+pub fn quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value.split_whitespace().collect::<Vec<&str>>().join(" ") == value
+        && !value.contains([';', '"', '\\']);
+    if plain {
+        return value.to_string()
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Reads back a value written between double quotes by `quote`, nothing can follow the closing quote
+pub fn unquote(quoted: &str) -> Result<String, &'static str> {
+    let mut value = String::new();
+    let mut chars = quoted.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                if i + 1 != quoted.len() {
+                    return Err("Unexpected text after the closing quote")
+                }
+                return Ok(value)
+            },
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, '"')) => value.push('"'),
+                Some((_, '\\')) => value.push('\\'),
+                _ => return Err("Invalid escape sequence")
+            },
+            c => value.push(c)
+        }
+    }
+    Err("Missing the closing quote")
+}
+
+/// Removes the comment of the line, semicolons inside quotes don't start a comment
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => ()
+        }
+    }
+    line
+}
@@ -0,0 +1,760 @@
+//! Binary punk files, laid out like the class files of the JVM. All the numbers are big endian:
+//!
+//! ```text
+//! magic            u32   0xCAFECAFE
+//! major, minor     u16   VERSION_MAJOR, VERSION_MINOR
+//! flags            u16   TRAP_OVERFLOW
+//! pool count       u16   entries of the pool plus one, index 0 means "nothing"
+//! pool entries           tag u8 followed by the entry
+//!     UTF8         u16 length, bytes
+//!     INTEGER      i32
+//!     LONG         i64
+//!     DOUBLE       f64
+//!     CLASS        u16 index of the UTF8 name
+//!     STRING       u16 index of the UTF8 value
+//!     FIELD_REF    u16 index of the UTF8 name
+//!     METHOD_REF   u16 index of the CLASS (0 if it has none), u16 index of the UTF8 name
+//! class count      u16
+//! classes                u16 CLASS, u16 super CLASS (0 if it has none),
+//!                        u16 flags (INTERFACE), u16 interface count, interfaces: u16 CLASS
+//!                        u16 field count, fields:   u16 UTF8 name, u16 UTF8 descriptor, u16 UTF8 value,
+//!                                                   u16 flags (STATIC_FIELD)
+//!                        u16 method count, methods: u16 UTF8 name, u16 UTF8 descriptor,
+//!                                                   u16 flags (STATIC_METHOD, ABSTRACT_METHOD), code, exceptions
+//! main                   code, exceptions
+//! code                   u32 instruction count, instructions: opcode u8 followed by its operands
+//! exceptions             u16 handler count, handlers: u16 UTF8 start, u16 UTF8 end, u16 UTF8 handler, u16 CLASS
+//! ```
+//!
+//! The format hasn't been released yet, so there is a single version and the reader doesn't accept any other.
+//!
+//! Operands are u16 indexes in the pool, except the local variables of `LOAD`, `STORE` and `INC` that are u16 numbers.
+//! `INC` has a second operand, the INTEGER with the delta.
+//! `NEWARRAY` points to the UTF8 descriptor of the type of the elements.
+//! `CONST` points to an INTEGER, LONG or DOUBLE when the literal is a number written in the form the machine writes
+//! it back, `10`, `10L` or `0.5`, and to a STRING otherwise.
+
+use crate::punkfile::punk_file::{PunkFile, Overflow};
+use crate::punkfile::class::Class;
+use crate::punkfile::code::{Code, ExceptionHandler};
+use crate::punkfile::field::Field;
+use crate::punkfile::main::Main;
+use crate::isa::bytecode::{self, ByteCode, Symbol};
+use crate::memory::vpk_stack::{Type, RetType};
+use std::collections::HashMap;
+use std::fmt;
+
+pub const MAGIC: u32 = 0xCAFE_CAFE;
+pub const VERSION_MAJOR: u16 = 1;
+pub const VERSION_MINOR: u16 = 0;
+
+/// The integer arithmetic fails when a result overflows instead of wrapping
+pub const TRAP_OVERFLOW: u16 = 0x0001;
+
+/// The method is called without a receiver
+pub const STATIC_METHOD: u16 = 0x0008;
+
+/// The field belongs to the class instead of its objects
+pub const STATIC_FIELD: u16 = 0x0008;
+
+/// The class is an interface
+pub const INTERFACE: u16 = 0x0200;
+
+/// The method has no code, it's declared by an interface
+pub const ABSTRACT_METHOD: u16 = 0x0400;
+
+/// Tags of the entries of the constant pool
+mod tag {
+    pub const UTF8: u8 = 1;
+    pub const INTEGER: u8 = 3;
+    pub const LONG: u8 = 5;
+    pub const DOUBLE: u8 = 6;
+    pub const CLASS: u8 = 7;
+    pub const STRING: u8 = 8;
+    pub const FIELD_REF: u8 = 9;
+    pub const METHOD_REF: u8 = 10;
+}
+
+/// Opcodes of the instructions
+mod op {
+    pub const MUL: u8 = 0x01;
+    pub const DIV: u8 = 0x02;
+    pub const SUB: u8 = 0x03;
+    pub const POP: u8 = 0x04;
+    pub const IADD: u8 = 0x05;
+    pub const SADD: u8 = 0x06;
+    pub const NULL: u8 = 0x07;
+    pub const PRINT: u8 = 0x08;
+    pub const RETURN: u8 = 0x09;
+    pub const REM: u8 = 0x0A;
+    pub const NEG: u8 = 0x0B;
+    pub const THROW: u8 = 0x0C;
+    pub const LABEL: u8 = 0x10;
+    pub const GOTO: u8 = 0x11;
+    pub const IF_EQ: u8 = 0x12;
+    pub const IF_CMPLT: u8 = 0x13;
+    pub const IF_CMPEQ: u8 = 0x14;
+    pub const IF_NE: u8 = 0x15;
+    pub const IF_LT: u8 = 0x16;
+    pub const IF_GE: u8 = 0x17;
+    pub const IF_GT: u8 = 0x18;
+    pub const IF_LE: u8 = 0x19;
+    pub const IF_CMPNE: u8 = 0x1A;
+    pub const IF_CMPGE: u8 = 0x1B;
+    pub const IF_CMPGT: u8 = 0x1C;
+    pub const IF_CMPLE: u8 = 0x1D;
+    pub const LOAD: u8 = 0x20;
+    pub const STORE: u8 = 0x21;
+    pub const CONST: u8 = 0x22;
+    pub const INC: u8 = 0x23;
+    pub const NEW: u8 = 0x30;
+    pub const GETFIELD: u8 = 0x31;
+    pub const PUTFIELD: u8 = 0x32;
+    pub const METHODCALL: u8 = 0x33;
+    pub const INVOKESTATIC: u8 = 0x34;
+    pub const INVOKEVIRTUAL: u8 = 0x35;
+    pub const INVOKESPECIAL: u8 = 0x36;
+    pub const GETSTATIC: u8 = 0x37;
+    pub const PUTSTATIC: u8 = 0x38;
+    pub const INSTANCEOF: u8 = 0x39;
+    pub const CHECKCAST: u8 = 0x3A;
+    pub const NEWARRAY: u8 = 0x40;
+    pub const ALOAD: u8 = 0x41;
+    pub const ASTORE: u8 = 0x42;
+    pub const ARRAYLENGTH: u8 = 0x43;
+    pub const AND: u8 = 0x50;
+    pub const OR: u8 = 0x51;
+    pub const XOR: u8 = 0x52;
+    pub const SHL: u8 = 0x53;
+    pub const SHR: u8 = 0x54;
+    pub const LADD: u8 = 0x60;
+    pub const LSUB: u8 = 0x61;
+    pub const LMUL: u8 = 0x62;
+    pub const LDIV: u8 = 0x63;
+    pub const LREM: u8 = 0x64;
+    pub const LNEG: u8 = 0x65;
+    pub const LCMP: u8 = 0x66;
+    pub const DADD: u8 = 0x70;
+    pub const DSUB: u8 = 0x71;
+    pub const DMUL: u8 = 0x72;
+    pub const DDIV: u8 = 0x73;
+    pub const DREM: u8 = 0x74;
+    pub const DNEG: u8 = 0x75;
+    pub const DCMPL: u8 = 0x76;
+    pub const DCMPG: u8 = 0x77;
+    pub const I2L: u8 = 0x80;
+    pub const I2D: u8 = 0x81;
+    pub const L2I: u8 = 0x82;
+    pub const L2D: u8 = 0x83;
+    pub const D2I: u8 = 0x84;
+    pub const D2L: u8 = 0x85;
+}
+
+/// Error found while reading or writing a binary punk file, offset is the byte where it was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Constant {
+    Utf8(String),
+    Integer(i32),
+    Long(i64),
+    /// Bits of the double, so the constants can be compared and hashed
+    Double(u64),
+    Class(u16),
+    String(u16),
+    FieldRef(u16),
+    MethodRef(u16, u16),
+}
+
+/// Entries of the pool, each one stored once
+#[derive(Default)]
+struct Pool {
+    entries: Vec<Constant>,
+    index: HashMap<Constant, u16>,
+}
+
+struct Writer {
+    pool: Pool,
+    /// Everything that goes after the pool
+    body: Vec<u8>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    pool: Vec<Constant>,
+}
+
+/// Encodes the punk file
+pub fn write(punk_file: &PunkFile) -> Result<Vec<u8>, FormatError> {
+    let mut w = Writer { pool: Default::default(), body: Vec::new() };
+    w.u16(punk_file.classes.len(), "classes")?;
+    for cls in punk_file.classes.iter() {
+        let this = w.pool.class(&cls.this)?;
+        w.index(this);
+        let super_cls = if cls.super_cls.is_empty() { 0 } else { w.pool.class(&cls.super_cls)? };
+        w.index(super_cls);
+        w.index(if cls.is_interface { INTERFACE } else { 0 });
+        w.u16(cls.interfaces.len(), "interfaces")?;
+        for interface in cls.interfaces.iter() {
+            let i = w.pool.class(interface)?;
+            w.index(i);
+        }
+        w.u16(cls.fields.len(), "fields")?;
+        for field in cls.fields.iter() {
+            w.utf8(&field.name)?;
+            w.utf8(&field.desc)?;
+            w.utf8(&field.value)?;
+            w.index(if field.is_static { STATIC_FIELD } else { 0 });
+        }
+        w.u16(cls.methods.len(), "methods")?;
+        for method in cls.methods.iter() {
+            w.utf8(&method.name)?;
+            w.utf8(&method.desc)?;
+            let static_flag = if method.is_static { STATIC_METHOD } else { 0 };
+            let abstract_flag = if method.is_abstract { ABSTRACT_METHOD } else { 0 };
+            w.index(static_flag | abstract_flag);
+            w.code(&method.code)?;
+            w.exceptions(&method.exceptions)?;
+        }
+    }
+    w.code(&punk_file.main.code)?;
+    w.exceptions(&punk_file.main.exceptions)?;
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&MAGIC.to_be_bytes());
+    bytes.extend_from_slice(&VERSION_MAJOR.to_be_bytes());
+    bytes.extend_from_slice(&VERSION_MINOR.to_be_bytes());
+    let flags = if punk_file.overflow.wraps() { 0 } else { TRAP_OVERFLOW };
+    bytes.extend_from_slice(&flags.to_be_bytes());
+    bytes.extend_from_slice(&(w.pool.entries.len() as u16 + 1).to_be_bytes());
+    for c in w.pool.entries.iter() {
+        match c {
+            Constant::Utf8(s) => {
+                bytes.push(tag::UTF8);
+                bytes.extend_from_slice(&(s.len() as u16).to_be_bytes());
+                bytes.extend_from_slice(s.as_bytes());
+            },
+            Constant::Integer(i) => {
+                bytes.push(tag::INTEGER);
+                bytes.extend_from_slice(&i.to_be_bytes());
+            },
+            Constant::Long(l) => {
+                bytes.push(tag::LONG);
+                bytes.extend_from_slice(&l.to_be_bytes());
+            },
+            Constant::Double(bits) => {
+                bytes.push(tag::DOUBLE);
+                bytes.extend_from_slice(&bits.to_be_bytes());
+            },
+            Constant::Class(name) => {
+                bytes.push(tag::CLASS);
+                bytes.extend_from_slice(&name.to_be_bytes());
+            },
+            Constant::String(value) => {
+                bytes.push(tag::STRING);
+                bytes.extend_from_slice(&value.to_be_bytes());
+            },
+            Constant::FieldRef(name) => {
+                bytes.push(tag::FIELD_REF);
+                bytes.extend_from_slice(&name.to_be_bytes());
+            },
+            Constant::MethodRef(class, name) => {
+                bytes.push(tag::METHOD_REF);
+                bytes.extend_from_slice(&class.to_be_bytes());
+                bytes.extend_from_slice(&name.to_be_bytes());
+            },
+        }
+    }
+    bytes.append(&mut w.body);
+    Ok(bytes)
+}
+
+/// Decodes a binary punk file
+pub fn read(bytes: &[u8]) -> Result<PunkFile, FormatError> {
+    let mut r = Reader { bytes, offset: 0, pool: Vec::new() };
+    if r.u32()? != MAGIC {
+        return Err(r.error_at(0, "This is not a binary punk file"))
+    }
+    let major = r.u16()?;
+    let minor = r.u16()?;
+    if major != VERSION_MAJOR || minor > VERSION_MINOR {
+        return Err(r.error_at(4, format!("Unsupported version {}.{}", major, minor).as_str()))
+    }
+    let flags = r.u16()?;
+    if flags & !TRAP_OVERFLOW != 0 {
+        return Err(r.error_at(8, format!("Unknown flags {:#06x}", flags).as_str()))
+    }
+
+    let count = r.u16()?;
+    // Index 0 is never used, keep a placeholder so the indexes match the positions
+    r.pool.push(Constant::Utf8(String::new()));
+    for _ in 1..count {
+        let c = match r.u8()? {
+            tag::UTF8 => {
+                let len = r.u16()? as usize;
+                let raw = r.bytes(len)?;
+                match String::from_utf8(raw.to_vec()) {
+                    Ok(s) => Constant::Utf8(s),
+                    Err(_) => return Err(r.error_at(r.offset - len, "Invalid UTF-8 string"))
+                }
+            },
+            tag::INTEGER => Constant::Integer(r.u32()? as i32),
+            tag::LONG => Constant::Long(r.u64()? as i64),
+            tag::DOUBLE => Constant::Double(r.u64()?),
+            tag::CLASS => Constant::Class(r.u16()?),
+            tag::STRING => Constant::String(r.u16()?),
+            tag::FIELD_REF => Constant::FieldRef(r.u16()?),
+            tag::METHOD_REF => Constant::MethodRef(r.u16()?, r.u16()?),
+            t => return Err(r.error_at(r.offset - 1, format!("Unknown constant tag {}", t).as_str()))
+        };
+        r.pool.push(c);
+    }
+
+    let mut classes = Vec::new();
+    for _ in 0..r.u16()? {
+        let this = r.class()?;
+        let super_cls = match r.u16()? {
+            0 => String::new(),
+            i => r.class_at(i)?
+        };
+        let flags = r.u16()?;
+        if flags & !INTERFACE != 0 {
+            return Err(r.error_at(r.offset - 2, format!("Unknown class flags {:#06x}", flags).as_str()))
+        }
+        let is_interface = flags & INTERFACE != 0;
+        let mut interfaces = Vec::new();
+        for _ in 0..r.u16()? {
+            interfaces.push(r.class()?);
+        }
+        let mut fields = Vec::new();
+        for _ in 0..r.u16()? {
+            let (name, desc, value) = (r.utf8()?, r.utf8()?, r.utf8()?);
+            let flags = r.u16()?;
+            if flags & !STATIC_FIELD != 0 {
+                return Err(r.error_at(r.offset - 2, format!("Unknown field flags {:#06x}", flags).as_str()))
+            }
+            fields.push(Field { name, desc, value, is_static: flags & STATIC_FIELD != 0 });
+        }
+        let mut methods = Vec::new();
+        for _ in 0..r.u16()? {
+            let (name, desc) = (r.utf8()?, r.utf8()?);
+            let flags = r.u16()?;
+            if flags & !(STATIC_METHOD | ABSTRACT_METHOD) != 0 {
+                return Err(r.error_at(r.offset - 2, format!("Unknown method flags {:#06x}", flags).as_str()))
+            }
+            methods.push(Code {
+                name,
+                desc,
+                is_static: flags & STATIC_METHOD != 0,
+                is_abstract: flags & ABSTRACT_METHOD != 0,
+                code: r.code()?,
+                exceptions: r.exceptions()?
+            });
+        }
+        classes.push(Class { this, super_cls, fields, methods, interfaces, is_interface, ..Default::default() });
+    }
+    let main = Main { code: r.code()?, exceptions: r.exceptions()? };
+    if r.offset != bytes.len() {
+        return Err(r.error("Unexpected bytes after the main code"))
+    }
+    let mut punk_file = PunkFile::new(classes, main);
+    punk_file.overflow = if flags & TRAP_OVERFLOW != 0 { Overflow::Trap } else { Overflow::Wrap };
+    Ok(punk_file)
+}
+
+impl Pool {
+    fn add(&mut self, c: Constant) -> Result<u16, FormatError> {
+        if let Some(i) = self.index.get(&c) {
+            return Ok(*i)
+        }
+        // Index 0 is reserved
+        if self.entries.len() + 1 > u16::MAX as usize - 1 {
+            return Err(FormatError { offset: 0, message: String::from("The constant pool is full") })
+        }
+        self.entries.push(c.clone());
+        let i = self.entries.len() as u16;
+        self.index.insert(c, i);
+        Ok(i)
+    }
+
+    fn utf8(&mut self, s: &str) -> Result<u16, FormatError> {
+        if s.len() > u16::MAX as usize {
+            return Err(FormatError { offset: 0, message: format!("A string of {} bytes is too long", s.len()) })
+        }
+        self.add(Constant::Utf8(s.to_string()))
+    }
+
+    fn class(&mut self, name: &str) -> Result<u16, FormatError> {
+        let name = self.utf8(name)?;
+        self.add(Constant::Class(name))
+    }
+}
+
+impl Writer {
+    fn index(&mut self, i: u16) {
+        self.body.extend_from_slice(&i.to_be_bytes());
+    }
+
+    fn u16(&mut self, n: usize, what: &str) -> Result<(), FormatError> {
+        if n > u16::MAX as usize {
+            return Err(FormatError { offset: self.body.len(), message: format!("Too many {}: {}", what, n) })
+        }
+        self.index(n as u16);
+        Ok(())
+    }
+
+    fn utf8(&mut self, s: &str) -> Result<(), FormatError> {
+        let i = self.pool.utf8(s)?;
+        self.index(i);
+        Ok(())
+    }
+
+    fn code(&mut self, code: &[ByteCode]) -> Result<(), FormatError> {
+        self.body.extend_from_slice(&(code.len() as u32).to_be_bytes());
+        for ins in code {
+            let (opcode, operand) = match ins {
+                ByteCode::MUL => (op::MUL, None),
+                ByteCode::DIV => (op::DIV, None),
+                ByteCode::SUB => (op::SUB, None),
+                ByteCode::REM => (op::REM, None),
+                ByteCode::NEG => (op::NEG, None),
+                ByteCode::AND => (op::AND, None),
+                ByteCode::OR => (op::OR, None),
+                ByteCode::XOR => (op::XOR, None),
+                ByteCode::SHL => (op::SHL, None),
+                ByteCode::SHR => (op::SHR, None),
+                ByteCode::LADD => (op::LADD, None),
+                ByteCode::LSUB => (op::LSUB, None),
+                ByteCode::LMUL => (op::LMUL, None),
+                ByteCode::LDIV => (op::LDIV, None),
+                ByteCode::LREM => (op::LREM, None),
+                ByteCode::LNEG => (op::LNEG, None),
+                ByteCode::LCMP => (op::LCMP, None),
+                ByteCode::DADD => (op::DADD, None),
+                ByteCode::DSUB => (op::DSUB, None),
+                ByteCode::DMUL => (op::DMUL, None),
+                ByteCode::DDIV => (op::DDIV, None),
+                ByteCode::DREM => (op::DREM, None),
+                ByteCode::DNEG => (op::DNEG, None),
+                ByteCode::DCMPL => (op::DCMPL, None),
+                ByteCode::DCMPG => (op::DCMPG, None),
+                ByteCode::I2L => (op::I2L, None),
+                ByteCode::I2D => (op::I2D, None),
+                ByteCode::L2I => (op::L2I, None),
+                ByteCode::L2D => (op::L2D, None),
+                ByteCode::D2I => (op::D2I, None),
+                ByteCode::D2L => (op::D2L, None),
+                ByteCode::POP => (op::POP, None),
+                ByteCode::IADD => (op::IADD, None),
+                ByteCode::SADD => (op::SADD, None),
+                ByteCode::NULL => (op::NULL, None),
+                ByteCode::PRINT => (op::PRINT, None),
+                ByteCode::RETURN => (op::RETURN, None),
+                ByteCode::THROW => (op::THROW, None),
+                ByteCode::LABEL(label) => (op::LABEL, Some(self.pool.utf8(label)?)),
+                ByteCode::GOTO(label) => (op::GOTO, Some(self.pool.utf8(&label.name)?)),
+                ByteCode::IF_EQ(label) => (op::IF_EQ, Some(self.pool.utf8(&label.name)?)),
+                ByteCode::IF_CMPLT(label) => (op::IF_CMPLT, Some(self.pool.utf8(&label.name)?)),
+                ByteCode::IF_CMPEQ(label) => (op::IF_CMPEQ, Some(self.pool.utf8(&label.name)?)),
+                ByteCode::IF_NE(label) => (op::IF_NE, Some(self.pool.utf8(&label.name)?)),
+                ByteCode::IF_LT(label) => (op::IF_LT, Some(self.pool.utf8(&label.name)?)),
+                ByteCode::IF_GE(label) => (op::IF_GE, Some(self.pool.utf8(&label.name)?)),
+                ByteCode::IF_GT(label) => (op::IF_GT, Some(self.pool.utf8(&label.name)?)),
+                ByteCode::IF_LE(label) => (op::IF_LE, Some(self.pool.utf8(&label.name)?)),
+                ByteCode::IF_CMPNE(label) => (op::IF_CMPNE, Some(self.pool.utf8(&label.name)?)),
+                ByteCode::IF_CMPGE(label) => (op::IF_CMPGE, Some(self.pool.utf8(&label.name)?)),
+                ByteCode::IF_CMPGT(label) => (op::IF_CMPGT, Some(self.pool.utf8(&label.name)?)),
+                ByteCode::IF_CMPLE(label) => (op::IF_CMPLE, Some(self.pool.utf8(&label.name)?)),
+                ByteCode::LOAD(var) | ByteCode::STORE(var) | ByteCode::INC(var, _) => {
+                    if *var > u16::MAX as usize {
+                        return Err(FormatError { offset: self.body.len(), message: format!("The local variable {} is too big", var) })
+                    }
+                    let opcode = match ins {
+                        ByteCode::LOAD(_) => op::LOAD,
+                        ByteCode::STORE(_) => op::STORE,
+                        _ => op::INC
+                    };
+                    (opcode, Some(*var as u16))
+                },
+                ByteCode::CONST(cst) => {
+                    let c = match bytecode::parse_const(cst) {
+                        Type::Integer(i) if i.to_string() == *cst => Constant::Integer(i),
+                        Type::Long(l) if format!("{}L", l) == *cst => Constant::Long(l),
+                        Type::Double(d) if format!("{:?}", d) == *cst => Constant::Double(d.to_bits()),
+                        _ => Constant::String(self.pool.utf8(cst)?)
+                    };
+                    (op::CONST, Some(self.pool.add(c)?))
+                },
+                ByteCode::NEW {class, ..} => (op::NEW, Some(self.pool.class(&class.name)?)),
+                ByteCode::INSTANCEOF {class} => (op::INSTANCEOF, Some(self.pool.class(&class.name)?)),
+                ByteCode::CHECKCAST {class} => (op::CHECKCAST, Some(self.pool.class(&class.name)?)),
+                ByteCode::GETFIELD {field, ..} => (op::GETFIELD, Some(self.field_ref(&field.name)?)),
+                ByteCode::PUTFIELD {field, ..} => (op::PUTFIELD, Some(self.field_ref(&field.name)?)),
+                ByteCode::GETSTATIC {field, ..} => (op::GETSTATIC, Some(self.field_ref(&field.name)?)),
+                ByteCode::PUTSTATIC {field, ..} => (op::PUTSTATIC, Some(self.field_ref(&field.name)?)),
+                ByteCode::METHODCALL {method, ..} | ByteCode::INVOKESTATIC {method} | ByteCode::INVOKEVIRTUAL {method, ..}
+                | ByteCode::INVOKESPECIAL {method} => {
+                    let (class, name) = match method.name.split_once('/') {
+                        Some((class, name)) => (self.pool.class(class)?, self.pool.utf8(name)?),
+                        None => (0, self.pool.utf8(&method.name)?)
+                    };
+                    let opcode = match ins {
+                        ByteCode::METHODCALL {..} => op::METHODCALL,
+                        ByteCode::INVOKESTATIC {..} => op::INVOKESTATIC,
+                        ByteCode::INVOKEVIRTUAL {..} => op::INVOKEVIRTUAL,
+                        _ => op::INVOKESPECIAL
+                    };
+                    (opcode, Some(self.pool.add(Constant::MethodRef(class, name))?))
+                },
+                ByteCode::NEWARRAY(elem) => (op::NEWARRAY, Some(self.pool.utf8(&elem.to_string())?)),
+                ByteCode::ALOAD => (op::ALOAD, None),
+                ByteCode::ASTORE => (op::ASTORE, None),
+                ByteCode::ARRAYLENGTH => (op::ARRAYLENGTH, None),
+            };
+            self.body.push(opcode);
+            if let Some(i) = operand {
+                self.index(i);
+            }
+            if let ByteCode::INC(_, delta) = ins {
+                let i = self.pool.add(Constant::Integer(*delta))?;
+                self.index(i);
+            }
+        }
+        Ok(())
+    }
+
+    fn exceptions(&mut self, exceptions: &[ExceptionHandler]) -> Result<(), FormatError> {
+        self.u16(exceptions.len(), "exception handlers")?;
+        for e in exceptions {
+            self.utf8(&e.start)?;
+            self.utf8(&e.end)?;
+            self.utf8(&e.handler)?;
+            let class = self.pool.class(&e.class)?;
+            self.index(class);
+        }
+        Ok(())
+    }
+
+    fn field_ref(&mut self, field: &str) -> Result<u16, FormatError> {
+        let name = self.pool.utf8(field)?;
+        self.pool.add(Constant::FieldRef(name))
+    }
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: &str) -> FormatError {
+        self.error_at(self.offset, message)
+    }
+
+    fn error_at(&self, offset: usize, message: &str) -> FormatError {
+        FormatError { offset, message: message.to_string() }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], FormatError> {
+        if self.offset + n > self.bytes.len() {
+            return Err(self.error("Unexpected end of file"))
+        }
+        let b = &self.bytes[self.offset..self.offset + n];
+        self.offset += n;
+        Ok(b)
+    }
+
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, FormatError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, FormatError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, FormatError> {
+        let b = self.bytes(8)?;
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(b);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    /// Entry of the pool, the offset of the errors is the one of the index that points to it
+    fn constant(&self, i: u16) -> Result<&Constant, FormatError> {
+        match self.pool.get(i as usize) {
+            Some(c) if i != 0 => Ok(c),
+            _ => Err(self.error_at(self.offset - 2, format!("Invalid constant pool index {}", i).as_str()))
+        }
+    }
+
+    fn utf8_at(&self, i: u16) -> Result<String, FormatError> {
+        match self.constant(i)? {
+            Constant::Utf8(s) => Ok(s.clone()),
+            _ => Err(self.error_at(self.offset - 2, format!("The constant {} is not a string", i).as_str()))
+        }
+    }
+
+    fn class_at(&self, i: u16) -> Result<String, FormatError> {
+        match self.constant(i)? {
+            Constant::Class(name) => self.utf8_at(*name),
+            _ => Err(self.error_at(self.offset - 2, format!("The constant {} is not a class", i).as_str()))
+        }
+    }
+
+    fn utf8(&mut self) -> Result<String, FormatError> {
+        let i = self.u16()?;
+        self.utf8_at(i)
+    }
+
+    fn class(&mut self) -> Result<String, FormatError> {
+        let i = self.u16()?;
+        self.class_at(i)
+    }
+
+    fn code(&mut self) -> Result<Vec<ByteCode>, FormatError> {
+        let len = self.u32()?;
+        let mut code = Vec::new();
+        for _ in 0..len {
+            let opcode = self.u8()?;
+            let ins = match opcode {
+                op::MUL => ByteCode::MUL,
+                op::DIV => ByteCode::DIV,
+                op::SUB => ByteCode::SUB,
+                op::REM => ByteCode::REM,
+                op::NEG => ByteCode::NEG,
+                op::AND => ByteCode::AND,
+                op::OR => ByteCode::OR,
+                op::XOR => ByteCode::XOR,
+                op::SHL => ByteCode::SHL,
+                op::SHR => ByteCode::SHR,
+                op::LADD => ByteCode::LADD,
+                op::LSUB => ByteCode::LSUB,
+                op::LMUL => ByteCode::LMUL,
+                op::LDIV => ByteCode::LDIV,
+                op::LREM => ByteCode::LREM,
+                op::LNEG => ByteCode::LNEG,
+                op::LCMP => ByteCode::LCMP,
+                op::DADD => ByteCode::DADD,
+                op::DSUB => ByteCode::DSUB,
+                op::DMUL => ByteCode::DMUL,
+                op::DDIV => ByteCode::DDIV,
+                op::DREM => ByteCode::DREM,
+                op::DNEG => ByteCode::DNEG,
+                op::DCMPL => ByteCode::DCMPL,
+                op::DCMPG => ByteCode::DCMPG,
+                op::I2L => ByteCode::I2L,
+                op::I2D => ByteCode::I2D,
+                op::L2I => ByteCode::L2I,
+                op::L2D => ByteCode::L2D,
+                op::D2I => ByteCode::D2I,
+                op::D2L => ByteCode::D2L,
+                op::POP => ByteCode::POP,
+                op::IADD => ByteCode::IADD,
+                op::SADD => ByteCode::SADD,
+                op::NULL => ByteCode::NULL,
+                op::PRINT => ByteCode::PRINT,
+                op::RETURN => ByteCode::RETURN,
+                op::THROW => ByteCode::THROW,
+                op::LABEL => ByteCode::LABEL(self.utf8()?),
+                op::GOTO => ByteCode::GOTO(Symbol::new(self.utf8()?)),
+                op::IF_EQ => ByteCode::IF_EQ(Symbol::new(self.utf8()?)),
+                op::IF_CMPLT => ByteCode::IF_CMPLT(Symbol::new(self.utf8()?)),
+                op::IF_CMPEQ => ByteCode::IF_CMPEQ(Symbol::new(self.utf8()?)),
+                op::IF_NE => ByteCode::IF_NE(Symbol::new(self.utf8()?)),
+                op::IF_LT => ByteCode::IF_LT(Symbol::new(self.utf8()?)),
+                op::IF_GE => ByteCode::IF_GE(Symbol::new(self.utf8()?)),
+                op::IF_GT => ByteCode::IF_GT(Symbol::new(self.utf8()?)),
+                op::IF_LE => ByteCode::IF_LE(Symbol::new(self.utf8()?)),
+                op::IF_CMPNE => ByteCode::IF_CMPNE(Symbol::new(self.utf8()?)),
+                op::IF_CMPGE => ByteCode::IF_CMPGE(Symbol::new(self.utf8()?)),
+                op::IF_CMPGT => ByteCode::IF_CMPGT(Symbol::new(self.utf8()?)),
+                op::IF_CMPLE => ByteCode::IF_CMPLE(Symbol::new(self.utf8()?)),
+                op::LOAD => ByteCode::LOAD(self.u16()? as usize),
+                op::STORE => ByteCode::STORE(self.u16()? as usize),
+                op::INC => {
+                    let var = self.u16()? as usize;
+                    let i = self.u16()?;
+                    match self.constant(i)? {
+                        Constant::Integer(delta) => ByteCode::INC(var, *delta),
+                        _ => return Err(self.error_at(self.offset - 2, format!("The constant {} is not an integer", i).as_str()))
+                    }
+                },
+                op::CONST => {
+                    let i = self.u16()?;
+                    match self.constant(i)? {
+                        Constant::Integer(n) => ByteCode::CONST(n.to_string()),
+                        Constant::Long(l) => ByteCode::CONST(format!("{}L", l)),
+                        Constant::Double(bits) => ByteCode::CONST(format!("{:?}", f64::from_bits(*bits))),
+                        Constant::String(s) => ByteCode::CONST(self.utf8_at(*s)?),
+                        _ => return Err(self.error_at(self.offset - 2, format!("The constant {} can't be loaded by CONST", i).as_str()))
+                    }
+                },
+                op::NEW => ByteCode::NEW { class: Symbol::new(self.class()?), constructor: usize::MAX },
+                op::INSTANCEOF => ByteCode::INSTANCEOF { class: Symbol::new(self.class()?) },
+                op::CHECKCAST => ByteCode::CHECKCAST { class: Symbol::new(self.class()?) },
+                op::GETFIELD => ByteCode::GETFIELD { field: Symbol::new(self.field_ref()?), slot: usize::MAX },
+                op::PUTFIELD => ByteCode::PUTFIELD { field: Symbol::new(self.field_ref()?), slot: usize::MAX },
+                op::GETSTATIC => ByteCode::GETSTATIC { field: Symbol::new(self.field_ref()?), slot: usize::MAX },
+                op::PUTSTATIC => ByteCode::PUTSTATIC { field: Symbol::new(self.field_ref()?), slot: usize::MAX },
+                op::METHODCALL => ByteCode::METHODCALL { method: self.method_ref()?, slot: usize::MAX },
+                op::INVOKESTATIC => ByteCode::INVOKESTATIC { method: self.method_ref()? },
+                op::INVOKEVIRTUAL => ByteCode::INVOKEVIRTUAL { method: self.method_ref()?, slot: usize::MAX },
+                op::INVOKESPECIAL => ByteCode::INVOKESPECIAL { method: self.method_ref()? },
+                op::NEWARRAY => {
+                    let desc = self.utf8()?;
+                    match RetType::get_type(&desc) {
+                        Ok(RetType::Void) | Err(_) => return Err(self.error_at(self.offset - 2, format!("{} is not a valid type for the elements of an array", desc).as_str())),
+                        Ok(t) => ByteCode::NEWARRAY(t)
+                    }
+                },
+                op::ALOAD => ByteCode::ALOAD,
+                op::ASTORE => ByteCode::ASTORE,
+                op::ARRAYLENGTH => ByteCode::ARRAYLENGTH,
+                x => return Err(self.error_at(self.offset - 1, format!("Unknown opcode {:#04x}", x).as_str()))
+            };
+            code.push(ins);
+        }
+        Ok(code)
+    }
+
+    /// Exception table of a code
+    fn exceptions(&mut self) -> Result<Vec<ExceptionHandler>, FormatError> {
+        let mut exceptions = Vec::new();
+        for _ in 0..self.u16()? {
+            exceptions.push(ExceptionHandler { start: self.utf8()?, end: self.utf8()?, handler: self.utf8()?, class: self.class()? });
+        }
+        Ok(exceptions)
+    }
+
+    fn method_ref(&mut self) -> Result<Symbol, FormatError> {
+        let i = self.u16()?;
+        let name = match self.constant(i)? {
+            Constant::MethodRef(0, name) => self.utf8_at(*name)?,
+            Constant::MethodRef(class, name) => format!("{}/{}", self.class_at(*class)?, self.utf8_at(*name)?),
+            _ => return Err(self.error_at(self.offset - 2, format!("The constant {} is not a method", i).as_str()))
+        };
+        Ok(Symbol::new(name))
+    }
+
+    fn field_ref(&mut self) -> Result<String, FormatError> {
+        let i = self.u16()?;
+        match self.constant(i)? {
+            Constant::FieldRef(name) => self.utf8_at(*name),
+            _ => Err(self.error_at(self.offset - 2, format!("The constant {} is not a field", i).as_str()))
+        }
+    }
+}
@@ -1,30 +1,42 @@
 use crate::punkfile::code::Code;
 use crate::punkfile::deserializer::ClassDeserialize;
 use crate::punkfile::field::Field;
+use crate::error::VmError;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Class {
     pub this: String,
     pub super_cls: String,
     pub fields: Vec<Field>,
     pub methods: Vec<Code>,
+    /// Interfaces that the class implements, or that the interface extends
+    pub interfaces: Vec<String>,
+    /// Interfaces only declare abstract methods, they can't have fields nor be created
+    pub is_interface: bool,
+    /// Defined by the machine instead of the program, like the built-in exceptions
+    pub builtin: bool,
 }
 
 impl Class {
-    pub fn new(cls: ClassDeserialize) -> Class {
-        let mut c: Class = Default::default();
-        c.this = cls.this;
-        c.super_cls = cls.super_cls;
+    pub fn new(cls: ClassDeserialize) -> Result<Class, VmError> {
+        let mut c = Class {
+            this: cls.this,
+            super_cls: cls.super_cls,
+            interfaces: cls.interfaces,
+            is_interface: cls.is_interface,
+            ..Default::default()
+        };
         for field in cls.fields {
             c.fields.push(Field::new(field))
         }
         for method in cls.methods {
-            c.methods.push(Code::new(method))
+            let m = Code::new(method, &c.this)?;
+            c.methods.push(m)
         }
-        c
+        Ok(c)
     }
 
     pub fn find_method(&self, method: &str) -> Option<&Code> {
-        self.methods.iter().find(|m| m.name == method.to_string())
+        self.methods.iter().find(|m| m.name == method)
     }
 }
\ No newline at end of file
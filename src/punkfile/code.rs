@@ -1,72 +1,205 @@
 use crate::punkfile::deserializer::CodeDeserialize;
-use crate::isa::bytecode::ByteCode;
+use crate::punkfile::assembler;
+use crate::isa::bytecode::{ByteCode, Symbol};
+use crate::error::{ErrorKind, VmError};
+use crate::memory::vpk_stack::RetType;
 use std::str::SplitWhitespace;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Code {
     pub name: String,
     pub desc: String,
+    /// Static methods are called without a receiver, their arguments start at the local variable 0
+    pub is_static: bool,
+    /// Abstract methods only have a descriptor, the classes implementing the interface that declares them
+    /// have the code
+    pub is_abstract: bool,
     pub code: Vec<ByteCode>,
+    /// Handlers of the exceptions thrown by the code, the first one that matches catches the exception
+    pub exceptions: Vec<ExceptionHandler>,
+}
+
+/// Entry of the exception table of a method. Exceptions of the class, or of its subclasses, thrown by the
+/// instructions from the label `start` until the label `end`, not included, jump to the label `handler`
+/// with the exception as the only value of the operator stack
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExceptionHandler {
+    pub start: String,
+    pub end: String,
+    pub handler: String,
+    pub class: String,
 }
 
 impl Code {
-    pub fn new(c: CodeDeserialize) -> Code {
-        let mut code: Code = Default::default();
-        code.name = c.name;
-        code.desc = c.descriptor;
+    /// Method of the class, it fails if one of its instructions is malformed
+    pub fn new(c: CodeDeserialize, class: &str) -> Result<Code, VmError> {
+        Ok(Code {
+            code: Code::parse_code(&c.code, &format!("{}/{}", class, c.name))?,
+            name: c.name,
+            desc: c.descriptor,
+            is_static: c.is_static,
+            is_abstract: c.is_abstract,
+            exceptions: c.exceptions,
+        })
+    }
 
-        for ins in c.code {
+    /// Instructions written as text, the pc of the errors is the position of the instruction in the method.
+    /// The literal of a `CONST` can be written between double quotes like in the assembler, to keep its spaces
+    pub fn parse_code(code: &[String], method: &str) -> Result<Vec<ByteCode>, VmError> {
+        let mut instructions = Vec::new();
+        for (pc, ins) in code.iter().enumerate() {
             let mut split_inst = ins.split_whitespace();
-            let instruction: ByteCode = match split_inst.next() {
-                Some(v) => {
-                    Code::parse_ins(&mut split_inst, v)
-                },
-                None => panic!("The code section is malformed"),
+            let literal = ins.trim().strip_prefix("CONST").map(str::trim_start).filter(|l| l.starts_with('"'));
+            let result = match (split_inst.next(), literal) {
+                (Some("CONST"), Some(literal)) => assembler::unquote(literal)
+                    .map(ByteCode::CONST)
+                    .map_err(|msg| ErrorKind::InvalidInstruction(msg.to_string())),
+                (Some(v), _) => Code::parse_ins(&mut split_inst, v),
+                (None, _) => Err(ErrorKind::InvalidInstruction("An instruction can't be empty".to_string()))
             };
-            code.code.push(instruction);
+            match result {
+                Ok(i) => instructions.push(i),
+                Err(kind) => return Err(VmError::at(kind, method.to_string(), pc))
+            }
         }
-        code
+        Ok(instructions)
     }
 
-    pub fn parse_ins(mut split_inst: &mut SplitWhitespace, v: &str) -> ByteCode {
-        fn next(iter: &mut SplitWhitespace) -> String{
-            iter.next().unwrap().to_string()
-        }
-        match v {
+    pub fn parse_ins(split_inst: &mut SplitWhitespace, v: &str) -> Result<ByteCode, ErrorKind> {
+        let missing = || ErrorKind::InvalidInstruction(format!("{} expects an operand", v));
+        let next = |iter: &mut SplitWhitespace| match iter.next() {
+            Some(op) => Ok(op.to_string()),
+            None => Err(missing())
+        };
+        let index = |iter: &mut SplitWhitespace| {
+            let op = next(iter)?;
+            match op.parse::<usize>() {
+                Ok(i) => Ok(i),
+                Err(_) => Err(ErrorKind::InvalidInstruction(format!("{} is not a valid local variable", op)))
+            }
+        };
+        let ins = match v {
             "MUL" => ByteCode::MUL,
             "DIV" => ByteCode::DIV,
             "POP" => ByteCode::POP,
             "SUB" => ByteCode::SUB,
+            "REM" => ByteCode::REM,
+            "NEG" => ByteCode::NEG,
+            "AND" => ByteCode::AND,
+            "OR" => ByteCode::OR,
+            "XOR" => ByteCode::XOR,
+            "SHL" => ByteCode::SHL,
+            "SHR" => ByteCode::SHR,
+            "LADD" => ByteCode::LADD,
+            "LSUB" => ByteCode::LSUB,
+            "LMUL" => ByteCode::LMUL,
+            "LDIV" => ByteCode::LDIV,
+            "LREM" => ByteCode::LREM,
+            "LNEG" => ByteCode::LNEG,
+            "LCMP" => ByteCode::LCMP,
+            "DADD" => ByteCode::DADD,
+            "DSUB" => ByteCode::DSUB,
+            "DMUL" => ByteCode::DMUL,
+            "DDIV" => ByteCode::DDIV,
+            "DREM" => ByteCode::DREM,
+            "DNEG" => ByteCode::DNEG,
+            "DCMPL" => ByteCode::DCMPL,
+            "DCMPG" => ByteCode::DCMPG,
+            "I2L" => ByteCode::I2L,
+            "I2D" => ByteCode::I2D,
+            "L2I" => ByteCode::L2I,
+            "L2D" => ByteCode::L2D,
+            "D2I" => ByteCode::D2I,
+            "D2L" => ByteCode::D2L,
             "IADD" => ByteCode::IADD,
             "SADD" => ByteCode::SADD,
             "NULL" => ByteCode::NULL,
             "PRINT" => ByteCode::PRINT,
             "RETURN" => ByteCode::RETURN,
-            "GOTO" => ByteCode::GOTO(next(&mut split_inst)),
-            "LOAD" => ByteCode::LOAD(next(&mut split_inst).parse::<usize>().unwrap()),
+            "THROW" => ByteCode::THROW,
+            "GOTO" => ByteCode::GOTO(Symbol::new(next(split_inst)?)),
+            "LOAD" => ByteCode::LOAD(index(split_inst)?),
             "CONST" => ByteCode::CONST({
-                let mut s = split_inst.fold(String::new(), |acc, st| format!("{} {}", acc, st));
-                s.remove(0);
-                s
+                let s: Vec<&str> = split_inst.collect();
+                if s.is_empty() {
+                    return Err(missing())
+                }
+                s.join(" ")
             }),
-            "LABEL" => ByteCode::LABEL(next(&mut split_inst)),
-            "STORE" => ByteCode::STORE(next(&mut split_inst).parse::<usize>().unwrap()),
-            "IF_EQ" => ByteCode::IF_EQ(next(&mut split_inst)),
-            "IF_CMPLT" => ByteCode::IF_CMPLT(next(&mut split_inst)),
-            "IF_CMPEQ" => ByteCode::IF_CMPEQ(next(&mut split_inst)),
+            "LABEL" => ByteCode::LABEL(next(split_inst)?),
+            "STORE" => ByteCode::STORE(index(split_inst)?),
+            "INC" => {
+                let var = index(split_inst)?;
+                let delta = next(split_inst)?;
+                match delta.parse::<i32>() {
+                    Ok(d) => ByteCode::INC(var, d),
+                    Err(_) => return Err(ErrorKind::InvalidInstruction(format!("{} is not a valid increment", delta)))
+                }
+            },
+            "IF_EQ" => ByteCode::IF_EQ(Symbol::new(next(split_inst)?)),
+            "IF_NE" => ByteCode::IF_NE(Symbol::new(next(split_inst)?)),
+            "IF_LT" => ByteCode::IF_LT(Symbol::new(next(split_inst)?)),
+            "IF_GE" => ByteCode::IF_GE(Symbol::new(next(split_inst)?)),
+            "IF_GT" => ByteCode::IF_GT(Symbol::new(next(split_inst)?)),
+            "IF_LE" => ByteCode::IF_LE(Symbol::new(next(split_inst)?)),
+            "IF_CMPLT" => ByteCode::IF_CMPLT(Symbol::new(next(split_inst)?)),
+            "IF_CMPEQ" => ByteCode::IF_CMPEQ(Symbol::new(next(split_inst)?)),
+            "IF_CMPNE" => ByteCode::IF_CMPNE(Symbol::new(next(split_inst)?)),
+            "IF_CMPGE" => ByteCode::IF_CMPGE(Symbol::new(next(split_inst)?)),
+            "IF_CMPGT" => ByteCode::IF_CMPGT(Symbol::new(next(split_inst)?)),
+            "IF_CMPLE" => ByteCode::IF_CMPLE(Symbol::new(next(split_inst)?)),
+            "INSTANCEOF" => ByteCode::INSTANCEOF {
+                class: Symbol::new(next(split_inst)?)
+            },
+            "CHECKCAST" => ByteCode::CHECKCAST {
+                class: Symbol::new(next(split_inst)?)
+            },
             "NEW" => ByteCode::NEW {
-                class: next(&mut split_inst),
+                class: Symbol::new(next(split_inst)?),
+                constructor: usize::MAX,
             },
             "GETFIELD" => ByteCode::GETFIELD {
-                field: next(&mut split_inst)
+                field: Symbol::new(next(split_inst)?),
+                slot: usize::MAX,
             },
             "PUTFIELD" => ByteCode::PUTFIELD {
-                field: next(&mut split_inst)
+                field: Symbol::new(next(split_inst)?),
+                slot: usize::MAX,
+            },
+            "GETSTATIC" => ByteCode::GETSTATIC {
+                field: Symbol::new(next(split_inst)?),
+                slot: usize::MAX,
+            },
+            "PUTSTATIC" => ByteCode::PUTSTATIC {
+                field: Symbol::new(next(split_inst)?),
+                slot: usize::MAX,
             },
             "METHODCALL" => ByteCode::METHODCALL {
-                method: next(&mut split_inst)
+                method: Symbol::new(next(split_inst)?),
+                slot: usize::MAX,
             },
-            x => panic!("The instruction {} not exist", x)
-        }
+            "INVOKESTATIC" => ByteCode::INVOKESTATIC {
+                method: Symbol::new(next(split_inst)?)
+            },
+            "INVOKEVIRTUAL" => ByteCode::INVOKEVIRTUAL {
+                method: Symbol::new(next(split_inst)?),
+                slot: usize::MAX,
+            },
+            "INVOKESPECIAL" => ByteCode::INVOKESPECIAL {
+                method: Symbol::new(next(split_inst)?)
+            },
+            "NEWARRAY" => {
+                let desc = next(split_inst)?;
+                match RetType::get_type(&desc) {
+                    Ok(RetType::Void) | Err(_) => return Err(ErrorKind::InvalidInstruction(format!("{} is not a valid type for the elements of an array", desc))),
+                    Ok(t) => ByteCode::NEWARRAY(t)
+                }
+            },
+            "ALOAD" => ByteCode::ALOAD,
+            "ASTORE" => ByteCode::ASTORE,
+            "ARRAYLENGTH" => ByteCode::ARRAYLENGTH,
+            x => return Err(ErrorKind::InvalidInstruction(format!("The instruction {} not exist", x)))
+        };
+        Ok(ins)
     }
 }
\ No newline at end of file
@@ -0,0 +1,38 @@
+use crate::memory::vpk_stack::RetType;
+use crate::error::ErrorKind;
+
+/// Parsed method descriptor of the form `(args)Ret`, where every argument is a single type letter
+/// preceded by a `[` for each dimension if it's an array, like `([II)[S`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Descriptor {
+    pub args: Vec<RetType>,
+    pub ret: RetType,
+}
+
+impl Descriptor {
+    pub fn parse(desc: &str) -> Result<Descriptor, ErrorKind> {
+        let v: Vec<&str> = desc.split(['(', ')']).collect();
+        if v.len() != 3 || !v[0].is_empty() {
+            return Err(ErrorKind::InvalidDescriptor(desc.to_string()))
+        }
+        let mut args = Vec::new();
+        let mut start = 0;
+        for (i, c) in v[1].char_indices() {
+            if c == '[' {
+                continue
+            }
+            match RetType::get_type(&v[1][start..i + c.len_utf8()]) {
+                Ok(RetType::Void) | Err(_) => return Err(ErrorKind::InvalidDescriptor(desc.to_string())),
+                Ok(t) => args.push(t),
+            }
+            start = i + c.len_utf8();
+        }
+        if start != v[1].len() {
+            return Err(ErrorKind::InvalidDescriptor(desc.to_string()))
+        }
+        Ok(Descriptor {
+            args,
+            ret: RetType::get_type(v[2])?,
+        })
+    }
+}
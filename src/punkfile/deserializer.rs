@@ -1,45 +1,72 @@
+use crate::punkfile::punk_file::Overflow;
+use crate::punkfile::code::ExceptionHandler;
+use crate::error::VmError;
 use std::fs::File;
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct PunkFileJSON {
     pub magic_number: String,
+    /// Optional, programs that don't say it wrap
+    #[serde(default, skip_serializing_if = "Overflow::wraps")]
+    pub overflow: Overflow,
     pub classes: Vec<ClassDeserialize>,
-    pub main_code: Vec<String>
+    pub main_code: Vec<String>,
+    /// Optional, the exception table of the main code
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub main_exceptions: Vec<ExceptionHandler>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct ClassDeserialize {
     #[serde(rename = "_this")]
     pub this: String,
     #[serde(rename = "_super")]
     pub super_cls: String,
+    /// Optional, classes that don't implement any interface don't have it
+    #[serde(rename = "_interfaces", default, skip_serializing_if = "Vec::is_empty")]
+    pub interfaces: Vec<String>,
+    /// Optional, classes are not interfaces unless they say so
+    #[serde(rename = "interface", default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_interface: bool,
     pub fields: Vec<FieldDeserialize>,
     pub methods: Vec<CodeDeserialize>
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct CodeDeserialize {
     pub name: String,
     pub descriptor: String,
-    pub code: Vec<String>
+    /// Optional, methods are instance methods unless they say so
+    #[serde(rename = "static", default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_static: bool,
+    /// Optional, the methods of the interfaces are abstract, they have a descriptor but no code
+    #[serde(rename = "abstract", default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_abstract: bool,
+    pub code: Vec<String>,
+    /// Optional, methods that don't catch anything don't have it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exceptions: Vec<ExceptionHandler>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct FieldDeserialize {
     pub name: String,
     pub descriptor: String,
-    pub value: String
+    pub value: String,
+    /// Optional, fields belong to the objects unless they say so
+    #[serde(rename = "static", default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_static: bool,
 }
 
 impl PunkFileJSON {
-    pub fn from_file(uri: &str) -> PunkFileJSON {
+    pub fn from_file(uri: &str) -> Result<PunkFileJSON, VmError> {
         let file = match File::open(uri) {
             Ok(file) => file,
-            Err(_) => panic!("The json file could not be opened")
+            Err(err) => return Err(VmError::file(format!("{} could not be opened: {}", uri, err)))
         };
         match serde_json::from_reader(file) {
-            Ok(v) => return v,
-            Err(err) => panic!("The json from {} could not be parsed\n {}", uri, err)
-        };
+            Ok(v) => Ok(v),
+            Err(err) => Err(VmError::file(format!("the json from {} could not be parsed: {}", uri, err)))
+        }
     }
 }
\ No newline at end of file
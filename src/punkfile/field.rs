@@ -1,11 +1,13 @@
 use crate::punkfile::deserializer::FieldDeserialize;
 use crate::memory::vpk_stack::Type;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Field {
     pub name: String,
     pub desc: String,
     pub value: String,
+    /// Static fields belong to the class, there's a single value instead of one in each object
+    pub is_static: bool,
 }
 
 impl Field {
@@ -13,11 +15,23 @@ impl Field {
         Field {
             name: fd.name,
             desc: fd.descriptor,
-            value: fd.value
+            value: fd.value,
+            is_static: fd.is_static,
         }
     }
 
+    /// Initial value of the field, array fields start as null because the value can't describe an array.
+    /// The value of a long can be written with or without the `L` suffix
     pub fn get_type(&self) -> Type {
+        if self.desc.starts_with('[') {
+            return Type::Null
+        }
+        let long = self.value.strip_suffix('L').unwrap_or(&self.value).parse::<i64>();
+        match (self.desc.as_str(), long, self.value.parse::<f64>()) {
+            ("L", Ok(l), _) => return Type::Long(l),
+            ("D", _, Ok(d)) => return Type::Double(d),
+            _ => ()
+        }
         match self.value.parse::<i32>() {
             Ok(i) => Type::Integer(i),
             Err(_) => Type::String(self.value.clone())
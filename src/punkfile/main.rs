@@ -1,24 +1,32 @@
-use crate::punkfile::code::Code;
+use crate::punkfile::code::{Code, ExceptionHandler};
 use crate::isa::bytecode::ByteCode;
+use crate::error::VmError;
+use crate::vm::MAIN_METHOD;
 
 #[derive(Default, Debug)]
 pub struct Main {
     pub code: Vec<ByteCode>,
+    pub exceptions: Vec<ExceptionHandler>,
 }
 
 impl Main {
-    pub fn new(code: Vec<String>) -> Main {
-        let mut main: Main = Default::default();
-        for ins in code {
-            let mut split_inst = ins.split_whitespace();
-            let instruction: ByteCode = match split_inst.next() {
-                Some(v) => {
-                    Code::parse_ins(&mut split_inst, v)
-                },
-                None => panic!("The code section is malformed"),
-            };
-            main.code.push(instruction);
+    /// Main code without exception table, it fails if one of its instructions is malformed
+    pub fn new(code: Vec<String>) -> Result<Main, VmError> {
+        Ok(Main {
+            code: Code::parse_code(&code, MAIN_METHOD)?,
+            exceptions: Vec::new(),
+        })
+    }
+
+    /// The main code as a static method without arguments with the given name
+    pub fn as_method(&self, name: &str) -> Code {
+        Code {
+            name: name.to_string(),
+            desc: "()V".to_string(),
+            is_static: true,
+            is_abstract: false,
+            code: self.code.clone(),
+            exceptions: self.exceptions.clone(),
         }
-        main
     }
 }
\ No newline at end of file
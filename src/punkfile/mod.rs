@@ -7,3 +7,6 @@ pub mod main;
 pub mod class;
 pub mod deserializer;
 pub mod field;
+pub mod descriptor;
+pub mod assembler;
+pub mod binary;
@@ -1,34 +1,148 @@
 use crate::punkfile::class::Class;
 use crate::punkfile::main::Main;
-use crate::punkfile::deserializer::PunkFileJSON;
+use crate::punkfile::deserializer::{PunkFileJSON, ClassDeserialize, CodeDeserialize, FieldDeserialize};
+use crate::punkfile::code::Code;
+use crate::punkfile::assembler;
+use crate::isa::bytecode::ByteCode;
+use crate::error::VmError;
+use crate::exception;
 
 const MAGIC_NUMBER: &str =  "CAFECAFE";
 
+/// What the integer arithmetic does when a result doesn't fit in an integer, it's chosen by each program
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Overflow {
+    /// The result wraps around, as two's complement
+    #[default]
+    Wrap,
+    /// The instruction fails with an `ArithmeticError`
+    Trap,
+}
+
 #[derive(Default, Debug)]
 pub struct PunkFile {
     magic_number: String,
+    pub overflow: Overflow,
     pub classes: Vec<Class>,
     pub main: Main,
 }
 
+impl Overflow {
+    pub fn wraps(&self) -> bool {
+        *self == Overflow::Wrap
+    }
+}
+
 impl PunkFile {
-    pub fn from_file(uri: &str) -> PunkFile {
-        let pk_des: PunkFileJSON = PunkFileJSON::from_file(uri);
-        let mut pk: PunkFile = Default::default();
-        pk.magic_number = pk_des.magic_number;
+    /// Reads the punk file in the JSON format, it fails if the file can't be read or its code is malformed
+    pub fn from_file(uri: &str) -> Result<PunkFile, VmError> {
+        let pk_des: PunkFileJSON = PunkFileJSON::from_file(uri)?;
+        if pk_des.magic_number != MAGIC_NUMBER {
+            return Err(VmError::file(format!("{} has the magic number {} instead of {}", uri, pk_des.magic_number, MAGIC_NUMBER)))
+        }
+        let mut pk = PunkFile {
+            magic_number: pk_des.magic_number,
+            overflow: pk_des.overflow,
+            ..Default::default()
+        };
         for cls in pk_des.classes {
-            let c: Class = Class::new(cls);
+            let c: Class = Class::new(cls)?;
             pk.classes.push(c);
         }
 
-        pk.main = Main::new(pk_des.main_code);
+        pk.main = Main::new(pk_des.main_code)?;
+        pk.main.exceptions = pk_des.main_exceptions;
 
-        assert_eq!(pk.magic_number, MAGIC_NUMBER);
+        Ok(pk)
+    }
 
-        pk
+    /// Punk file with the given classes and main code
+    pub fn new(classes: Vec<Class>, main: Main) -> PunkFile {
+        PunkFile {
+            magic_number: String::from(MAGIC_NUMBER),
+            overflow: Overflow::Wrap,
+            classes,
+            main,
+        }
     }
 
+    /// Writes the punk file in the JSON format read by `from_file`. The instructions of that format are split
+    /// by whitespace, so the `CONST` literals that wouldn't be read back as they are are quoted
+    pub fn to_json(&self) -> String {
+        let code = |code: &[ByteCode]| code.iter().map(|ins| match ins {
+            ByteCode::CONST(cst) => format!("CONST {}", assembler::quote(cst)),
+            ins => ins.to_string()
+        }).collect();
+        let method = |m: &Code| CodeDeserialize {
+            name: m.name.clone(),
+            descriptor: m.desc.clone(),
+            is_static: m.is_static,
+            is_abstract: m.is_abstract,
+            code: code(&m.code),
+            exceptions: m.exceptions.clone(),
+        };
+        let json = PunkFileJSON {
+            magic_number: self.magic_number.clone(),
+            overflow: self.overflow,
+            classes: self.classes.iter().map(|cls| ClassDeserialize {
+                this: cls.this.clone(),
+                super_cls: cls.super_cls.clone(),
+                interfaces: cls.interfaces.clone(),
+                is_interface: cls.is_interface,
+                fields: cls.fields.iter().map(|f| FieldDeserialize {
+                    name: f.name.clone(),
+                    descriptor: f.desc.clone(),
+                    value: f.value.clone(),
+                    is_static: f.is_static,
+                }).collect(),
+                methods: cls.methods.iter().map(method).collect(),
+            }).collect(),
+            main_code: code(&self.main.code),
+            main_exceptions: self.main.exceptions.clone(),
+        };
+        serde_json::to_string_pretty(&json).expect("A punk file can always be written as JSON")
+    }
+
+    /// Searches the class in the program and, if the program doesn't define it, in the built-in classes
     pub fn find_class(&self, name: &str) -> Option<&Class> {
-        self.classes.iter().find(|c| c.this == name.to_string())
+        self.all_classes().find(|c| c.this == name)
+    }
+
+    /// Classes of the program followed by the built-in classes that the program doesn't define
+    pub fn all_classes(&self) -> impl Iterator<Item = &Class> {
+        let builtins = exception::builtins().iter()
+            .filter(move |b| !self.classes.iter().any(|c| c.this == b.this));
+        self.classes.iter().chain(builtins)
+    }
+
+    /// Adds to the classes of the program the built-in classes that it doesn't define
+    pub fn add_builtins(&mut self) {
+        let missing: Vec<Class> = self.all_classes().skip(self.classes.len()).cloned().collect();
+        self.classes.extend(missing);
+    }
+
+    /// Whether the class is `ancestor`, inherits from it or implements it, directly or through its parents
+    /// and the interfaces they implement
+    pub fn is_subclass(&self, class: &str, ancestor: &str) -> bool {
+        let mut pending = vec![class];
+        // Classes already searched, so a cycle doesn't go on forever
+        let mut visited = Vec::new();
+        while let Some(cls_name) = pending.pop() {
+            if cls_name == ancestor {
+                return true
+            }
+            if visited.contains(&cls_name) {
+                continue
+            }
+            visited.push(cls_name);
+            if let Some(cls) = self.find_class(cls_name) {
+                if !cls.super_cls.is_empty() {
+                    pending.push(cls.super_cls.as_str());
+                }
+                pending.extend(cls.interfaces.iter().map(|i| i.as_str()));
+            }
+        }
+        false
     }
 }
\ No newline at end of file
@@ -0,0 +1,120 @@
+//! Tracer that reports every instruction the machine executes, with the operator stack before and after it,
+//! plus the objects and arrays allocated, the fields and elements written and the exceptions caught by it.
+//! Each event is a line, either as text or as a JSON object so traces of different versions of the machine
+//! can be compared:
+//!
+//! ```text
+//! {"after":[],"before":[{"Object":0},{"String":"Rex"}],"event":"instruction","instruction":"PUTFIELD name","method":"Dog/constructor","pc":7}
+//! {"event":"putfield","field":"name","handle":0,"value":{"String":"Rex"}}
+//! ```
+//!
+//! `after` is the operator stack of the frame that runs next, so for calls it's the stack of the called
+//! method and for returns the one of the caller.
+
+use crate::memory::vpk_stack::{Type, RetType};
+use crate::isa::bytecode::ByteCode;
+use std::io::{self, Write};
+use serde_json::json;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    Json,
+}
+
+/// Something that happened while executing an instruction
+enum Event {
+    Allocation { handle: usize, class: String },
+    ArrayAllocation { handle: usize, elem: String, length: usize },
+    FieldWrite { handle: usize, field: String, value: Type },
+    ElementWrite { handle: usize, index: i32, value: Type },
+    /// The exception has been caught by the handler at the pc
+    Throw { handle: usize, class: String, handler: usize },
+}
+
+pub struct Tracer {
+    format: TraceFormat,
+    out: Box<dyn Write>,
+    /// Events of the instruction being executed, they're written after it
+    pending: Vec<Event>,
+}
+
+impl Tracer {
+    pub fn new(format: TraceFormat, out: Box<dyn Write>) -> Tracer {
+        Tracer {
+            format,
+            out,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Tracer that writes to the standard error, so the trace isn't mixed with the output of the program
+    pub fn stderr(format: TraceFormat) -> Tracer {
+        Tracer::new(format, Box::new(io::stderr()))
+    }
+
+    pub fn allocation(&mut self, handle: usize, class: &str) {
+        self.pending.push(Event::Allocation { handle, class: class.to_string() });
+    }
+
+    pub fn array_allocation(&mut self, handle: usize, elem: &RetType, length: usize) {
+        self.pending.push(Event::ArrayAllocation { handle, elem: elem.to_string(), length });
+    }
+
+    pub fn field_write(&mut self, handle: usize, field: &str, value: Type) {
+        self.pending.push(Event::FieldWrite { handle, field: field.to_string(), value });
+    }
+
+    pub fn element_write(&mut self, handle: usize, index: i32, value: Type) {
+        self.pending.push(Event::ElementWrite { handle, index, value });
+    }
+
+    pub fn throw(&mut self, handle: usize, class: &str, handler: usize) {
+        self.pending.push(Event::Throw { handle, class: class.to_string(), handler });
+    }
+
+    /// Writes the instruction that has been executed followed by its events.
+    /// Failing to write the trace doesn't stop the program
+    pub fn instruction(&mut self, pc: usize, method: &str, ins: &ByteCode, before: &[Type], after: &[Type]) {
+        let mut lines = vec![match self.format {
+            TraceFormat::Text => format!("{:>6} {:<24} {:<28} {:?} -> {:?}", pc, method, ins.to_string(), before, after),
+            TraceFormat::Json => json!({
+                "event": "instruction",
+                "pc": pc,
+                "method": method,
+                "instruction": ins.to_string(),
+                "before": before,
+                "after": after,
+            }).to_string()
+        }];
+        for event in self.pending.drain(..) {
+            lines.push(match (self.format, event) {
+                (TraceFormat::Text, Event::Allocation { handle, class }) =>
+                    format!("{:>6} alloc {:?} {}", "", Type::Object(handle), class),
+                (TraceFormat::Text, Event::ArrayAllocation { handle, elem, length }) =>
+                    format!("{:>6} newarray {:?} {}[{}]", "", Type::Array(handle), elem, length),
+                (TraceFormat::Text, Event::FieldWrite { handle, field, value }) =>
+                    format!("{:>6} putfield {:?}.{} = {:?}", "", Type::Object(handle), field, value),
+                (TraceFormat::Text, Event::ElementWrite { handle, index, value }) =>
+                    format!("{:>6} astore {:?}[{}] = {:?}", "", Type::Array(handle), index, value),
+                (TraceFormat::Text, Event::Throw { handle, class, handler }) =>
+                    format!("{:>6} throw {:?} {} -> pc {}", "", Type::Object(handle), class, handler),
+                (TraceFormat::Json, Event::Allocation { handle, class }) =>
+                    json!({ "event": "alloc", "handle": handle, "class": class }).to_string(),
+                (TraceFormat::Json, Event::ArrayAllocation { handle, elem, length }) =>
+                    json!({ "event": "newarray", "handle": handle, "elem": elem, "length": length }).to_string(),
+                (TraceFormat::Json, Event::FieldWrite { handle, field, value }) =>
+                    json!({ "event": "putfield", "handle": handle, "field": field, "value": value }).to_string(),
+                (TraceFormat::Json, Event::ElementWrite { handle, index, value }) =>
+                    json!({ "event": "astore", "handle": handle, "index": index, "value": value }).to_string(),
+                (TraceFormat::Json, Event::Throw { handle, class, handler }) =>
+                    json!({ "event": "throw", "handle": handle, "class": class, "handler": handler }).to_string(),
+            });
+        }
+        for line in lines {
+            if writeln!(self.out, "{}", line).is_err() {
+                return
+            }
+        }
+    }
+}
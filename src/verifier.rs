@@ -0,0 +1,514 @@
+use crate::punkfile::punk_file::PunkFile;
+use crate::punkfile::code::Code;
+use crate::punkfile::descriptor::Descriptor;
+use crate::memory::vpk_stack::{Type, RetType};
+use crate::isa::bytecode::{self, ByteCode};
+use crate::error::{ErrorKind, VmError};
+use crate::vm::{MAIN_METHOD, CLINIT};
+use crate::native::Natives;
+use crate::exception::EXCEPTION;
+use std::collections::HashMap;
+
+/// Abstract value the verifier tracks for every slot of the operator stack and every local variable
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VType {
+    Integer,
+    Long,
+    Double,
+    String,
+    Boolean,
+    Object,
+    Array,
+    Null,
+    /// Any value, for example a field whose type can't be known statically
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Local {
+    /// Nothing has been stored in the variable in at least one of the paths reaching the instruction
+    Unset,
+    Set(VType),
+}
+
+/// What is known before executing an instruction
+#[derive(Debug, Clone, PartialEq)]
+struct State {
+    stack: Vec<VType>,
+    locals: Vec<Local>,
+}
+
+/// Checks every method and the main code of the punk file without running them. For every pc it computes the
+/// types of the operator stack and local variables and checks that:
+/// - every instruction finds on the stack the values it needs, with the right types
+/// - the stack has the same depth and types no matter which path reaches an instruction
+/// - local variables are only read after something has been stored in them
+/// - `RETURN` returns what the descriptor of the method says
+/// - jumps go to labels defined in the method and execution never falls off the end of a method
+/// - the handlers of the exception table catch exceptions and find the same locals as the instructions they cover
+///
+/// The pc of the errors is the position of the instruction inside its method.
+/// Calls to methods that no class defines are checked against the standard native methods
+pub fn verify(punk_file: &PunkFile) -> Result<(), Vec<VmError>> {
+    verify_with_natives(punk_file, &Natives::standard())
+}
+
+/// Same as `verify` with the given table of native methods
+pub fn verify_with_natives(punk_file: &PunkFile, natives: &Natives) -> Result<(), Vec<VmError>> {
+    let mut errors = Vec::new();
+    for cls in punk_file.classes.iter() {
+        // Abstract methods don't have code
+        for method in cls.methods.iter().filter(|m| !m.is_abstract) {
+            let name = format!("{}/{}", cls.this, method.name);
+            if let Err(err) = verify_method(punk_file, natives, name.as_str(), method) {
+                errors.push(err);
+            }
+        }
+    }
+    let main = punk_file.main.as_method(MAIN_METHOD);
+    if let Err(err) = verify_method(punk_file, natives, MAIN_METHOD, &main) {
+        errors.push(err);
+    }
+
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+/// Checks a single method of a class, the local variable 0 holds the object and the next ones the arguments.
+/// The arguments of static methods start at the local variable 0
+pub fn verify_method(punk_file: &PunkFile, natives: &Natives, name: &str, method: &Code) -> Result<(), VmError> {
+    let desc = match Descriptor::parse(&method.desc) {
+        Ok(d) => d,
+        Err(kind) => return Err(VmError::at(kind, name.to_string(), 0))
+    };
+    let mut locals = if method.is_static { Vec::new() } else { vec![Local::Set(VType::Object)] };
+    locals.extend(desc.args.iter().map(|t| Local::Set(VType::from_ret_type(t))));
+    verify_code(punk_file, natives, name, method, locals)
+}
+
+fn verify_code(punk_file: &PunkFile, natives: &Natives, name: &str, method: &Code, locals: Vec<Local>) -> Result<(), VmError> {
+    let error = |kind, pc| VmError::at(kind, name.to_string(), pc);
+    let ret = match Descriptor::parse(&method.desc) {
+        Ok(d) => d.ret,
+        Err(kind) => return Err(error(kind, 0))
+    };
+    let code = &method.code;
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    for (pc, ins) in code.iter().enumerate() {
+        if let ByteCode::LABEL(label) = ins {
+            if labels.insert(label.as_str(), pc).is_some() {
+                return Err(error(ErrorKind::DuplicateLabel(label.clone()), pc))
+            }
+        }
+    }
+
+    if code.is_empty() {
+        return Err(error(ErrorKind::InvalidPc(0), 0))
+    }
+    // Ranges of the exception table as (start, end, handler) pcs
+    let mut handlers = Vec::new();
+    for e in method.exceptions.iter() {
+        let pc_of = |label: &String| match labels.get(label.as_str()) {
+            Some(pc) => Ok(*pc),
+            None => Err(error(ErrorKind::UnknownLabel(label.clone()), 0))
+        };
+        if punk_file.find_class(&e.class).is_none() {
+            return Err(error(ErrorKind::UnknownClass(e.class.clone()), 0))
+        }
+        if !punk_file.is_subclass(&e.class, EXCEPTION) {
+            return Err(error(ErrorKind::TypeMismatch(format!("{} is not an exception", e.class)), 0))
+        }
+        handlers.push((pc_of(&e.start)?, pc_of(&e.end)?, pc_of(&e.handler)?));
+    }
+    let mut states: Vec<Option<State>> = vec![None; code.len()];
+    let mut pending = vec![0];
+    states[0] = Some(State { stack: Vec::new(), locals });
+
+    while let Some(pc) = pending.pop() {
+        let mut state = states[pc].clone().unwrap();
+        // A handler starts with the exception on the stack and the locals from before the failed instruction
+        let caught = State { stack: vec![VType::Object], locals: state.locals.clone() };
+        let ins = &code[pc];
+        let successors = execute(punk_file, natives, pc, ins, &ret, &labels, &mut state).map_err(|kind| error(kind, pc))?;
+        let mut next_states: Vec<(usize, &State)> = successors.into_iter().map(|next| (next, &state)).collect();
+        for (start, end, handler) in handlers.iter() {
+            if *start <= pc && pc < *end {
+                next_states.push((*handler, &caught));
+            }
+        }
+        for (next, state) in next_states {
+            if next >= code.len() {
+                return Err(error(ErrorKind::InvalidPc(next), pc))
+            }
+            let merged = match &states[next] {
+                None => state.clone(),
+                Some(old) => old.merge(state).map_err(|kind| error(kind, next))?
+            };
+            if states[next].as_ref() != Some(&merged) {
+                states[next] = Some(merged);
+                pending.push(next);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies the effect of the instruction to the state, returns the pcs that can be executed after it
+fn execute(punk_file: &PunkFile, natives: &Natives, pc: usize, ins: &ByteCode, ret: &RetType, labels: &HashMap<&str, usize>, state: &mut State) -> Result<Vec<usize>, ErrorKind> {
+    let pc_of = |label: &str| match labels.get(label) {
+        Some(pc) => Ok(*pc),
+        None => Err(ErrorKind::UnknownLabel(label.to_string()))
+    };
+
+    match ins {
+        ByteCode::MUL | ByteCode::DIV | ByteCode::SUB | ByteCode::IADD | ByteCode::REM | ByteCode::AND | ByteCode::OR
+        | ByteCode::XOR | ByteCode::SHL | ByteCode::SHR => {
+            state.pop_expect(&[VType::Integer])?;
+            state.pop_expect(&[VType::Integer])?;
+            state.stack.push(VType::Integer);
+        },
+        ByteCode::NEG => {
+            state.pop_expect(&[VType::Integer])?;
+            state.stack.push(VType::Integer);
+        },
+        ByteCode::LADD | ByteCode::LSUB | ByteCode::LMUL | ByteCode::LDIV | ByteCode::LREM => {
+            state.pop_expect(&[VType::Long])?;
+            state.pop_expect(&[VType::Long])?;
+            state.stack.push(VType::Long);
+        },
+        ByteCode::DADD | ByteCode::DSUB | ByteCode::DMUL | ByteCode::DDIV | ByteCode::DREM => {
+            state.pop_expect(&[VType::Double])?;
+            state.pop_expect(&[VType::Double])?;
+            state.stack.push(VType::Double);
+        },
+        ByteCode::LNEG => {
+            state.pop_expect(&[VType::Long])?;
+            state.stack.push(VType::Long);
+        },
+        ByteCode::DNEG => {
+            state.pop_expect(&[VType::Double])?;
+            state.stack.push(VType::Double);
+        },
+        ByteCode::LCMP => {
+            state.pop_expect(&[VType::Long])?;
+            state.pop_expect(&[VType::Long])?;
+            state.stack.push(VType::Integer);
+        },
+        ByteCode::DCMPL | ByteCode::DCMPG => {
+            state.pop_expect(&[VType::Double])?;
+            state.pop_expect(&[VType::Double])?;
+            state.stack.push(VType::Integer);
+        },
+        ByteCode::I2L | ByteCode::I2D | ByteCode::L2I | ByteCode::L2D | ByteCode::D2I | ByteCode::D2L => {
+            let (from, to) = match ins {
+                ByteCode::I2L => (VType::Integer, VType::Long),
+                ByteCode::I2D => (VType::Integer, VType::Double),
+                ByteCode::L2I => (VType::Long, VType::Integer),
+                ByteCode::L2D => (VType::Long, VType::Double),
+                ByteCode::D2I => (VType::Double, VType::Integer),
+                _ => (VType::Double, VType::Long)
+            };
+            state.pop_expect(&[from])?;
+            state.stack.push(to);
+        },
+        ByteCode::SADD => {
+            state.pop_expect(&[VType::String, VType::Integer, VType::Long, VType::Double])?;
+            state.pop_expect(&[VType::String, VType::Integer, VType::Long, VType::Double])?;
+            state.stack.push(VType::String);
+        },
+        ByteCode::POP => {
+            state.pop()?;
+        },
+        ByteCode::NULL => state.stack.push(VType::Null),
+        ByteCode::PRINT => {
+            state.pop_expect(&[VType::Integer, VType::Long, VType::Double, VType::String, VType::Boolean])?;
+        },
+        ByteCode::RETURN => {
+            let value = match ret {
+                RetType::Void => return Ok(Vec::new()),
+                _ => state.pop()?
+            };
+            if !value.matches(&VType::from_ret_type(ret)) {
+                return Err(ErrorKind::InvalidReturn(format!("expected {:?} but found {:?}", ret, value)))
+            }
+            return Ok(Vec::new())
+        },
+        ByteCode::THROW => {
+            state.pop_expect(&[VType::Object])?;
+            return Ok(Vec::new())
+        },
+        ByteCode::LABEL(_) => (),
+        ByteCode::GOTO(label) => return Ok(vec![pc_of(&label.name)?]),
+        ByteCode::LOAD(i) => {
+            match state.locals.get(*i) {
+                Some(Local::Set(t)) => state.stack.push(*t),
+                _ => return Err(ErrorKind::UninitializedLocal(*i))
+            }
+        },
+        ByteCode::STORE(i) => {
+            let t = state.pop()?;
+            if state.locals.len() <= *i {
+                state.locals.resize(*i + 1, Local::Unset);
+            }
+            state.locals[*i] = Local::Set(t);
+        },
+        ByteCode::INC(i, _) => {
+            match state.locals.get(*i) {
+                Some(Local::Set(VType::Integer)) => (),
+                Some(Local::Set(t)) => return Err(ErrorKind::TypeMismatch(format!("INC needs an integer in the local variable {} but found {:?}", i, t))),
+                _ => return Err(ErrorKind::UninitializedLocal(*i))
+            }
+        },
+        ByteCode::CONST(cst) => state.stack.push(VType::from_type(&bytecode::parse_const(cst))),
+        ByteCode::IF_EQ(label) | ByteCode::IF_NE(label) | ByteCode::IF_LT(label) | ByteCode::IF_GE(label)
+        | ByteCode::IF_GT(label) | ByteCode::IF_LE(label) => {
+            state.pop_expect(&[VType::Integer])?;
+            return Ok(vec![pc_of(&label.name)?, pc + 1])
+        },
+        ByteCode::IF_CMPLT(label) | ByteCode::IF_CMPGE(label) | ByteCode::IF_CMPGT(label) | ByteCode::IF_CMPLE(label) => {
+            state.pop_expect(&[VType::Integer])?;
+            state.pop_expect(&[VType::Integer])?;
+            return Ok(vec![pc_of(&label.name)?, pc + 1])
+        },
+        ByteCode::IF_CMPEQ(label) | ByteCode::IF_CMPNE(label) => {
+            let t = state.pop_expect(&[VType::Integer, VType::Boolean])?;
+            state.pop_expect(&[t])?;
+            return Ok(vec![pc_of(&label.name)?, pc + 1])
+        },
+        ByteCode::NEW {class, ..} => {
+            if punk_file.find_class(&class.name).is_none() {
+                return Err(ErrorKind::UnknownClass(class.name.clone()))
+            }
+            state.stack.push(VType::Object);
+        },
+        ByteCode::INSTANCEOF {class} | ByteCode::CHECKCAST {class} => {
+            if punk_file.find_class(&class.name).is_none() {
+                return Err(ErrorKind::UnknownClass(class.name.clone()))
+            }
+            let t = state.pop_expect(&[VType::Object, VType::Array])?;
+            state.stack.push(if let ByteCode::INSTANCEOF {..} = ins { VType::Boolean } else { t });
+        },
+        ByteCode::GETFIELD {field, ..} => {
+            state.pop_expect(&[VType::Object])?;
+            state.stack.push(field_type(punk_file, &field.name, false)?);
+        },
+        ByteCode::PUTFIELD {field, ..} => {
+            let t = field_type(punk_file, &field.name, false)?;
+            state.pop_expect(&[t])?;
+            state.pop_expect(&[VType::Object])?;
+        },
+        ByteCode::GETSTATIC {field, ..} => state.stack.push(field_type(punk_file, &field.name, true)?),
+        ByteCode::PUTSTATIC {field, ..} => {
+            let t = field_type(punk_file, &field.name, true)?;
+            state.pop_expect(&[t])?;
+        },
+        // The type of the elements isn't tracked, the machine checks it when storing
+        ByteCode::NEWARRAY(_) => {
+            state.pop_expect(&[VType::Integer])?;
+            state.stack.push(VType::Array);
+        },
+        ByteCode::ALOAD => {
+            state.pop_expect(&[VType::Integer])?;
+            state.pop_expect(&[VType::Array])?;
+            state.stack.push(VType::Unknown);
+        },
+        ByteCode::ASTORE => {
+            state.pop()?;
+            state.pop_expect(&[VType::Integer])?;
+            state.pop_expect(&[VType::Array])?;
+        },
+        ByteCode::ARRAYLENGTH => {
+            state.pop_expect(&[VType::Array])?;
+            state.stack.push(VType::Integer);
+        },
+        ByteCode::METHODCALL {method, ..} | ByteCode::INVOKESTATIC {method} | ByteCode::INVOKEVIRTUAL {method, ..}
+        | ByteCode::INVOKESPECIAL {method} => {
+            // Native and static methods don't receive an object
+            let (desc, is_static, is_abstract) = match (find_method(punk_file, &method.name), natives.find(&method.name)) {
+                (Err(ErrorKind::UnknownMethod(_)), Some(n)) => (n.desc.clone(), true, false),
+                (m, _) => {
+                    let m = m?;
+                    (Descriptor::parse(&m.desc)?, m.is_static, m.is_abstract)
+                }
+            };
+            if method.name.rsplit('/').next() == Some(CLINIT) {
+                return Err(ErrorKind::InvalidCall(format!("{} is only called by the machine", method.name)))
+            }
+            match ins {
+                ByteCode::INVOKESTATIC {..} if !is_static => return Err(ErrorKind::InvalidCall(format!("{} is not static", method.name))),
+                ByteCode::INVOKEVIRTUAL {..} | ByteCode::INVOKESPECIAL {..} if is_static =>
+                    return Err(ErrorKind::InvalidCall(format!("{} is static", method.name))),
+                ByteCode::INVOKESPECIAL {..} if is_abstract => return Err(ErrorKind::InvalidCall(format!("{} is abstract", method.name))),
+                _ => ()
+            }
+            for arg in desc.args.iter().rev() {
+                state.pop_expect(&[VType::from_ret_type(arg)])?;
+            }
+            if !is_static {
+                state.pop_expect(&[VType::Object])?;
+            }
+            if desc.ret != RetType::Void {
+                state.stack.push(VType::from_ret_type(&desc.ret));
+            }
+        },
+    }
+    Ok(vec![pc + 1])
+}
+
+/// Type of the field, if different classes declare a field with the same name and different types it can't be known.
+/// A field named as CLASS/FIELD is the one declared by the class or by the closest parent that declares it,
+/// static fields are always named like that
+fn field_type(punk_file: &PunkFile, field: &str, is_static: bool) -> Result<VType, ErrorKind> {
+    if let Some((class, name)) = field.split_once('/') {
+        let mut cls = match punk_file.find_class(class) {
+            Some(c) => c,
+            None => return Err(ErrorKind::UnknownClass(class.to_string()))
+        };
+        // A chain longer than the number of classes has a cycle
+        for _ in 0..punk_file.all_classes().count() {
+            if let Some(f) = cls.fields.iter().rev().find(|f| f.name == name && f.is_static == is_static) {
+                return Ok(VType::from_ret_type(&RetType::get_type(&f.desc)?))
+            }
+            cls = match punk_file.find_class(&cls.super_cls) {
+                Some(c) => c,
+                None => break
+            };
+        }
+        return Err(ErrorKind::UnknownField(field.to_string()))
+    }
+    if is_static {
+        return Err(ErrorKind::UnknownField(field.to_string()))
+    }
+    let mut types = punk_file.all_classes()
+        .flat_map(|c| c.fields.iter())
+        .filter(|f| f.name == field && !f.is_static)
+        .map(|f| RetType::get_type(&f.desc).map(|t| VType::from_ret_type(&t)));
+    let first = match types.next() {
+        Some(t) => t?,
+        None => return Err(ErrorKind::UnknownField(field.to_string()))
+    };
+    for t in types {
+        if t? != first {
+            return Ok(VType::Unknown)
+        }
+    }
+    Ok(first)
+}
+
+/// Method named CLASS/METHOD, searching it also in the parents of the class
+fn find_method<'a>(punk_file: &'a PunkFile, name: &str) -> Result<&'a Code, ErrorKind> {
+    let v: Vec<&str> = name.split('/').collect();
+    if v.len() != 2 {
+        return Err(ErrorKind::UnknownMethod(name.to_string()))
+    }
+    let mut cls_name = v[0];
+    // Interfaces only declare abstract methods, they're searched after all the parents
+    let mut interfaces = Vec::new();
+    while let Some(cls) = punk_file.find_class(cls_name) {
+        if let Some(m) = cls.find_method(v[1]) {
+            return Ok(m)
+        }
+        interfaces.extend(cls.interfaces.iter().map(|i| i.as_str()));
+        cls_name = cls.super_cls.as_str();
+    }
+    let mut visited = Vec::new();
+    while let Some(interface) = interfaces.pop() {
+        if visited.contains(&interface) {
+            continue
+        }
+        visited.push(interface);
+        if let Some(cls) = punk_file.find_class(interface) {
+            if let Some(m) = cls.find_method(v[1]) {
+                return Ok(m)
+            }
+            interfaces.extend(cls.interfaces.iter().map(|i| i.as_str()));
+        }
+    }
+    Err(ErrorKind::UnknownMethod(name.to_string()))
+}
+
+impl VType {
+    fn from_ret_type(t: &RetType) -> VType {
+        match t {
+            RetType::Integer => VType::Integer,
+            RetType::Long => VType::Long,
+            RetType::Double => VType::Double,
+            RetType::String => VType::String,
+            RetType::Boolean => VType::Boolean,
+            RetType::Object => VType::Object,
+            RetType::Array(_) => VType::Array,
+            RetType::Void => VType::Unknown,
+        }
+    }
+
+    fn from_type(t: &Type) -> VType {
+        match t {
+            Type::Integer(_) => VType::Integer,
+            Type::Long(_) => VType::Long,
+            Type::Double(_) => VType::Double,
+            Type::String(_) => VType::String,
+            Type::Boolean(_) => VType::Boolean,
+            Type::Object(_) => VType::Object,
+            Type::Array(_) => VType::Array,
+            Type::Null => VType::Null,
+        }
+    }
+
+    /// Whether a value of this type can be used where `expected` is needed
+    fn matches(&self, expected: &VType) -> bool {
+        match (self, expected) {
+            (VType::Unknown, _) | (_, VType::Unknown) => true,
+            (VType::Null, VType::Object) | (VType::Null, VType::Array) => true,
+            (a, b) => a == b,
+        }
+    }
+
+    fn merge(&self, other: &VType) -> Option<VType> {
+        match (self, other) {
+            (a, b) if a == b => Some(*a),
+            (VType::Unknown, _) | (_, VType::Unknown) => Some(VType::Unknown),
+            (VType::Null, VType::Object) | (VType::Object, VType::Null) => Some(VType::Object),
+            (VType::Null, VType::Array) | (VType::Array, VType::Null) => Some(VType::Array),
+            _ => None,
+        }
+    }
+}
+
+impl State {
+    fn pop(&mut self) -> Result<VType, ErrorKind> {
+        match self.stack.pop() {
+            Some(t) => Ok(t),
+            None => Err(ErrorKind::StackUnderflow)
+        }
+    }
+
+    /// Pops a value that has to match one of the expected types, returns the type that matched
+    fn pop_expect(&mut self, expected: &[VType]) -> Result<VType, ErrorKind> {
+        let t = self.pop()?;
+        match expected.iter().find(|e| t.matches(e)) {
+            Some(e) => Ok(if t == VType::Unknown { *e } else { t }),
+            None => Err(ErrorKind::TypeMismatch(format!("expected {:?} but found {:?}", expected, t)))
+        }
+    }
+
+    /// State of an instruction reachable from two paths. The stacks must agree, local variables that
+    /// aren't set in both paths can't be read anymore
+    fn merge(&self, other: &State) -> Result<State, ErrorKind> {
+        if self.stack.len() != other.stack.len() {
+            return Err(ErrorKind::InconsistentStack(format!("depth {} and {}", self.stack.len(), other.stack.len())))
+        }
+        let mut stack = Vec::new();
+        for (a, b) in self.stack.iter().zip(other.stack.iter()) {
+            match a.merge(b) {
+                Some(t) => stack.push(t),
+                None => return Err(ErrorKind::InconsistentStack(format!("{:?} and {:?}", a, b)))
+            }
+        }
+        let n_locals = std::cmp::max(self.locals.len(), other.locals.len());
+        let local = |locals: &Vec<Local>, i: usize| *locals.get(i).unwrap_or(&Local::Unset);
+        let locals = (0..n_locals).map(|i| match (local(&self.locals, i), local(&other.locals, i)) {
+            (Local::Set(a), Local::Set(b)) => Local::Set(a.merge(&b).unwrap_or(VType::Unknown)),
+            _ => Local::Unset,
+        }).collect();
+        Ok(State { stack, locals })
+    }
+}
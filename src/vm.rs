@@ -0,0 +1,563 @@
+use crate::punkfile::punk_file::PunkFile;
+use crate::memory::objects::{Objects, Object, Array, HeapStats};
+use crate::memory::vpk_stack::{StackVM, Frame, Type};
+use crate::memory::instructions::Instructions;
+use crate::isa::bytecode::ByteCode;
+use crate::isa::bytecode;
+use crate::error::{ErrorKind, VmError, StackTraceEntry, Stage};
+use crate::trace::Tracer;
+use crate::profile::Profiler;
+use crate::native::{Natives, HostFn};
+use crate::exception;
+
+/// Name under which the code of the main section is registered in `Instructions`
+pub const MAIN_METHOD: &str = "AppMain";
+
+/// Name of the static method that initializes a class, the machine calls it the first time the class is used
+pub const CLINIT: &str = "<clinit>";
+
+/// State of the machine after executing an instruction
+#[derive(Debug, Clone, PartialEq)]
+pub enum State {
+    /// There are still instructions to execute
+    Running,
+    /// The main code returned, holds the value left at the top of its operator stack
+    Finished(Option<Type>),
+}
+
+/// The Virtual Punk Machine. Owns the loaded program and all the runtime data structures
+pub struct Vm {
+    punk_file: PunkFile,
+    objects: Objects,
+    stack: StackVM,
+    instructions: Instructions,
+    natives: Natives,
+    /// Frame of the method being executed, it's not kept inside `stack` while running
+    frame: Frame,
+    /// Values of the static fields of each class, by position in `PunkFile::classes`
+    statics: Vec<Vec<Type>>,
+    /// Whether each class has been initialized, or its initialization has started
+    initialized: Vec<bool>,
+    state: State,
+    /// Once an instruction fails the machine can't continue, every later step reports the same error
+    error: Option<VmError>,
+    /// Whether the operands of the code have been resolved
+    linked: bool,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+}
+
+impl Vm {
+    /// Loads and links the program, it fails if the code of any method is malformed or refers to something
+    /// that doesn't exist
+    pub fn new(punk_file: PunkFile) -> Result<Vm, VmError> {
+        let mut vm = Vm::load(punk_file)?;
+        vm.link()?;
+        Ok(vm)
+    }
+
+    /// Loads the program without linking it, so native methods can be registered before the calls to them
+    /// are resolved. It's linked before executing the first instruction. The built-in exceptions that the
+    /// program doesn't define are added to its classes
+    pub fn load(mut punk_file: PunkFile) -> Result<Vm, VmError> {
+        let mut instructions: Instructions = Default::default();
+        punk_file.add_builtins();
+
+        // Initialize code structure
+        for cls in punk_file.classes.iter() {
+            for m in cls.methods.iter() {
+                instructions.new_method(&cls.this, m)?
+            }
+        }
+        instructions.new_method("", &punk_file.main.as_method(MAIN_METHOD))?;
+        instructions.build_vtables(&punk_file)?;
+        instructions.check_interfaces(&punk_file)?;
+        instructions.build_layouts(&punk_file);
+        let natives = Natives::standard();
+
+        // Set the pc to the first instruction of the main code
+        let main = instructions.get_method_index(MAIN_METHOD).unwrap();
+        let mut stack = StackVM::new();
+        stack.new_pc(instructions.get_method(main).pc);
+        let mut frame = Frame::new();
+        frame.set_method(main);
+        let statics = instructions.statics.iter().map(|s| s.values.clone()).collect();
+        let initialized = vec![false; punk_file.classes.len()];
+
+        Ok(Vm {
+            punk_file,
+            objects: Objects::new(),
+            stack,
+            instructions,
+            natives,
+            frame,
+            statics,
+            initialized,
+            state: State::Running,
+            error: None,
+            linked: false,
+            tracer: None,
+            profiler: None,
+        })
+    }
+
+    /// Resolves the labels, methods and classes named by the code, including the native methods registered
+    /// until now. Linking again after registering more natives is allowed
+    pub fn link(&mut self) -> Result<(), VmError> {
+        self.instructions.new_natives(&self.natives);
+        self.instructions.link(&self.punk_file)?;
+        self.linked = true;
+        Ok(())
+    }
+
+    /// Makes the Rust function callable as the method `name`, in the form CLASS/METHOD, with the descriptor `desc`.
+    /// The arguments and the returned value are converted with `FromPunk` and `IntoPunk` and have to match
+    /// the descriptor. Methods with code take precedence over natives with the same name.
+    /// To call host methods from the program it has to be created with `Vm::load`
+    pub fn register_native<F, Args>(&mut self, name: &str, desc: &str, func: F) -> Result<(), VmError>
+        where F: HostFn<Args>
+    {
+        if let Err(kind) = self.natives.register_host(name, desc, func) {
+            return Err(VmError::register(kind, name.to_string()))
+        }
+        if self.linked {
+            self.instructions.new_natives(&self.natives);
+        }
+        Ok(())
+    }
+
+    /// Executes instructions until the main code returns, handing back the value left on its stack
+    pub fn run(&mut self) -> Result<Option<Type>, VmError> {
+        loop {
+            if let State::Finished(value) = self.step()? {
+                return Ok(value)
+            }
+        }
+    }
+
+    /// Executes a single instruction
+    pub fn step(&mut self) -> Result<State, VmError> {
+        if let Some(err) = &self.error {
+            return Err(err.clone())
+        }
+        if self.state != State::Running {
+            return Ok(self.state.clone())
+        }
+        if !self.linked {
+            if let Err(err) = self.link() {
+                self.error = Some(err.clone());
+                return Err(err)
+            }
+        }
+
+        let pc = self.stack.get_pc();
+        let method = self.frame.get_method();
+        let depth = self.stack.depth();
+        match self.initialize(pc) {
+            Ok(false) => (),
+            Ok(true) => {
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.enter(self.frame.get_method());
+                }
+                return Ok(State::Running)
+            },
+            Err(kind) => {
+                let err = self.error(kind, pc);
+                self.error = Some(err.clone());
+                return Err(err)
+            }
+        }
+        let before = match self.tracer {
+            Some(_) => self.frame.operands().to_vec(),
+            None => Vec::new()
+        };
+        match self.execute(pc) {
+            Ok(state) => {
+                // The instruction exists, it has just been executed
+                let ins = self.instructions.get_ins(pc).unwrap();
+                if let Some(tracer) = &mut self.tracer {
+                    let name = &self.instructions.get_method(method).name;
+                    tracer.instruction(pc, name, ins, &before, self.frame.operands());
+                }
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.instruction(ins.mnemonic());
+                    if state != State::Running {
+                        profiler.finish();
+                    }
+                    else if self.stack.depth() > depth {
+                        profiler.enter(self.frame.get_method());
+                    }
+                    else if self.stack.depth() < depth {
+                        profiler.exit();
+                    }
+                }
+                Ok(state)
+            },
+            Err(kind) => {
+                let kind = match self.raise(kind, pc) {
+                    Ok(unwound) => {
+                        let ins = self.instructions.get_ins(pc).unwrap();
+                        if let Some(tracer) = &mut self.tracer {
+                            let name = &self.instructions.get_method(method).name;
+                            tracer.instruction(pc, name, ins, &before, self.frame.operands());
+                        }
+                        if let Some(profiler) = &mut self.profiler {
+                            profiler.instruction(ins.mnemonic());
+                            for _ in 0..unwound {
+                                profiler.exit();
+                            }
+                        }
+                        return Ok(State::Running)
+                    },
+                    Err(kind) => kind
+                };
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.finish();
+                }
+                let err = self.error(kind, pc);
+                self.error = Some(err.clone());
+                Err(err)
+            }
+        }
+    }
+
+    /// Searches, from the innermost frame, the handler that catches the exception of the failed instruction at pc.
+    /// If there's one, the frames above it are discarded and it continues with the exception as the only value
+    /// of its operator stack, returns how many frames have been discarded. Otherwise returns the error
+    /// to report: errors that can't be caught as they are, and thrown exceptions as `UncaughtException`
+    fn raise(&mut self, kind: ErrorKind, pc: usize) -> Result<usize, ErrorKind> {
+        let class = match &kind {
+            ErrorKind::Thrown(handle) => self.punk_file.classes[self.objects.get(*handle)?.class].this.clone(),
+            kind => match exception::class_of(kind) {
+                Some(class) => class.to_string(),
+                None => return Err(kind.clone())
+            }
+        };
+        let mut frames = vec![(self.frame.get_method(), pc)];
+        frames.extend(self.stack.call_stack().iter().map(|(frame, frame_pc)| (frame.get_method(), *frame_pc)));
+        let found = frames.iter().enumerate().find_map(|(unwound, (method, frame_pc))| {
+            self.instructions.find_handler(&self.punk_file, *method, *frame_pc, &class).map(|handler| (unwound, handler))
+        });
+        let (unwound, handler) = match (found, &kind) {
+            (Some(found), _) => found,
+            (None, ErrorKind::Thrown(handle)) => {
+                let obj = self.objects.get(*handle)?;
+                let message = match self.instructions.layouts[obj.class].slot("message").map(|s| &obj.fields[s]) {
+                    Some(Type::String(msg)) => msg.clone(),
+                    _ => String::new()
+                };
+                return Err(ErrorKind::UncaughtException(class, message))
+            },
+            (None, _) => return Err(kind)
+        };
+
+        let handle = match kind {
+            ErrorKind::Thrown(handle) => handle,
+            kind => {
+                // The class is a built-in one or one defined by the program with the same name
+                let index = match self.punk_file.classes.iter().position(|c| c.this == class) {
+                    Some(i) => i,
+                    None => return Err(ErrorKind::UnknownClass(class))
+                };
+                let layout = &self.instructions.layouts[index];
+                let mut fields = layout.values.clone();
+                if let Some(slot) = layout.slot("message") {
+                    fields[slot] = Type::String(kind.to_string());
+                }
+                if self.objects.should_collect() {
+                    self.objects.collect(roots(&self.stack, &self.frame, &self.statics));
+                }
+                let handle = self.objects.new_object(index, fields);
+                if let Some(tracer) = &mut self.tracer {
+                    tracer.allocation(handle, &class);
+                }
+                handle
+            }
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.throw(handle, &class, handler);
+        }
+        for _ in 0..unwound {
+            self.frame = self.stack.pop_frame()?;
+            self.stack.ret_pc()?;
+        }
+        self.frame.clear_operands();
+        self.frame.push(Type::Object(handle));
+        self.stack.new_pc(handler);
+        Ok(unwound)
+    }
+
+    /// Initializes the class that the instruction at pc uses if it hasn't been initialized yet: `NEW`, the static
+    /// fields and the static methods. Its parents are initialized first, from the root of the chain, and each
+    /// class is only initialized once. Returns true if it called the `<clinit>` of one of them, the instruction
+    /// runs again when it returns. A class whose `<clinit>` throws an exception stays initialized
+    fn initialize(&mut self, pc: usize) -> Result<bool, ErrorKind> {
+        let class = match self.instructions.get_ins(pc)? {
+            ByteCode::NEW {class, ..} => class.index,
+            ByteCode::GETSTATIC {field, ..} | ByteCode::PUTSTATIC {field, ..} => field.index,
+            ByteCode::INVOKESTATIC {method} | ByteCode::METHODCALL {method, ..} => {
+                let m = self.instructions.get_method(method.index);
+                match m.class_index {
+                    Some(c) if m.is_static => c,
+                    _ => return Ok(false)
+                }
+            },
+            _ => return Ok(false)
+        };
+        if self.initialized[class] {
+            return Ok(false)
+        }
+        let mut chain = vec![class];
+        while let Some(parent) = self.punk_file.classes.iter().position(|c| c.this == self.punk_file.classes[chain[chain.len() - 1]].super_cls) {
+            chain.push(parent);
+        }
+        for c in chain.into_iter().rev() {
+            if self.initialized[c] {
+                continue
+            }
+            self.initialized[c] = true;
+            let clinit = format!("{}/{}", self.punk_file.classes[c].this, CLINIT);
+            if let Some(method) = self.instructions.get_method_index(&clinit) {
+                bytecode::invoke(&mut self.stack, &mut self.frame, &self.instructions, method, None, Vec::new())?;
+                return Ok(true)
+            }
+        }
+        Ok(false)
+    }
+
+    fn execute(&mut self, pc: usize) -> Result<State, ErrorKind> {
+        let ins = self.instructions.get_ins(pc)?;
+        let stack = &mut self.stack;
+        let frame = &mut self.frame;
+        let overflow = self.punk_file.overflow;
+
+        match ins {
+            ByteCode::MUL => bytecode::mul(frame, overflow)?,
+            ByteCode::DIV => bytecode::div(frame, overflow)?,
+            ByteCode::SUB => bytecode::sub(frame, overflow)?,
+            ByteCode::REM => bytecode::rem(frame)?,
+            ByteCode::NEG => bytecode::neg(frame, overflow)?,
+            ByteCode::AND | ByteCode::OR | ByteCode::XOR | ByteCode::SHL | ByteCode::SHR => bytecode::bitwise(frame, ins)?,
+            ByteCode::LADD | ByteCode::LSUB | ByteCode::LMUL | ByteCode::LDIV | ByteCode::LREM | ByteCode::LNEG =>
+                bytecode::long_arith(frame, ins, overflow)?,
+            ByteCode::DADD | ByteCode::DSUB | ByteCode::DMUL | ByteCode::DDIV | ByteCode::DREM | ByteCode::DNEG =>
+                bytecode::double_arith(frame, ins)?,
+            ByteCode::LCMP => bytecode::lcmp(frame)?,
+            ByteCode::DCMPL => bytecode::dcmp(frame, -1)?,
+            ByteCode::DCMPG => bytecode::dcmp(frame, 1)?,
+            ByteCode::I2L | ByteCode::I2D | ByteCode::L2I | ByteCode::L2D | ByteCode::D2I | ByteCode::D2L =>
+                bytecode::convert(frame, ins)?,
+            ByteCode::POP => bytecode::pop(frame)?,
+            ByteCode::IADD => bytecode::iadd(frame, overflow)?,
+            ByteCode::SADD => bytecode::sadd(frame)?,
+            ByteCode::NULL => bytecode::null(frame)?,
+            ByteCode::PRINT => bytecode::print(frame)?,
+            ByteCode::THROW => bytecode::throw(frame, &self.punk_file, &self.objects)?,
+            ByteCode::RETURN => {
+                let method = self.instructions.get_method(frame.get_method());
+                let initializer = method.class_index.is_some() && method.name.rsplit('/').next() == Some(CLINIT);
+                if bytecode::ret(stack, frame)? {
+                    // If there's no more frames the execution of the program should be done
+                    let value = frame.try_pop();
+                    self.state = State::Finished(value);
+                    return Ok(self.state.clone())
+                }
+                if initializer {
+                    // The instruction that needed the class runs again, now that it's initialized
+                    stack.new_pc(stack.get_pc() - 1);
+                }
+            },
+            ByteCode::NEW {class, constructor} => {
+                let cls = &self.punk_file.classes[class.index];
+                let fields = self.instructions.layouts[class.index].values.clone();
+
+                // Create object
+                if self.objects.should_collect() {
+                    self.objects.collect(roots(stack, frame, &self.statics));
+                }
+                let handle = bytecode::new(&mut self.objects, frame, class.index, fields)?;
+                if let Some(tracer) = &mut self.tracer {
+                    tracer.allocation(handle, &cls.this);
+                }
+
+                // The reference stays on the stack of the caller, the constructor gets it as its receiver
+                bytecode::invoke(stack, frame, &self.instructions, *constructor, Some(Type::Object(handle)), Vec::new())?;
+            },
+            ByteCode::GETSTATIC {field, slot} => bytecode::getstatic(frame, &self.statics[field.index], *slot)?,
+            ByteCode::PUTSTATIC {field, slot} => bytecode::putstatic(frame, &mut self.statics[field.index], *slot)?,
+            ByteCode::INSTANCEOF {class} => bytecode::instanceof(frame, &self.instructions, &self.objects, class.index)?,
+            ByteCode::CHECKCAST {class} => bytecode::checkcast(frame, &self.punk_file, &self.instructions, &self.objects, class.index)?,
+            ByteCode::GOTO(label) => bytecode::goto(stack, label.index)?,
+            ByteCode::LOAD(var) => bytecode::load(frame, *var)?,
+            ByteCode::STORE(var) => bytecode::store(frame, *var)?,
+            ByteCode::INC(var, delta) => bytecode::inc(frame, *var, *delta, overflow)?,
+            ByteCode::CONST(cst) => bytecode::cnst(frame, cst)?,
+            ByteCode::IF_EQ(label) => branch(stack, bytecode::if_eq(frame)?, label.index),
+            ByteCode::IF_NE(label) => branch(stack, bytecode::if_zero(frame, |x| x != 0)?, label.index),
+            ByteCode::IF_LT(label) => branch(stack, bytecode::if_zero(frame, |x| x < 0)?, label.index),
+            ByteCode::IF_GE(label) => branch(stack, bytecode::if_zero(frame, |x| x >= 0)?, label.index),
+            ByteCode::IF_GT(label) => branch(stack, bytecode::if_zero(frame, |x| x > 0)?, label.index),
+            ByteCode::IF_LE(label) => branch(stack, bytecode::if_zero(frame, |x| x <= 0)?, label.index),
+            ByteCode::IF_CMPLT(label) => branch(stack, bytecode::if_cmplt(frame)?, label.index),
+            ByteCode::IF_CMPEQ(label) => branch(stack, bytecode::if_cmpeq(frame)?, label.index),
+            ByteCode::IF_CMPNE(label) => branch(stack, bytecode::if_cmpne(frame)?, label.index),
+            ByteCode::IF_CMPGE(label) => branch(stack, bytecode::if_cmp(frame, |x1, x2| x1 >= x2)?, label.index),
+            ByteCode::IF_CMPGT(label) => branch(stack, bytecode::if_cmp(frame, |x1, x2| x1 > x2)?, label.index),
+            ByteCode::IF_CMPLE(label) => branch(stack, bytecode::if_cmp(frame, |x1, x2| x1 <= x2)?, label.index),
+            ByteCode::GETFIELD {field, slot} => bytecode::getfield(frame, &self.objects, &self.instructions, field, *slot)?,
+            ByteCode::PUTFIELD {field, slot} => {
+                let write = match (&self.tracer, frame.operands()) {
+                    (Some(_), [.., Type::Object(handle), value]) => Some((*handle, value.clone())),
+                    _ => None
+                };
+                bytecode::putfield(frame, &mut self.objects, &self.instructions, field, *slot)?;
+                if let (Some(tracer), Some((handle, value))) = (&mut self.tracer, write) {
+                    tracer.field_write(handle, &field.name, value);
+                }
+            },
+            ByteCode::METHODCALL {method, slot} => bytecode::methodcall(stack, frame, &self.objects, &self.instructions, &self.natives, method.index, *slot)?,
+            ByteCode::INVOKESTATIC {method} => bytecode::invokestatic(stack, frame, &self.instructions, &self.natives, method.index)?,
+            ByteCode::INVOKEVIRTUAL {method, slot} => bytecode::invokevirtual(stack, frame, &self.objects, &self.instructions, method.index, *slot)?,
+            ByteCode::INVOKESPECIAL {method} => bytecode::invokespecial(stack, frame, &self.instructions, method.index)?,
+            ByteCode::NEWARRAY(elem) => {
+                if self.objects.should_collect() {
+                    self.objects.collect(roots(stack, frame, &self.statics));
+                }
+                bytecode::newarray(&mut self.objects, frame, elem)?;
+                if let (Some(tracer), Some(Type::Array(handle))) = (&mut self.tracer, frame.operands().last()) {
+                    let length = self.objects.get_array(*handle)?.values.len();
+                    tracer.array_allocation(*handle, elem, length);
+                }
+            },
+            ByteCode::ALOAD => bytecode::aload(frame, &self.objects)?,
+            ByteCode::ASTORE => {
+                let write = match (&self.tracer, frame.operands()) {
+                    (Some(_), [.., Type::Array(handle), Type::Integer(index), value]) => Some((*handle, *index, value.clone())),
+                    _ => None
+                };
+                bytecode::astore(frame, &mut self.objects)?;
+                if let (Some(tracer), Some((handle, index, value))) = (&mut self.tracer, write) {
+                    tracer.element_write(handle, index, value);
+                }
+            },
+            ByteCode::ARRAYLENGTH => bytecode::arraylength(frame, &self.objects)?,
+            ByteCode::LABEL(_) => ()
+        };
+
+        match ins {
+            ByteCode::RETURN | ByteCode::NEW {..} => (),
+            ins if ins.jump().is_some() || ins.call().is_some() => (),
+            _ => {
+                stack.inc_pc();
+            }
+        };
+
+        Ok(State::Running)
+    }
+
+    /// Builds the error for `kind` with the context of the frames that are active at `pc`
+    fn error(&self, kind: ErrorKind, pc: usize) -> VmError {
+        let method_name = |frame: &Frame| self.instructions.get_method(frame.get_method()).name.clone();
+        let mut backtrace = vec![StackTraceEntry { method: method_name(&self.frame), pc }];
+        for (frame, frame_pc) in self.stack.call_stack() {
+            backtrace.push(StackTraceEntry { method: method_name(frame), pc: frame_pc });
+        }
+        VmError {
+            kind,
+            stage: Stage::Run,
+            pc,
+            method: method_name(&self.frame),
+            backtrace,
+        }
+    }
+
+    /// Reports every executed instruction to the tracer, None stops tracing
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /// Counts the instructions executed from now on in the profiler, None stops profiling.
+    /// The methods that are running are entered in the profiler from the outermost
+    pub fn set_profiler(&mut self, mut profiler: Option<Profiler>) {
+        if let Some(p) = &mut profiler {
+            for frame in self.stack.frames() {
+                p.enter(frame.get_method());
+            }
+            p.enter(self.frame.get_method());
+        }
+        self.profiler = profiler;
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Runs the garbage collector, returns how many objects have been freed
+    pub fn gc(&mut self) -> usize {
+        self.objects.collect(roots(&self.stack, &self.frame, &self.statics))
+    }
+
+    /// Sets the number of live objects at which the garbage collector runs on its own
+    pub fn set_gc_threshold(&mut self, threshold: usize) {
+        self.objects.set_threshold(threshold)
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        self.objects.stats()
+    }
+
+    /// Returns the state in which the last executed instruction left the machine
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn punk_file(&self) -> &PunkFile {
+        &self.punk_file
+    }
+
+    /// Position in `Instructions::code` of the next instruction to execute
+    pub fn pc(&self) -> usize {
+        self.stack.get_pc()
+    }
+
+    /// Active frames from the innermost, the one being executed, to the outermost, each one with the pc
+    /// it's executing: the next instruction for the innermost and the pending call for the rest
+    pub fn frames(&self) -> Vec<(&Frame, usize)> {
+        let mut frames = vec![(&self.frame, self.stack.get_pc())];
+        frames.extend(self.stack.call_stack());
+        frames
+    }
+
+    pub fn natives(&self) -> &Natives {
+        &self.natives
+    }
+
+    pub fn object(&self, handle: usize) -> Result<&Object, ErrorKind> {
+        self.objects.get(handle)
+    }
+
+    pub fn array(&self, handle: usize) -> Result<&Array, ErrorKind> {
+        self.objects.get_array(handle)
+    }
+
+    /// Code of every method after being linked
+    pub fn instructions(&self) -> &Instructions {
+        &self.instructions
+    }
+}
+
+/// Values from which the garbage collector starts to search reachable objects:
+/// the local variables and operator stacks of every frame and the static fields
+fn roots<'a>(stack: &'a StackVM, frame: &'a Frame, statics: &'a [Vec<Type>]) -> impl Iterator<Item = &'a Type> {
+    stack.frames().flat_map(|f| f.values()).chain(frame.values()).chain(statics.iter().flatten())
+}
+
+/// Jumps to `label_pc` if the condition holds, otherwise continues with the next instruction
+fn branch(stack: &mut StackVM, condition: bool, label_pc: usize) {
+    if condition {
+        stack.new_pc(label_pc)
+    }
+    else {
+        stack.inc_pc();
+    }
+}
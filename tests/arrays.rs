@@ -0,0 +1,117 @@
+//! Arrays: creating them, reading and writing their elements, their bounds and the array descriptors
+
+extern crate vpm;
+
+mod common;
+
+use common::{int, program, run, text, verify};
+use vpm::memory::vpk_stack::{Type, RetType};
+use vpm::punkfile::descriptor::Descriptor;
+use vpm::punkfile::field::Field;
+use vpm::ErrorKind;
+
+/// Runs the main code with a new array of `len` elements of `elem` in the local variable 0
+fn with_array(elem: &str, len: i32, main: &str) -> Result<Option<Type>, ErrorKind> {
+    run(&program("", &format!("    CONST {}\n    NEWARRAY {}\n    STORE 0\n{}", len, elem, main)))
+}
+
+fn array(elem: RetType) -> RetType {
+    RetType::Array(Box::new(elem))
+}
+
+#[test]
+fn new_arrays_have_the_default_values() {
+    assert_eq!(with_array("I", 3, "    LOAD 0\n    ARRAYLENGTH"), int(3));
+    assert_eq!(with_array("I", 0, "    LOAD 0\n    ARRAYLENGTH"), int(0));
+    assert_eq!(with_array("I", 3, "    LOAD 0\n    CONST 2\n    ALOAD"), int(0));
+    assert_eq!(with_array("L", 1, "    LOAD 0\n    CONST 0\n    ALOAD"), Ok(Some(Type::Long(0))));
+    assert_eq!(with_array("D", 1, "    LOAD 0\n    CONST 0\n    ALOAD"), Ok(Some(Type::Double(0.0))));
+    assert_eq!(with_array("B", 1, "    LOAD 0\n    CONST 0\n    ALOAD"), Ok(Some(Type::Boolean(false))));
+    assert_eq!(with_array("S", 1, "    LOAD 0\n    CONST 0\n    ALOAD"), Ok(Some(Type::Null)));
+    assert_eq!(with_array("[I", 1, "    LOAD 0\n    CONST 0\n    ALOAD"), Ok(Some(Type::Null)));
+}
+
+#[test]
+fn elements_are_stored_and_loaded() {
+    let main = "    LOAD 0\n    CONST 1\n    CONST 7\n    ASTORE\n    LOAD 0\n    CONST 2\n    CONST 5\n    ASTORE\n    LOAD 0\n    CONST 1\n    ALOAD\n    LOAD 0\n    CONST 2\n    ALOAD\n    SUB";
+    assert_eq!(with_array("I", 3, main), int(2));
+    assert_eq!(with_array("S", 2, "    LOAD 0\n    CONST 1\n    CONST last\n    ASTORE\n    LOAD 0\n    CONST 1\n    ALOAD"), text("last"));
+    // Arrays of arrays hold references to the inner arrays
+    let nested = "    LOAD 0\n    CONST 0\n    CONST 4\n    NEWARRAY I\n    ASTORE\n    LOAD 0\n    CONST 0\n    ALOAD\n    ARRAYLENGTH";
+    assert_eq!(with_array("[I", 2, nested), int(4));
+}
+
+#[test]
+fn elements_have_the_type_of_the_array() {
+    assert_eq!(with_array("I", 1, "    LOAD 0\n    CONST 0\n    CONST text\n    ASTORE"),
+               Err(ErrorKind::TypeMismatch("String(\"text\") can't be stored in an array of I".to_string())));
+    assert_eq!(with_array("S", 1, "    LOAD 0\n    CONST 0\n    NULL\n    ASTORE\n    LOAD 0\n    ARRAYLENGTH"), int(1));
+    assert!(matches!(with_array("I", 1, "    LOAD 0\n    CONST 0L\n    ALOAD"), Err(ErrorKind::TypeMismatch(_))));
+    assert!(matches!(run(&program("", "    CONST 1\n    ARRAYLENGTH")), Err(ErrorKind::TypeMismatch(_))));
+    assert_eq!(run(&program("", "    NULL\n    ARRAYLENGTH")), Err(ErrorKind::NullReference));
+}
+
+#[test]
+fn indexes_are_checked() {
+    let load = |i: i32| with_array("I", 3, &format!("    LOAD 0\n    CONST {}\n    ALOAD", i));
+    assert_eq!(load(3), Err(ErrorKind::IndexOutOfBounds(3, 3)));
+    assert_eq!(load(-1), Err(ErrorKind::IndexOutOfBounds(-1, 3)));
+    let store = |i: i32| with_array("I", 3, &format!("    LOAD 0\n    CONST {}\n    CONST 1\n    ASTORE", i));
+    assert_eq!(store(3), Err(ErrorKind::IndexOutOfBounds(3, 3)));
+    assert_eq!(store(-1), Err(ErrorKind::IndexOutOfBounds(-1, 3)));
+    assert_eq!(with_array("I", 0, "    LOAD 0\n    CONST 0\n    ALOAD"), Err(ErrorKind::IndexOutOfBounds(0, 0)));
+}
+
+#[test]
+fn sizes_can_not_be_negative() {
+    assert_eq!(with_array("I", -1, "    LOAD 0"), Err(ErrorKind::NegativeArraySize(-1)));
+    let caught = "
+.catch NegativeArraySizeError start end failed
+    LABEL start
+    CONST -5
+    NEWARRAY S
+    RETURN
+    LABEL end
+    LABEL failed
+    METHODCALL Exception/getMessage";
+    assert_eq!(run(&program("", caught)), text("An array can't have a negative length: -5"));
+}
+
+#[test]
+fn descriptors_name_the_arrays() {
+    let desc = Descriptor::parse("([II[[S)[L").unwrap();
+    assert_eq!(desc.args, [array(RetType::Integer), RetType::Integer, array(array(RetType::String))]);
+    assert_eq!(desc.ret, array(RetType::Long));
+    for invalid in ["([)I", "(I[)I", "()[", "()[V", "([V)I"].iter() {
+        assert!(matches!(Descriptor::parse(invalid), Err(ErrorKind::InvalidDescriptor(_))), "{}", invalid);
+    }
+    assert_eq!(RetType::get_type("[[D"), Ok(array(array(RetType::Double))));
+    assert_eq!(array(array(RetType::Integer)).to_string(), "[[I");
+
+    let sum = ".class Util
+.method static sum ([I)I
+    LOAD 0
+    CONST 0
+    ALOAD
+    LOAD 0
+    CONST 1
+    ALOAD
+    IADD
+    RETURN";
+    let main = "    CONST 2\n    NEWARRAY I\n    STORE 0\n    LOAD 0\n    CONST 1\n    CONST 4\n    ASTORE\n    LOAD 0\n    INVOKESTATIC Util/sum";
+    assert_eq!(verify(&program(sum, main)), Ok(()));
+    assert_eq!(run(&program(sum, main)), int(4));
+}
+
+#[test]
+fn array_fields_start_as_null() {
+    let field = |desc: &str, value: &str| Field { name: "f".to_string(), desc: desc.to_string(), value: value.to_string(), is_static: false };
+    assert_eq!(field("[I", "").get_type(), Type::Null);
+    assert_eq!(field("[S", "text").get_type(), Type::Null);
+    assert_eq!(field("I", "3").get_type(), Type::Integer(3));
+
+    let classes = ".class Bag\n.field items [I\n.method constructor ()V\n    RETURN";
+    assert_eq!(run(&program(classes, "    NEW Bag\n    GETFIELD items")), Ok(Some(Type::Null)));
+    let main = "    NEW Bag\n    STORE 0\n    LOAD 0\n    CONST 2\n    NEWARRAY I\n    PUTFIELD items\n    LOAD 0\n    GETFIELD items\n    ARRAYLENGTH";
+    assert_eq!(run(&program(classes, main)), int(2));
+}
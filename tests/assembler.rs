@@ -0,0 +1,131 @@
+//! The assembly syntax: directives, comments, quoted literals and the position of the errors
+
+extern crate vpm;
+
+mod common;
+
+use common::assemble;
+use vpm::punkfile::assembler::{self, ParseError};
+use vpm::punkfile::code::ExceptionHandler;
+use vpm::punkfile::punk_file::Overflow;
+
+fn error(source: &str) -> (usize, usize, String) {
+    match assembler::assemble(source) {
+        Ok(_) => panic!("The program is valid"),
+        Err(ParseError { line, column, message }) => (line, column, message)
+    }
+}
+
+fn at(line: usize, column: usize, message: &str) -> (usize, usize, String) {
+    (line, column, message.to_string())
+}
+
+/// The code of the main section as it's written back
+fn main_code(source: &str) -> Vec<String> {
+    assemble(source).main.code.iter().map(|ins| ins.to_string()).collect()
+}
+
+#[test]
+fn directives_describe_the_classes() {
+    let punk_file = assemble("
+.overflow trap
+.interface Named
+.method abstract name ()S
+
+.class Animal
+.implements Named
+.field legs I 4
+.field static count I 0
+.field name S \"Mr. \\\"Rex\\\"\"
+.method constructor ()V
+    RETURN
+.method name ()S
+    LOAD 0
+    GETFIELD name
+    RETURN
+.method static create ()O
+    NEW Animal
+    RETURN
+
+.class Dog
+.super Animal
+
+.main
+.catch Exception start end end
+    LABEL start
+    LABEL end
+    RETURN
+");
+    assert_eq!(punk_file.overflow, Overflow::Trap);
+    let named = &punk_file.classes[0];
+    assert_eq!((named.this.as_str(), named.is_interface), ("Named", true));
+    assert!(named.methods[0].is_abstract && named.methods[0].code.is_empty());
+
+    let animal = &punk_file.classes[1];
+    assert_eq!((animal.this.as_str(), animal.super_cls.as_str(), animal.is_interface), ("Animal", "", false));
+    assert_eq!(animal.interfaces, ["Named"]);
+    let fields: Vec<(&str, &str, &str, bool)> = animal.fields.iter().map(|f| (f.name.as_str(), f.desc.as_str(), f.value.as_str(), f.is_static)).collect();
+    assert_eq!(fields, [("legs", "I", "4", false), ("count", "I", "0", true), ("name", "S", "Mr. \"Rex\"", false)]);
+    let methods: Vec<(&str, &str, bool, usize)> = animal.methods.iter().map(|m| (m.name.as_str(), m.desc.as_str(), m.is_static, m.code.len())).collect();
+    assert_eq!(methods, [("constructor", "()V", false, 1), ("name", "()S", false, 3), ("create", "()O", true, 2)]);
+
+    assert_eq!(punk_file.classes[2].super_cls, "Animal");
+    let handler = ExceptionHandler { class: "Exception".to_string(), start: "start".to_string(), end: "end".to_string(), handler: "end".to_string() };
+    assert_eq!(punk_file.main.exceptions, [handler]);
+    assert_eq!(punk_file.main.code.len(), 3);
+    // Without the directive the arithmetic wraps
+    assert_eq!(assemble(".main\n    RETURN\n").overflow, Overflow::Wrap);
+}
+
+#[test]
+fn comments_run_until_the_end_of_the_line() {
+    let source = "; A program
+.main ; starts here
+
+    CONST 1 ; one
+    ; nothing
+    CONST \"a; b\" ; the semicolon is part of the literal
+    CONST \"say \\\"hi\\\"\\tnow\"
+    RETURN";
+    assert_eq!(main_code(source), ["CONST 1", "CONST a; b", "CONST say \"hi\"\tnow", "RETURN"]);
+    assert_eq!(main_code(".main\n    CONST   two  words  \n    RETURN\n"), ["CONST two  words", "RETURN"]);
+}
+
+#[test]
+fn errors_point_to_the_directive() {
+    assert_eq!(error(".main\n.klass A\n"), at(2, 1, "Unknown directive .klass"));
+    assert_eq!(error("  .super A\n.main\n"), at(1, 3, ".super must be inside a .class"));
+    assert_eq!(error(".class A B\n.main\n"), at(1, 1, ".class expects 1 operands"));
+    assert_eq!(error(".main\n.main\n"), at(2, 1, "The .main section is defined more than once"));
+    assert_eq!(error(".overflow wrap\n.overflow trap\n.main\n"), at(2, 1, "The .overflow directive is used more than once"));
+    assert_eq!(error(".main\n.catch Exception a b\n"), at(2, 1, ".catch expects 4 operands"));
+    assert_eq!(error(".class A\n.catch Exception a b c\n"), at(2, 1, ".catch must be inside a .method or the .main section"));
+    assert_eq!(error(".class A\n.field x\n.main\n"), at(2, 1, "Expected .field [static] <name> <descriptor> [value]"));
+    assert_eq!(error(".class A\n.method f\n.main\n"), at(2, 1, ".method expects 2 operands"));
+    assert_eq!(error(".class A\n\n"), at(3, 1, "The .main section is missing"));
+}
+
+#[test]
+fn errors_point_to_the_operand() {
+    assert_eq!(error(".overflow  saturate\n.main\n"), at(1, 12, "saturate is not wrap or trap"));
+    assert_eq!(error(".class A\n.field static  x Q 1\n.main\n"), at(2, 18, "Q is not a valid field descriptor"));
+    assert_eq!(error(".class A\n.method f (I\n.main\n"), at(2, 11, "The descriptor (I is not valid"));
+    assert_eq!(error(".main\n    CONST \"abc\n"), at(2, 11, "Missing the closing quote"));
+    assert_eq!(error(".main\n    CONST \"a\" b\n"), at(2, 11, "Unexpected text after the closing quote"));
+    assert_eq!(error(".main\n    CONST \"a\\q\"\n"), at(2, 11, "Invalid escape sequence"));
+    // Columns count characters, not bytes
+    assert_eq!(error(".class Ñu\n.field año Q\n.main\n"), at(2, 12, "Q is not a valid field descriptor"));
+}
+
+#[test]
+fn errors_point_to_the_instruction() {
+    assert_eq!(error("    RETURN\n.main\n"), at(1, 5, "Instructions must be inside a .method or the .main section"));
+    assert_eq!(error(".interface A\n.method abstract f ()V\n    RETURN\n.main\n"), at(3, 5, "Instructions must be inside a .method or the .main section"));
+    let (line, column, _) = error(".main\n    CONST 1\n\tFOO 2\n");
+    assert_eq!((line, column), (3, 2));
+    let (line, column, _) = error(".main\n  ; comment\n    GOTO\n");
+    assert_eq!((line, column), (3, 5));
+    // The errors print as line:column: message
+    let err = assembler::assemble(".main\n.klass A\n").unwrap_err();
+    assert_eq!(err.to_string(), "2:1: Unknown directive .klass");
+}
@@ -0,0 +1,112 @@
+//! Reading and writing binary punk files
+
+extern crate vpm;
+
+use vpm::punkfile::{assembler, binary};
+use vpm::punkfile::binary::FormatError;
+use vpm::memory::vpk_stack::Type;
+use vpm::Vm;
+
+const PROGRAM: &str = "
+.overflow trap
+.class Animal
+.field name S Unknown
+.method constructor ()V
+    RETURN
+.method speak ()S
+    LOAD 0
+    GETFIELD name
+    CONST \" says hi\"
+    SADD
+    RETURN
+
+.class Dog
+.super Animal
+.method constructor ()V
+    LOAD 0
+    CONST Rex
+    PUTFIELD name
+    RETURN
+
+.main
+.catch Exception start end failed
+    LABEL start
+    NEW Dog
+    METHODCALL Animal/speak
+    RETURN
+    LABEL end
+    LABEL failed
+    CONST 1
+    RETURN
+";
+
+fn bytes() -> Vec<u8> {
+    binary::write(&assembler::assemble(PROGRAM).unwrap()).unwrap()
+}
+
+fn error(offset: usize, message: &str) -> Result<(), FormatError> {
+    Err(FormatError { offset, message: message.to_string() })
+}
+
+#[test]
+fn files_are_read_back_as_written() {
+    let bytes = bytes();
+    let read = binary::read(&bytes).unwrap();
+    assert_eq!(binary::write(&read).unwrap(), bytes);
+    assert!(!read.overflow.wraps());
+    assert_eq!(read.classes[1].super_cls, "Animal");
+    assert_eq!(read.main.exceptions.len(), 1);
+    assert_eq!(Vm::new(read).unwrap().run().map_err(|err| err.kind), Ok(Some(Type::String("Rex says hi".to_string()))));
+}
+
+#[test]
+fn bad_magic_numbers_are_rejected() {
+    let mut bytes = bytes();
+    bytes[0] = 0;
+    assert_eq!(binary::read(&bytes).map(|_| ()), error(0, "This is not a binary punk file"));
+    assert_eq!(binary::read(b"{\"classes\": []}").map(|_| ()), error(0, "This is not a binary punk file"));
+}
+
+#[test]
+fn other_versions_are_rejected() {
+    let mut bytes = bytes();
+    // A newer minor version may have sections this reader doesn't know
+    bytes[6..8].copy_from_slice(&(binary::VERSION_MINOR + 1).to_be_bytes());
+    let message = format!("Unsupported version {}.{}", binary::VERSION_MAJOR, binary::VERSION_MINOR + 1);
+    assert_eq!(binary::read(&bytes).map(|_| ()), error(4, &message));
+
+    let mut bytes = self::bytes();
+    bytes[4..6].copy_from_slice(&(binary::VERSION_MAJOR + 1).to_be_bytes());
+    let message = format!("Unsupported version {}.{}", binary::VERSION_MAJOR + 1, binary::VERSION_MINOR);
+    assert_eq!(binary::read(&bytes).map(|_| ()), error(4, &message));
+}
+
+#[test]
+fn truncated_files_are_rejected() {
+    let bytes = bytes();
+    // The offset is where the value that doesn't fit starts
+    assert_eq!(binary::read(&bytes[..2]).map(|_| ()), error(0, "Unexpected end of file"));
+    assert_eq!(binary::read(&bytes[..9]).map(|_| ()), error(8, "Unexpected end of file"));
+    for len in [20, bytes.len() / 2, bytes.len() - 1].iter() {
+        let err = binary::read(&bytes[..*len]).unwrap_err();
+        assert_eq!(err.message, "Unexpected end of file");
+        assert!(err.offset <= *len);
+    }
+    let mut longer = bytes;
+    longer.push(0);
+    assert_eq!(binary::read(&longer).map(|_| ()), error(longer.len() - 1, "Unexpected bytes after the main code"));
+}
+
+#[test]
+fn pool_indexes_out_of_range_are_rejected() {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&binary::MAGIC.to_be_bytes());
+    bytes.extend_from_slice(&binary::VERSION_MAJOR.to_be_bytes());
+    bytes.extend_from_slice(&binary::VERSION_MINOR.to_be_bytes());
+    // No flags, an empty pool and one class whose name is the entry 5 of the pool
+    bytes.extend_from_slice(&[0, 0, 0, 1, 0, 1, 0, 5]);
+    assert_eq!(binary::read(&bytes).map(|_| ()), error(14, "Invalid constant pool index 5"));
+    // The entry 0 means "nothing", it can't be used as the name of a class
+    bytes[15] = 0;
+    assert_eq!(binary::read(&bytes).map(|_| ()), error(14, "Invalid constant pool index 0"));
+}
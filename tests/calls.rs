@@ -0,0 +1,76 @@
+//! Static, virtual and special calls
+
+extern crate vpm;
+
+mod common;
+
+use common::{program, text};
+use vpm::memory::vpk_stack::Type;
+use vpm::ErrorKind;
+
+const ANIMALS: &str = "
+.class Animal
+.field name S Unknown
+.method constructor ()V
+    RETURN
+.method speak ()S
+    LOAD 0
+    GETFIELD name
+    RETURN
+.method static twice (S)S
+    LOAD 0
+    LOAD 0
+    SADD
+    RETURN
+
+.class Dog
+.super Animal
+.method constructor ()V
+    LOAD 0
+    INVOKESPECIAL Animal/constructor
+    LOAD 0
+    CONST Rex
+    PUTFIELD name
+    RETURN
+.method speak ()S
+    LOAD 0
+    INVOKESPECIAL Animal/speak
+    CONST \" barks\"
+    SADD
+    RETURN
+";
+
+fn run(main: &str) -> Result<Option<Type>, ErrorKind> {
+    common::run(&program(ANIMALS, main))
+}
+
+#[test]
+fn static_calls_have_no_receiver() {
+    assert_eq!(run("    CONST ab\n    INVOKESTATIC Animal/twice"), text("abab"));
+    assert_eq!(run("    CONST 3\n    CONST -4\n    INVOKESTATIC Math/min"), Ok(Some(Type::Integer(-4))));
+}
+
+#[test]
+fn virtual_calls_use_the_class_of_the_receiver() {
+    assert_eq!(run("    NEW Dog\n    INVOKEVIRTUAL Animal/speak"), text("Rex barks"));
+    assert_eq!(run("    NEW Animal\n    INVOKEVIRTUAL Animal/speak"), text("Unknown"));
+    assert_eq!(run("    NULL\n    INVOKEVIRTUAL Animal/speak"), Err(ErrorKind::NullReference));
+}
+
+#[test]
+fn special_calls_use_the_named_class() {
+    assert_eq!(run("    NEW Dog\n    INVOKESPECIAL Animal/speak"), text("Rex"));
+}
+
+#[test]
+fn methodcall_follows_the_kind_of_method() {
+    assert_eq!(run("    NEW Dog\n    METHODCALL Animal/speak"), text("Rex barks"));
+    assert_eq!(run("    CONST ab\n    METHODCALL Animal/twice"), text("abab"));
+}
+
+#[test]
+fn calls_must_match_the_kind_of_method() {
+    assert!(matches!(run("    NEW Dog\n    INVOKESTATIC Animal/speak"), Err(ErrorKind::InvalidCall(_))));
+    assert!(matches!(run("    NEW Dog\n    CONST ab\n    INVOKEVIRTUAL Animal/twice"), Err(ErrorKind::InvalidCall(_))));
+    assert!(matches!(run("    CONST 1\n    CONST 2\n    INVOKESPECIAL Math/min"), Err(ErrorKind::InvalidCall(_))));
+}
@@ -0,0 +1,84 @@
+//! The options of the command line, each test runs the `vpm` binary on a program written to a temporary file
+
+use std::process::{Command, Output};
+
+/// Runs vpm with the options on the program, `name` keeps apart the files of each test
+fn vpm(name: &str, options: &[&str], source: &str) -> Output {
+    let path = std::env::temp_dir().join(format!("vpm_cli_{}.pasm", name));
+    std::fs::write(&path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_vpm")).args(options).arg(&path).output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    output
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+/// Prints before taking a value from an empty stack
+const UNDERFLOW: &str = ".main\n    CONST 1\n    PRINT\n    POP\n    RETURN\n";
+
+#[test]
+fn verify_rejects_the_program_before_running_it() {
+    let output = vpm("verify_rejects", &["--verify"], UNDERFLOW);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(stderr(&output), "At pc: 2, in AppMain, the program couldn't be loaded: No element on the operator stack\n");
+
+    // Without the option the program runs until the instruction fails
+    let output = vpm("verify_not_asked", &[], UNDERFLOW);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "1\n");
+    assert!(stderr(&output).contains("the execution throw an error: No element on the operator stack"), "{}", stderr(&output));
+}
+
+#[test]
+fn verify_runs_the_programs_it_accepts() {
+    let output = vpm("verify_accepts", &["--verify"], ".main\n    CONST 1\n    PRINT\n    RETURN\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "1\n");
+}
+
+#[test]
+fn verify_is_only_for_running() {
+    let output = vpm("verify_disasm", &["disasm", "--verify"], UNDERFLOW);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("--verify can only be used when running a program\n"));
+}
+
+#[test]
+fn profiles_are_written_when_the_program_ends() {
+    let collapsed = std::env::temp_dir().join("vpm_cli_profile.folded");
+    let option = format!("--profile-collapsed={}", collapsed.display());
+    let output = vpm("profile", &["--profile-sample=2", &option], ".main\n    CONST 1\n    PRINT\n    RETURN\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "1\n");
+    assert!(stderr(&output).starts_with("Executed instructions: 3\n"), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Samples every 2 instructions: 1\n"), "{}", stderr(&output));
+    assert_eq!(std::fs::read_to_string(&collapsed).unwrap(), "AppMain 3\n");
+    std::fs::remove_file(&collapsed).unwrap();
+
+    let output = vpm("profile_interval", &["--profile-sample=0"], ".main\n    RETURN\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("Invalid sampling interval 0\n"));
+}
+
+#[test]
+fn converted_programs_do_the_same() {
+    let natives = concat!(env!("CARGO_MANIFEST_DIR"), "/programs/natives.pasm");
+    let json = std::env::temp_dir().join("vpm_cli_natives.json");
+    let output = Command::new(env!("CARGO_BIN_EXE_vpm")).arg("convert").arg(natives).arg(&json).output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    // The literal " 17 " keeps its spaces, so it's still a string
+    assert!(std::fs::read_to_string(&json).unwrap().contains(r#""CONST \" 17 \"""#));
+
+    let run = |file: &std::path::Path| Command::new(env!("CARGO_BIN_EXE_vpm")).arg(file).output().unwrap();
+    let (original, converted) = (run(std::path::Path::new(natives)), run(&json));
+    std::fs::remove_file(&json).unwrap();
+    assert!(converted.status.success(), "{}", stderr(&converted));
+    assert_eq!(stdout(&converted), stdout(&original));
+}
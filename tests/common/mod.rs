@@ -0,0 +1,292 @@
+//! Helpers shared by the integration tests, each test file uses some of them
+
+#![allow(dead_code)]
+
+use vpm::punkfile::assembler;
+use vpm::punkfile::punk_file::PunkFile;
+use vpm::memory::vpk_stack::Type;
+use vpm::{verifier, Vm, ErrorKind};
+use std::collections::BTreeSet;
+
+/// Uses every instruction of the machine, every kind of class member and every flag of the punk files.
+/// It's well typed and its main code returns 31
+pub const EVERY_OPCODE: &str = "
+.overflow trap
+
+.interface Shape
+.method abstract area ()I
+
+.class Counter
+.field static created I 0
+.method static <clinit> ()V
+    CONST 100
+    PUTSTATIC Counter/created
+    RETURN
+.method static add (I)I
+    GETSTATIC Counter/created
+    LOAD 0
+    IADD
+    PUTSTATIC Counter/created
+    GETSTATIC Counter/created
+    RETURN
+
+.class Square
+.implements Shape
+.field side I 3
+.field label S \"a  square\"
+.field cells [I
+.method constructor ()V
+    CONST 1
+    INVOKESTATIC Counter/add
+    POP
+    RETURN
+.method area ()I
+    LOAD 0
+    GETFIELD side
+    LOAD 0
+    GETFIELD Square/side
+    MUL
+    RETURN
+
+.class Cube
+.super Square
+.field side I 1
+.method constructor ()V
+    LOAD 0
+    INVOKESPECIAL Square/constructor
+    LOAD 0
+    CONST 4
+    PUTFIELD Square/side
+    RETURN
+
+.main
+.catch Exception start end failed
+    CONST 7
+    CONST 2
+    MUL
+    CONST 3
+    DIV
+    CONST 1
+    SUB
+    CONST 2
+    REM
+    NEG
+    CONST 6
+    AND
+    CONST 1
+    OR
+    CONST 2
+    XOR
+    CONST 2
+    SHL
+    CONST 1
+    SHR
+    STORE 0
+    INC 0 1
+    CONST 10L
+    CONST 4L
+    LADD
+    CONST 2L
+    LSUB
+    CONST 3L
+    LMUL
+    CONST 5L
+    LDIV
+    CONST 4L
+    LREM
+    LNEG
+    CONST 0L
+    LCMP
+    LOAD 0
+    IADD
+    STORE 0
+    CONST 1.5
+    CONST 2.50
+    DADD
+    CONST 1e0
+    DSUB
+    CONST 2.0
+    DMUL
+    CONST 4.0
+    DDIV
+    CONST 1.0
+    DREM
+    DNEG
+    CONST 0.0
+    DCMPL
+    CONST 1.0
+    CONST 0.0
+    DCMPG
+    IADD
+    LOAD 0
+    IADD
+    I2L
+    L2I
+    I2D
+    D2I
+    I2L
+    L2D
+    D2L
+    L2I
+    STORE 0
+    CONST 0
+    IF_EQ j1
+    LABEL j1
+    CONST 1
+    IF_NE j2
+    LABEL j2
+    CONST -1
+    IF_LT j3
+    LABEL j3
+    CONST 0
+    IF_GE j4
+    LABEL j4
+    CONST 1
+    IF_GT j5
+    LABEL j5
+    CONST 0
+    IF_LE j6
+    LABEL j6
+    CONST 1
+    CONST 2
+    IF_CMPLT j7
+    LABEL j7
+    CONST true
+    CONST true
+    IF_CMPEQ j8
+    LABEL j8
+    CONST 1
+    CONST 2
+    IF_CMPNE j9
+    LABEL j9
+    CONST 2
+    CONST 2
+    IF_CMPGE j10
+    LABEL j10
+    CONST 3
+    CONST 2
+    IF_CMPGT j11
+    LABEL j11
+    CONST 2
+    CONST 2
+    IF_CMPLE j12
+    LABEL j12
+    GOTO objects
+    LABEL objects
+    NEW Cube
+    STORE 1
+    LOAD 1
+    INSTANCEOF Shape
+    POP
+    LOAD 1
+    CHECKCAST Shape
+    INVOKEVIRTUAL Shape/area
+    LOAD 1
+    METHODCALL Square/area
+    IADD
+    LOAD 1
+    GETFIELD side
+    IADD
+    LOAD 0
+    IADD
+    STORE 0
+    CONST 3
+    NEWARRAY I
+    STORE 2
+    LOAD 2
+    CONST 0
+    CONST 9
+    ASTORE
+    LOAD 2
+    CONST 0
+    ALOAD
+    LOAD 2
+    ARRAYLENGTH
+    IADD
+    LOAD 0
+    IADD
+    STORE 0
+    CONST \"created - total:\"
+    GETSTATIC Counter/created
+    LOAD 0
+    SUB
+    SADD
+    PRINT
+    NULL
+    POP
+    LABEL start
+    NEW Exception
+    THROW
+    LABEL end
+    LABEL failed
+    POP
+    LOAD 0
+    RETURN
+";
+
+/// Classes of the program, without its main code
+pub fn classes(source: &str) -> &str {
+    source.split("\n.main\n").next().unwrap()
+}
+
+/// Program with the classes and a main code that returns what it leaves on the stack
+pub fn program(classes: &str, main: &str) -> String {
+    format!("{}\n.main\n{}\n    RETURN\n", classes, main)
+}
+
+pub fn assemble(source: &str) -> PunkFile {
+    assembler::assemble(source).expect("The program is valid")
+}
+
+/// Loads and links the program
+pub fn load(source: &str) -> Result<Vm, ErrorKind> {
+    Vm::new(assemble(source)).map_err(|err| err.kind)
+}
+
+/// Runs the program, the result is what the main code leaves on the stack
+pub fn run(source: &str) -> Result<Option<Type>, ErrorKind> {
+    load(source)?.run().map_err(|err| err.kind)
+}
+
+/// First error that the verifier finds in the program
+pub fn verify(source: &str) -> Result<(), ErrorKind> {
+    verifier::verify(&assemble(source)).map_err(|errors| errors[0].kind.clone())
+}
+
+pub fn int(i: i32) -> Result<Option<Type>, ErrorKind> {
+    Ok(Some(Type::Integer(i)))
+}
+
+pub fn text(s: &str) -> Result<Option<Type>, ErrorKind> {
+    Ok(Some(Type::String(s.to_string())))
+}
+
+pub fn boolean(b: bool) -> Result<Option<Type>, ErrorKind> {
+    Ok(Some(Type::Boolean(b)))
+}
+
+/// Writes the punk file in the JSON format and reads it back, `name` keeps apart the files of each test
+pub fn json_round_trip(punk_file: &PunkFile, name: &str) -> PunkFile {
+    let path = std::env::temp_dir().join(format!("vpm_{}.json", name));
+    std::fs::write(&path, punk_file.to_json()).unwrap();
+    let read = PunkFile::from_file(path.to_str().unwrap());
+    std::fs::remove_file(&path).unwrap();
+    read.expect("The JSON written can be read")
+}
+
+/// Mnemonics of the instructions that the punk file uses
+pub fn mnemonics(punk_file: &PunkFile) -> BTreeSet<&'static str> {
+    let code = punk_file.classes.iter().flat_map(|c| c.methods.iter()).flat_map(|m| m.code.iter());
+    code.chain(punk_file.main.code.iter()).map(|ins| ins.mnemonic()).collect()
+}
+
+/// Every instruction of the machine
+pub const ALL_MNEMONICS: &[&str] = &[
+    "MUL", "DIV", "SUB", "REM", "NEG", "AND", "OR", "XOR", "SHL", "SHR",
+    "LADD", "LSUB", "LMUL", "LDIV", "LREM", "LNEG", "DADD", "DSUB", "DMUL", "DDIV", "DREM", "DNEG",
+    "LCMP", "DCMPL", "DCMPG", "I2L", "I2D", "L2I", "L2D", "D2I", "D2L",
+    "POP", "IADD", "SADD", "NULL", "PRINT", "RETURN", "THROW", "LABEL", "GOTO", "LOAD", "STORE", "INC", "CONST",
+    "IF_EQ", "IF_NE", "IF_LT", "IF_GE", "IF_GT", "IF_LE", "IF_CMPLT", "IF_CMPEQ", "IF_CMPNE", "IF_CMPGE", "IF_CMPGT", "IF_CMPLE",
+    "NEW", "GETFIELD", "PUTFIELD", "GETSTATIC", "PUTSTATIC", "METHODCALL", "INVOKESTATIC", "INVOKEVIRTUAL", "INVOKESPECIAL",
+    "INSTANCEOF", "CHECKCAST", "NEWARRAY", "ALOAD", "ASTORE", "ARRAYLENGTH",
+];
@@ -0,0 +1,114 @@
+//! Debugger sessions, each test feeds the commands and compares what the debugger answers
+
+extern crate vpm;
+
+mod common;
+
+use common::load;
+use vpm::debugger::Debugger;
+
+const PROGRAM: &str = "
+.class Math2
+.method static square (I)I
+    LOAD 0
+    LOAD 0
+    MUL
+    RETURN
+.method static twice (I)I
+    LOAD 0
+    INVOKESTATIC Math2/square
+    LABEL done
+    CONST 2
+    MUL
+    RETURN
+.main
+    CONST 3
+    INVOKESTATIC Math2/twice
+    STORE 0
+    LOAD 0
+    RETURN
+";
+
+/// Answers of the debugger to each command, without the prompt nor the greeting. The first one is
+/// where the program starts
+fn session(commands: &[&str]) -> Vec<String> {
+    let mut output = Vec::new();
+    let input = commands.join("\n");
+    Debugger::new(load(PROGRAM).unwrap()).run(input.as_bytes(), &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let output = output.strip_prefix("Type help to see the commands\n").unwrap();
+    output.split("(vpm) ").map(|answer| answer.trim_end().to_string()).filter(|answer| !answer.is_empty()).collect()
+}
+
+#[test]
+fn breakpoints_on_methods_labels_and_pcs() {
+    let answers = session(&["break Math2/square", "b Math2/twice:done", "break AppMain:3", "breakpoints"]);
+    assert_eq!(answers, [
+        "AppMain pc 14 (+0): CONST 3",
+        "Breakpoint 0 at Math2/square pc 0 (+0)",
+        "Breakpoint 1 at Math2/twice pc 6 (+2)",
+        "Breakpoint 2 at AppMain pc 17 (+3)",
+        "0: Math2/square at Math2/square pc 0 (+0)\n1: Math2/twice:done at Math2/twice pc 6 (+2)\n2: AppMain:3 at AppMain pc 17 (+3)",
+    ]);
+    let answers = session(&["break Nope/x", "break AppMain:99", "break AppMain:nolabel", "delete 5", "breakpoints"]);
+    assert_eq!(&answers[1..], [
+        "There's no method Nope/x",
+        "AppMain only has 5 instructions, 99 is out of it",
+        "There's no label nolabel in AppMain",
+        "delete expects the number of a breakpoint",
+        "There are no breakpoints",
+    ]);
+}
+
+#[test]
+fn continue_stops_at_each_breakpoint() {
+    let answers = session(&["break Math2/twice:done", "break AppMain:3", "c", "continue", "continue", "continue"]);
+    assert_eq!(&answers[3..], [
+        "Breakpoint 0 (Math2/twice:done)\nMath2/twice pc 6 (+2): LABEL done",
+        "Breakpoint 1 (AppMain:3)\nAppMain pc 17 (+3): LOAD 0",
+        "The program has finished returning Integer(18)",
+        "The program has finished",
+    ]);
+    // A deleted breakpoint doesn't stop anymore
+    let answers = session(&["break Math2/square", "delete 0", "continue"]);
+    assert_eq!(&answers[2..], ["Deleted breakpoint 0 (Math2/square)", "The program has finished returning Integer(18)"]);
+}
+
+#[test]
+fn step_enters_the_calls_and_next_runs_them() {
+    let answers = session(&["step", "step", "step", "where"]);
+    assert_eq!(&answers[1..], [
+        "AppMain pc 15 (+1): INVOKESTATIC Math2/twice",
+        "Math2/twice pc 4 (+0): LOAD 0",
+        "Math2/twice pc 5 (+1): INVOKESTATIC Math2/square",
+        "Math2/twice pc 5 (+1): INVOKESTATIC Math2/square",
+    ]);
+    let answers = session(&["next", "next", "next", "next", "next"]);
+    assert_eq!(&answers[1..], [
+        "AppMain pc 15 (+1): INVOKESTATIC Math2/twice",
+        "AppMain pc 16 (+2): STORE 0",
+        "AppMain pc 17 (+3): LOAD 0",
+        "AppMain pc 18 (+4): RETURN",
+        "The program has finished returning Integer(18)",
+    ]);
+}
+
+#[test]
+fn finish_returns_to_the_caller() {
+    let answers = session(&["break Math2/square", "continue", "frames", "locals", "stack", "finish", "stack", "finish", "finish"]);
+    assert_eq!(&answers[2..], [
+        "Breakpoint 0 (Math2/square)\nMath2/square pc 0 (+0): LOAD 0",
+        "#0 Math2/square pc 0 (+0)\n    locals: [Integer(3)]\n    stack: []\n#1 Math2/twice pc 5 (+1)\n    locals: [Integer(3)]\n    stack: []\n#2 AppMain pc 15 (+1)\n    locals: []\n    stack: []",
+        "0: Integer(3)",
+        "The operator stack is empty",
+        "Math2/twice pc 6 (+2): LABEL done",
+        "top -> Integer(9)",
+        "AppMain pc 16 (+2): STORE 0",
+        "The program has finished returning Integer(18)",
+    ]);
+}
+
+#[test]
+fn unknown_commands_are_reported() {
+    assert_eq!(&session(&["foo", "quit", "step"])[1..], ["Unknown command foo, type help to see the commands"]);
+}
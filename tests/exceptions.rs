@@ -0,0 +1,162 @@
+//! Exceptions thrown by `THROW` and by the failing instructions, caught by the exception tables
+
+extern crate vpm;
+
+mod common;
+
+use common::{load, run, text};
+use vpm::memory::vpk_stack::Type;
+use vpm::ErrorKind;
+
+const DIVIDER: &str = "
+.class NegativeError
+.super Exception
+
+.class Divider
+.method constructor ()V
+    RETURN
+.method divide (II)I
+    LOAD 1
+    IF_LT negative
+    LOAD 1
+    LOAD 2
+    DIV
+    RETURN
+    LABEL negative
+    NEW NegativeError
+    STORE 3
+    LOAD 3
+    CONST negative dividend
+    PUTFIELD message
+    LOAD 3
+    THROW
+";
+
+/// Calls `Divider/divide` with the operands inside a range caught by the handlers, which leave the message
+/// of the exception on the stack
+fn divide(x: i32, y: i32, catches: &[&str]) -> Result<Option<Type>, ErrorKind> {
+    let handlers: Vec<String> = catches.iter().map(|c| format!(".catch {} start end failed", c)).collect();
+    run(&format!("{}
+.main
+{}
+    NEW Divider
+    STORE 0
+    LABEL start
+    LOAD 0
+    CONST {}
+    CONST {}
+    METHODCALL Divider/divide
+    LABEL end
+    RETURN
+    LABEL failed
+    METHODCALL Exception/getMessage
+    RETURN
+", DIVIDER, handlers.join("\n"), x, y))
+}
+
+#[test]
+fn no_exception() {
+    assert_eq!(divide(10, 2, &["Exception"]), Ok(Some(Type::Integer(5))));
+}
+
+#[test]
+fn thrown_exception_unwinds_to_the_caller() {
+    assert_eq!(divide(-10, 2, &["NegativeError"]), text("negative dividend"));
+}
+
+#[test]
+fn handlers_catch_subclasses() {
+    assert_eq!(divide(-10, 2, &["Exception"]), text("negative dividend"));
+    assert_eq!(divide(-10, 2, &["ArithmeticError", "Exception"]), text("negative dividend"));
+}
+
+#[test]
+fn machine_errors_are_exceptions() {
+    assert_eq!(divide(10, 0, &["ArithmeticError"]), text("Arithmetic error, division by zero"));
+    assert_eq!(divide(10, 0, &["NegativeError", "Exception"]), text("Arithmetic error, division by zero"));
+}
+
+#[test]
+fn uncaught_exceptions() {
+    assert_eq!(divide(-10, 2, &["ArithmeticError"]),
+               Err(ErrorKind::UncaughtException("NegativeError".to_string(), "negative dividend".to_string())));
+    // Errors of the machine that aren't caught are reported as they are
+    assert_eq!(divide(10, 0, &["NegativeError"]), Err(ErrorKind::ArithmeticError("division by zero".to_string())));
+}
+
+#[test]
+fn the_end_of_the_range_is_not_covered() {
+    let source = "
+.main
+.catch Exception start end failed
+    LABEL start
+    CONST 1
+    LABEL end
+    CONST 0
+    DIV
+    RETURN
+    LABEL failed
+    RETURN
+";
+    assert!(matches!(run(source), Err(ErrorKind::ArithmeticError(_))));
+}
+
+#[test]
+fn handler_keeps_the_locals_of_its_frame() {
+    let source = "
+.main
+.catch NullReferenceError start end failed
+    CONST 7
+    STORE 0
+    LABEL start
+    NULL
+    GETFIELD message
+    LABEL end
+    RETURN
+    LABEL failed
+    POP
+    LOAD 0
+    RETURN
+";
+    assert_eq!(run(source), Ok(Some(Type::Integer(7))));
+}
+
+#[test]
+fn only_exceptions_can_be_thrown() {
+    let source = "
+.class Box
+.method constructor ()V
+    RETURN
+.main
+    NEW Box
+    THROW
+    RETURN
+";
+    assert!(matches!(run(source), Err(ErrorKind::TypeMismatch(_))));
+    assert_eq!(run(".main\n    NULL\n    THROW\n    RETURN\n"), Err(ErrorKind::NullReference));
+}
+
+#[test]
+fn exceptions_inherit_the_fields_of_every_parent() {
+    let source = "
+.class AppError
+.super Exception
+.class ConfigError
+.super AppError
+.main
+    NEW ConfigError
+    STORE 0
+    LOAD 0
+    CONST missing key
+    PUTFIELD message
+    LOAD 0
+    THROW
+    RETURN
+";
+    assert_eq!(run(source), Err(ErrorKind::UncaughtException("ConfigError".to_string(), "missing key".to_string())));
+}
+
+#[test]
+fn unknown_handler_class_fails_to_link() {
+    assert_eq!(load(".main\n.catch Missing a a a\n    LABEL a\n    RETURN\n").err(), Some(ErrorKind::UnknownClass("Missing".to_string())));
+}
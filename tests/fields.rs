@@ -0,0 +1,116 @@
+//! Field layouts across the whole chain of parents and fields hidden by a subclass
+
+extern crate vpm;
+
+mod common;
+
+use common::{assemble, load, program, text};
+use vpm::isa::bytecode::ByteCode;
+use vpm::memory::vpk_stack::Type;
+use vpm::debugger::Debugger;
+use vpm::{Vm, ErrorKind};
+
+const VEHICLES: &str = "
+.class Vehicle
+.field wheels I 0
+.field label S vehicle
+.method constructor ()V
+    RETURN
+.method describe ()S
+    LOAD 0
+    GETFIELD Vehicle/label
+    RETURN
+
+.class Car
+.super Vehicle
+.field doors I 4
+.field label S car
+
+.class SportsCar
+.super Car
+.field top I 300
+.method constructor ()V
+    LOAD 0
+    CONST 4
+    PUTFIELD wheels
+    RETURN
+
+.class Boat
+.field label S boat
+.method constructor ()V
+    RETURN
+";
+
+fn run(main: &str) -> Result<Option<Type>, ErrorKind> {
+    common::run(&program(VEHICLES, main))
+}
+
+#[test]
+fn fields_of_every_parent_are_inherited() {
+    assert_eq!(run("    NEW SportsCar\n    GETFIELD wheels"), Ok(Some(Type::Integer(4))));
+    assert_eq!(run("    NEW SportsCar\n    GETFIELD doors"), Ok(Some(Type::Integer(4))));
+    assert_eq!(run("    NEW SportsCar\n    GETFIELD top"), Ok(Some(Type::Integer(300))));
+}
+
+#[test]
+fn subclass_fields_hide_the_ones_of_the_parents() {
+    assert_eq!(run("    NEW SportsCar\n    GETFIELD label"), text("car"));
+    assert_eq!(run("    NEW Vehicle\n    GETFIELD label"), text("vehicle"));
+    // Both fields exist, the parent keeps its own
+    assert_eq!(run("    NEW SportsCar\n    GETFIELD Vehicle/label"), text("vehicle"));
+    assert_eq!(run("    NEW SportsCar\n    METHODCALL Vehicle/describe"), text("vehicle"));
+    assert_eq!(run("    NEW Car\n    STORE 0\n    LOAD 0\n    CONST sedan\n    PUTFIELD label\n    LOAD 0\n    GETFIELD Vehicle/label"), text("vehicle"));
+    assert_eq!(run("    NEW Car\n    STORE 0\n    LOAD 0\n    CONST old\n    PUTFIELD Vehicle/label\n    LOAD 0\n    METHODCALL Vehicle/describe"), text("old"));
+}
+
+#[test]
+fn one_instruction_reads_the_field_of_every_class() {
+    // The same GETFIELD finds label in a different slot for each class, and again after another class
+    let labels = ".class Labels\n.method static of (O)S\n    LOAD 0\n    GETFIELD label\n    RETURN";
+    let main = "    NEW SportsCar\n    INVOKESTATIC Labels/of\n    NEW Boat\n    INVOKESTATIC Labels/of\n    SADD
+    NEW Vehicle\n    INVOKESTATIC Labels/of\n    SADD\n    NEW SportsCar\n    INVOKESTATIC Labels/of\n    SADD";
+    assert_eq!(common::run(&program(&format!("{}\n{}", VEHICLES, labels), main)), text("carboatvehiclecar"));
+
+    // Linking again keeps the cache of each instruction
+    let mut vm = load(&program(&format!("{}\n{}", VEHICLES, labels), main)).unwrap();
+    let caches = |vm: &Vm| -> Vec<usize> {
+        vm.instructions().code.iter().filter_map(|ins| match ins {
+            ByteCode::GETFIELD {field, slot} | ByteCode::PUTFIELD {field, slot} if !field.name.contains('/') => Some(*slot),
+            _ => None
+        }).collect()
+    };
+    let before = caches(&vm);
+    vm.link().unwrap();
+    assert_eq!(caches(&vm), before);
+    assert_eq!(vm.run().map_err(|err| err.kind), text("carboatvehiclecar"));
+}
+
+#[test]
+fn qualified_names_use_the_closest_declaration() {
+    assert_eq!(run("    NEW SportsCar\n    GETFIELD SportsCar/label"), text("car"));
+    assert_eq!(run("    NEW SportsCar\n    GETFIELD Car/wheels"), Ok(Some(Type::Integer(4))));
+}
+
+#[test]
+fn objects_without_the_field() {
+    assert!(matches!(run("    NEW Boat\n    GETFIELD Vehicle/label"), Err(ErrorKind::UnknownField(_))));
+    assert!(matches!(run("    NEW Vehicle\n    GETFIELD doors"), Err(ErrorKind::UnknownField(_))));
+    assert!(matches!(run("    NEW Vehicle\n    GETFIELD Car/doors"), Err(ErrorKind::UnknownField(_))));
+}
+
+#[test]
+fn qualified_names_are_linked() {
+    let load = |main: &str| load(&program(VEHICLES, main)).err();
+    assert_eq!(load("    NEW Car\n    GETFIELD Truck/label"), Some(ErrorKind::UnknownClass("Truck".to_string())));
+    assert_eq!(load("    NEW Car\n    GETFIELD Vehicle/doors"), Some(ErrorKind::UnknownField("Vehicle/doors".to_string())));
+}
+
+#[test]
+fn debugger_shows_the_hidden_fields_with_their_class() {
+    let punk_file = assemble(&program(VEHICLES, "    NEW Car"));
+    let mut output = Vec::new();
+    Debugger::new(Vm::new(punk_file).unwrap()).run("continue\nobject 0\n".as_bytes(), &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let expected = "Object 0 of class Car\n    wheels: Integer(0)\n    Vehicle/label: String(\"vehicle\")\n    doors: Integer(4)\n    label: String(\"car\")";
+    assert!(output.contains(expected), "{}", output);
+}
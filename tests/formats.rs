@@ -0,0 +1,74 @@
+//! Round trips of a program that uses every instruction through the binary and the JSON punk files and
+//! through the disassembler
+
+extern crate vpm;
+
+mod common;
+
+use common::{assemble, json_round_trip, mnemonics, EVERY_OPCODE, ALL_MNEMONICS};
+use vpm::punkfile::{assembler, binary};
+use vpm::isa::bytecode::ByteCode;
+use vpm::punkfile::punk_file::PunkFile;
+use vpm::memory::vpk_stack::Type;
+use vpm::{disassembler, Vm};
+
+fn code(punk_file: &PunkFile) -> Vec<String> {
+    let methods = punk_file.classes.iter().flat_map(|c| c.methods.iter()).flat_map(|m| m.code.iter());
+    methods.chain(punk_file.main.code.iter()).map(|ins| ins.to_string()).collect()
+}
+
+fn result(punk_file: PunkFile) -> Option<Type> {
+    Vm::new(punk_file).and_then(|mut vm| vm.run()).expect("The program runs")
+}
+
+#[test]
+fn the_program_uses_every_instruction() {
+    let all = ALL_MNEMONICS.iter().copied().collect();
+    assert_eq!(mnemonics(&assemble(EVERY_OPCODE)), all);
+}
+
+#[test]
+fn binary_files_keep_everything() {
+    let punk_file = assemble(EVERY_OPCODE);
+    let bytes = binary::write(&punk_file).unwrap();
+    let read = binary::read(&bytes).unwrap();
+    assert_eq!(binary::write(&read).unwrap(), bytes);
+    assert_eq!(read.to_json(), punk_file.to_json());
+    // Numbers are kept in the form they were written
+    assert_eq!(code(&read), code(&punk_file));
+    assert_eq!(result(read), Some(Type::Integer(31)));
+}
+
+#[test]
+fn json_files_keep_everything() {
+    let punk_file = assemble(EVERY_OPCODE);
+    let json = json_round_trip(&punk_file, "every_opcode");
+    assert_eq!(json.to_json(), punk_file.to_json());
+    assert_eq!(binary::write(&json).unwrap(), binary::write(&punk_file).unwrap());
+    assert_eq!(result(json), Some(Type::Integer(31)));
+}
+
+#[test]
+fn disassembled_programs_assemble_again() {
+    let punk_file = assemble(EVERY_OPCODE);
+    let text = disassembler::disassemble(&Vm::new(assemble(EVERY_OPCODE)).unwrap());
+    let again = assemble(&text);
+    assert_eq!(again.to_json(), punk_file.to_json());
+    assert_eq!(code(&again), code(&punk_file));
+    // The comments of the loaded program are the same, so the text is too
+    assert_eq!(disassembler::disassemble(&Vm::new(assemble(&text)).unwrap()), text);
+    assert_eq!(result(again), Some(Type::Integer(31)));
+}
+
+#[test]
+fn json_files_keep_the_spaces_of_the_literals() {
+    let literals = [" 17 ", "a  b", "tab\there", "\"quoted\"", "semi; colon", "17"];
+    let main: String = literals.iter().map(|l| format!("    CONST {}\n    POP\n", assembler::quote(l))).collect();
+    let punk_file = assemble(&format!(".main\n{}    RETURN\n", main));
+    let json = json_round_trip(&punk_file, "literals");
+    let read: Vec<String> = json.main.code.iter().filter_map(|ins| match ins {
+        ByteCode::CONST(cst) => Some(cst.clone()),
+        _ => None
+    }).collect();
+    assert_eq!(read, literals);
+}
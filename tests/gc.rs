@@ -0,0 +1,198 @@
+//! The garbage collector: what survives a collection, what is freed and when it runs on its own
+
+extern crate vpm;
+
+mod common;
+
+use common::{load, program};
+use vpm::memory::objects::HeapStats;
+use vpm::Vm;
+
+const NODES: &str = "
+.class Node
+.field next O
+.method constructor ()V
+    RETURN
+
+.class Cache
+.field static value O
+";
+
+/// Runs the program until it's about to execute the label of the method
+fn run_to(vm: &mut Vm, method: &str, label: &str) {
+    let instructions = vm.instructions();
+    let method = instructions.get_method(instructions.get_method_index(method).unwrap());
+    let pc = method.labels[label];
+    while vm.pc() != pc {
+        vm.step().unwrap();
+    }
+}
+
+fn stats(live: usize, capacity: usize, allocated: usize, freed: usize, collections: usize) -> HeapStats {
+    HeapStats { live, capacity, allocated, freed, collections }
+}
+
+#[test]
+fn unreachable_cycles_are_freed() {
+    let main = "
+    NEW Node
+    STORE 0
+    NEW Node
+    STORE 1
+    LOAD 0
+    LOAD 1
+    PUTFIELD next
+    LOAD 1
+    LOAD 0
+    PUTFIELD next
+    LABEL linked
+    NULL
+    STORE 0
+    LABEL half
+    NULL
+    STORE 1
+    LABEL dropped";
+    let mut vm = load(&program(NODES, main)).unwrap();
+    run_to(&mut vm, "AppMain", "linked");
+    assert_eq!(vm.heap_stats(), stats(2, 2, 2, 0, 0));
+    assert_eq!(vm.gc(), 0);
+    assert_eq!(vm.heap_stats(), stats(2, 2, 2, 0, 1));
+    // The local variable 1 still reaches both through the cycle
+    run_to(&mut vm, "AppMain", "half");
+    assert_eq!(vm.gc(), 0);
+    run_to(&mut vm, "AppMain", "dropped");
+    assert_eq!(vm.gc(), 2);
+    assert_eq!(vm.heap_stats(), stats(0, 2, 2, 2, 3));
+}
+
+#[test]
+fn operand_stacks_are_roots() {
+    let main = "    NEW Node\n    NEW Node\n    POP\n    CONST 1\n    NEWARRAY I\n    LABEL check\n    NEW Node\n    LABEL reused";
+    let mut vm = load(&program(NODES, main)).unwrap();
+    run_to(&mut vm, "AppMain", "check");
+    assert_eq!(vm.heap_stats(), stats(3, 3, 3, 0, 0));
+    assert_eq!(vm.gc(), 1);
+    assert_eq!(vm.heap_stats(), stats(2, 3, 3, 1, 1));
+    // The freed handle is reused
+    run_to(&mut vm, "AppMain", "reused");
+    assert_eq!(vm.heap_stats(), stats(3, 3, 4, 1, 1));
+}
+
+#[test]
+fn frames_of_the_callers_are_roots() {
+    let classes = format!("{}
+.class Work
+.method static outer ()I
+    NEW Node
+    STORE 0
+    NEW Node
+    CONST 1
+    INVOKESTATIC Work/inner
+    POP
+    POP
+    CONST 0
+    RETURN
+.method static inner (I)I
+    NEW Node
+    POP
+    NEW Node
+    STORE 1
+    LABEL check
+    CONST 0
+    RETURN", NODES);
+    let mut vm = load(&program(&classes, "    NEW Node\n    INVOKESTATIC Work/outer")).unwrap();
+    run_to(&mut vm, "Work/inner", "check");
+    assert_eq!(vm.heap_stats().live, 5);
+    // Only the object popped in inner is garbage, the others are in the stacks and locals of the frames
+    assert_eq!(vm.gc(), 1);
+    assert_eq!(vm.heap_stats().live, 4);
+}
+
+#[test]
+fn statics_arrays_and_fields_keep_objects_alive() {
+    let main = "
+    NEW Node
+    PUTSTATIC Cache/value
+    CONST 2
+    NEWARRAY O
+    STORE 0
+    LOAD 0
+    CONST 1
+    NEW Node
+    ASTORE
+    NEW Node
+    STORE 1
+    LOAD 1
+    NEW Node
+    PUTFIELD next
+    NEW Node
+    POP
+    LABEL check
+    NULL
+    STORE 0
+    LABEL dropped";
+    let mut vm = load(&program(NODES, main)).unwrap();
+    run_to(&mut vm, "AppMain", "check");
+    assert_eq!(vm.heap_stats().live, 6);
+    assert_eq!(vm.gc(), 1);
+    // The array and the node in it go together
+    run_to(&mut vm, "AppMain", "dropped");
+    assert_eq!(vm.gc(), 2);
+    assert_eq!(vm.heap_stats(), stats(3, 6, 6, 3, 2));
+}
+
+#[test]
+fn the_threshold_starts_collections() {
+    let main = "
+    CONST 0
+    STORE 0
+    LABEL loop
+    NEW Node
+    POP
+    INC 0 1
+    LOAD 0
+    CONST 100
+    IF_CMPLT loop";
+    let mut vm = load(&program(NODES, main)).unwrap();
+    vm.set_gc_threshold(10);
+    vm.run().unwrap();
+    let stats = vm.heap_stats();
+    assert_eq!(stats.allocated, 100);
+    // Every collection starts with 10 live objects and frees all of them
+    assert_eq!(stats.collections, 9);
+    assert_eq!(stats.freed, 90);
+    assert_eq!((stats.live, stats.capacity), (10, 10));
+
+    // Without reaching the threshold nothing is collected
+    let mut vm = load(&program(NODES, main)).unwrap();
+    vm.set_gc_threshold(1000);
+    vm.run().unwrap();
+    assert_eq!(vm.heap_stats(), self::stats(100, 100, 100, 0, 0));
+}
+
+#[test]
+fn the_threshold_grows_with_the_live_objects() {
+    // Every node is kept in the list that starts in the local variable 1
+    let main = "
+    CONST 0
+    STORE 0
+    NULL
+    STORE 1
+    LABEL loop
+    NEW Node
+    STORE 2
+    LOAD 2
+    LOAD 1
+    PUTFIELD next
+    LOAD 2
+    STORE 1
+    INC 0 1
+    LOAD 0
+    CONST 20
+    IF_CMPLT loop";
+    let mut vm = load(&program(NODES, main)).unwrap();
+    vm.set_gc_threshold(4);
+    vm.run().unwrap();
+    // Collections at 4, 8 and 16 live objects, none of them frees anything
+    assert_eq!(vm.heap_stats(), stats(20, 20, 20, 0, 3));
+}
@@ -0,0 +1,152 @@
+//! Integer comparisons and arithmetic, each program leaves its result at the top of the stack of the main code
+
+extern crate vpm;
+
+mod common;
+
+use common::program;
+use vpm::memory::vpk_stack::Type;
+use vpm::ErrorKind;
+
+fn run(code: &str) -> Result<Option<Type>, ErrorKind> {
+    run_with(code, "wrap")
+}
+
+fn run_with(code: &str, overflow: &str) -> Result<Option<Type>, ErrorKind> {
+    common::run(&program(&format!(".overflow {}", overflow), code))
+}
+
+fn int(code: &str) -> i32 {
+    match run(code) {
+        Ok(Some(Type::Integer(x))) => x,
+        other => panic!("Expected an integer but got {:?}", other)
+    }
+}
+
+/// Pushes 1 if the jump is taken and 0 otherwise
+fn jumps(operands: &str, ins: &str) -> bool {
+    let code = format!("{}\n    {} yes\n    CONST 0\n    GOTO end\n    LABEL yes\n    CONST 1\n    LABEL end", operands, ins);
+    int(&code) == 1
+}
+
+#[test]
+fn compare_with_zero() {
+    let cases = [
+        ("IF_NE", [false, true, true]),
+        ("IF_LT", [false, true, false]),
+        ("IF_GE", [true, false, true]),
+        ("IF_GT", [false, false, true]),
+        ("IF_LE", [true, true, false]),
+    ];
+    for (ins, expected) in cases.iter() {
+        for (value, jump) in ["0", "-3", "5"].iter().zip(expected.iter()) {
+            assert_eq!(jumps(&format!("    CONST {}", value), ins), *jump, "{} with {}", ins, value);
+        }
+    }
+}
+
+#[test]
+fn compare_two_integers() {
+    let cases = [
+        ("IF_CMPNE", [false, true, true]),
+        ("IF_CMPGE", [true, false, true]),
+        ("IF_CMPGT", [false, false, true]),
+        ("IF_CMPLE", [true, true, false]),
+    ];
+    for (ins, expected) in cases.iter() {
+        for ((v1, v2), jump) in [("4", "4"), ("-1", "2"), ("7", "3")].iter().zip(expected.iter()) {
+            assert_eq!(jumps(&format!("    CONST {}\n    CONST {}", v1, v2), ins), *jump, "{} with {} {}", ins, v1, v2);
+        }
+    }
+}
+
+#[test]
+fn compare_booleans_for_inequality() {
+    assert!(jumps("    CONST true\n    CONST false", "IF_CMPNE"));
+    assert!(!jumps("    CONST true\n    CONST true", "IF_CMPNE"));
+}
+
+#[test]
+fn remainder_keeps_the_sign_of_the_dividend() {
+    assert_eq!(int("    CONST 17\n    CONST 5\n    REM"), 2);
+    assert_eq!(int("    CONST -17\n    CONST 5\n    REM"), -2);
+    assert_eq!(int("    CONST 17\n    CONST -5\n    REM"), 2);
+    assert_eq!(run("    CONST 17\n    CONST 0\n    REM"), Err(ErrorKind::ArithmeticError("division by zero".to_string())));
+}
+
+#[test]
+fn negation() {
+    assert_eq!(int("    CONST 8\n    NEG"), -8);
+    assert_eq!(int("    CONST -8\n    NEG"), 8);
+}
+
+#[test]
+fn bitwise_operations() {
+    assert_eq!(int("    CONST 12\n    CONST 10\n    AND"), 8);
+    assert_eq!(int("    CONST 12\n    CONST 10\n    OR"), 14);
+    assert_eq!(int("    CONST 12\n    CONST 10\n    XOR"), 6);
+    assert_eq!(int("    CONST 3\n    CONST 4\n    SHL"), 48);
+    assert_eq!(int("    CONST -16\n    CONST 2\n    SHR"), -4);
+    assert_eq!(int("    CONST 1\n    CONST 33\n    SHL"), 2);
+}
+
+#[test]
+fn increment_a_local_variable() {
+    assert_eq!(int("    CONST 10\n    STORE 0\n    INC 0 5\n    INC 0 -2\n    LOAD 0"), 13);
+    assert!(matches!(run("    CONST hi\n    STORE 0\n    INC 0 1"), Err(ErrorKind::TypeMismatch(_))));
+}
+
+#[test]
+fn count_down_loop() {
+    let code = "
+    CONST 0
+    STORE 0
+    CONST 5
+    STORE 1
+    LABEL loop
+    LOAD 1
+    IF_LE end
+    LOAD 0
+    LOAD 1
+    IADD
+    STORE 0
+    INC 1 -1
+    GOTO loop
+    LABEL end
+    LOAD 0";
+    assert_eq!(int(code), 15);
+}
+
+#[test]
+fn division_by_zero_is_an_error() {
+    for overflow in ["wrap", "trap"].iter() {
+        assert!(matches!(run_with("    CONST 1\n    CONST 0\n    DIV", overflow), Err(ErrorKind::ArithmeticError(_))));
+    }
+}
+
+#[test]
+fn overflow_wraps() {
+    assert_eq!(int("    CONST 2147483647\n    CONST 1\n    IADD"), i32::MIN);
+    assert_eq!(int("    CONST -2147483648\n    CONST 1\n    SUB"), i32::MAX);
+    assert_eq!(int("    CONST 65536\n    CONST 65536\n    MUL"), 0);
+    assert_eq!(int("    CONST -2147483648\n    CONST -1\n    DIV"), i32::MIN);
+    assert_eq!(int("    CONST -2147483648\n    NEG"), i32::MIN);
+    assert_eq!(int("    CONST 2147483647\n    STORE 0\n    INC 0 1\n    LOAD 0"), i32::MIN);
+    assert_eq!(int("    CONST -2147483648\n    CONST -1\n    REM"), 0);
+}
+
+#[test]
+fn overflow_traps() {
+    let cases = [
+        "    CONST 2147483647\n    CONST 1\n    IADD",
+        "    CONST -2147483648\n    CONST 1\n    SUB",
+        "    CONST 65536\n    CONST 65536\n    MUL",
+        "    CONST -2147483648\n    CONST -1\n    DIV",
+        "    CONST -2147483648\n    NEG",
+        "    CONST 2147483647\n    STORE 0\n    INC 0 1",
+    ];
+    for code in cases.iter() {
+        assert!(matches!(run_with(code, "trap"), Err(ErrorKind::ArithmeticError(_))), "{}", code);
+    }
+    assert_eq!(run_with("    CONST -2147483648\n    CONST -1\n    REM", "trap"), Ok(Some(Type::Integer(0))));
+}
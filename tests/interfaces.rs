@@ -0,0 +1,156 @@
+//! Interfaces, the calls through them and the checks of INSTANCEOF and CHECKCAST
+
+extern crate vpm;
+
+mod common;
+
+use common::{boolean, load, program, text};
+use vpm::isa::bytecode::ByteCode;
+use vpm::memory::vpk_stack::Type;
+use vpm::ErrorKind;
+
+const ANIMALS: &str = "
+.interface Named
+.method abstract name ()S
+
+.interface Pet
+.implements Named
+.method abstract sound (I)S
+
+.class Animal
+.method constructor ()V
+    RETURN
+.method name ()S
+    CONST animal
+    RETURN
+
+.class Dog
+.super Animal
+.implements Pet
+.method sound (I)S
+    CONST woof
+    RETURN
+
+.class Puppy
+.super Dog
+.method name ()S
+    CONST puppy
+    RETURN
+
+.class Rock
+.implements Named
+.method constructor ()V
+    RETURN
+.method name ()S
+    CONST rock
+    RETURN
+";
+
+fn run(main: &str) -> Result<Option<Type>, ErrorKind> {
+    common::run(&program(ANIMALS, main))
+}
+
+#[test]
+fn calls_through_an_interface_run_the_method_of_the_object() {
+    assert_eq!(run("    NEW Dog\n    INVOKEVIRTUAL Named/name"), text("animal"));
+    assert_eq!(run("    NEW Puppy\n    INVOKEVIRTUAL Named/name"), text("puppy"));
+    assert_eq!(run("    NEW Rock\n    METHODCALL Named/name"), text("rock"));
+    assert_eq!(run("    NEW Puppy\n    CONST 1\n    INVOKEVIRTUAL Pet/sound"), text("woof"));
+    // The methods of the super-interfaces are called through the interfaces that extend them
+    assert_eq!(run("    NEW Puppy\n    INVOKEVIRTUAL Pet/name"), text("puppy"));
+}
+
+#[test]
+fn interface_calls_use_the_position_in_the_interface() {
+    let vm = load(&program(ANIMALS, "    NEW Puppy\n    INVOKEVIRTUAL Pet/name\n    NEW Puppy\n    CONST 1\n    INVOKEVIRTUAL Pet/sound")).unwrap();
+    let instructions = vm.instructions();
+    let main = instructions.get_method(instructions.get_method_index("AppMain").unwrap()).pc;
+    let slots: Vec<usize> = instructions.code[main..].iter().filter_map(|ins| match ins {
+        ByteCode::INVOKEVIRTUAL {slot, ..} => Some(*slot),
+        _ => None
+    }).collect();
+    // Named/name is the first method of Named and Pet/sound the first one of Pet
+    assert_eq!(slots, [0, 0]);
+    let puppy = vm.punk_file().classes.iter().position(|c| c.this == "Puppy").unwrap();
+    let interfaces: Vec<&str> = instructions.vtables[puppy].interfaces.iter().map(|(i, _)| vm.punk_file().classes[*i].this.as_str()).collect();
+    assert_eq!(interfaces, ["Pet", "Named"]);
+    // A class that doesn't implement the interface doesn't have the method, even with the same name
+    assert_eq!(run("    NEW Animal\n    INVOKEVIRTUAL Named/name"), Err(ErrorKind::UnknownMethod("Named/name".to_string())));
+}
+
+#[test]
+fn instanceof_checks_the_parents_and_the_interfaces() {
+    assert_eq!(run("    NEW Puppy\n    INSTANCEOF Animal"), boolean(true));
+    assert_eq!(run("    NEW Puppy\n    INSTANCEOF Pet"), boolean(true));
+    assert_eq!(run("    NEW Puppy\n    INSTANCEOF Named"), boolean(true));
+    assert_eq!(run("    NEW Dog\n    INSTANCEOF Puppy"), boolean(false));
+    assert_eq!(run("    NEW Rock\n    INSTANCEOF Pet"), boolean(false));
+    assert_eq!(run("    NEW Rock\n    INSTANCEOF Animal"), boolean(false));
+    assert_eq!(run("    NULL\n    INSTANCEOF Named"), boolean(false));
+    assert_eq!(run("    CONST 2\n    NEWARRAY I\n    INSTANCEOF Named"), boolean(false));
+}
+
+#[test]
+fn supertypes_are_found_when_loaded() {
+    let vm = load(&program(ANIMALS, "")).unwrap();
+    let classes = &vm.punk_file().classes;
+    let index = |name: &str| classes.iter().position(|c| c.this == name).unwrap();
+    let mut supertypes: Vec<&str> = vm.instructions().vtables[index("Puppy")].supertypes.iter().map(|c| classes[*c].this.as_str()).collect();
+    supertypes.sort_unstable();
+    assert_eq!(supertypes, ["Animal", "Dog", "Named", "Pet", "Puppy"]);
+    assert!(vm.instructions().is_instance(index("Rock"), index("Named")));
+    assert!(!vm.instructions().is_instance(index("Rock"), index("Pet")));
+}
+
+#[test]
+fn checkcast_keeps_the_reference() {
+    assert_eq!(run("    NEW Puppy\n    CHECKCAST Pet\n    INVOKEVIRTUAL Named/name"), text("puppy"));
+    assert_eq!(run("    NULL\n    CHECKCAST Pet"), Ok(Some(Type::Null)));
+    assert_eq!(run("    NEW Rock\n    CHECKCAST Pet"), Err(ErrorKind::ClassCast("Rock".to_string(), "Pet".to_string())));
+    assert_eq!(run("    NEW Animal\n    CHECKCAST Dog"), Err(ErrorKind::ClassCast("Animal".to_string(), "Dog".to_string())));
+}
+
+#[test]
+fn failed_casts_can_be_caught() {
+    let main = "
+.catch ClassCastError start end failed
+    LABEL start
+    NEW Rock
+    CHECKCAST Animal
+    RETURN
+    LABEL end
+    LABEL failed
+    METHODCALL Exception/getMessage";
+    assert_eq!(run(main), text("Rock can't be cast to Animal"));
+}
+
+#[test]
+fn classes_have_to_implement_every_method() {
+    let source = |class: &str| program(&format!("{}\n{}", ANIMALS, class), "");
+    assert!(load(&source(".class Cat\n.implements Pet\n.method name ()S\n    CONST cat\n    RETURN\n.method sound (I)S\n    CONST meow\n    RETURN")).is_ok());
+    // The method of the super-interface is missing
+    let missing = ".class Cat\n.implements Pet\n.method sound (I)S\n    CONST meow\n    RETURN";
+    assert_eq!(load(&source(missing)).err(), Some(ErrorKind::UnimplementedMethod("Cat doesn't implement Named/name ()S".to_string())));
+    let wrong_desc = ".class Cat\n.super Animal\n.implements Pet\n.method sound ()S\n    CONST meow\n    RETURN";
+    assert_eq!(load(&source(wrong_desc)).err(), Some(ErrorKind::UnimplementedMethod("Cat doesn't implement Pet/sound (I)S".to_string())));
+}
+
+#[test]
+fn interfaces_are_checked_when_loaded() {
+    let source = |classes: &str| program(&format!("{}\n{}", ANIMALS, classes), "");
+    assert!(matches!(load(&source(".class Cat\n.implements Animal")), Err(ErrorKind::InvalidInterface(_))));
+    assert_eq!(load(&source(".class Cat\n.implements Missing")).err(), Some(ErrorKind::UnknownClass("Missing".to_string())));
+    assert!(matches!(load(&source(".interface Walker\n.field legs I 4")), Err(ErrorKind::InvalidInterface(_))));
+    assert!(matches!(load(&source(".interface Walker\n.super Animal")), Err(ErrorKind::InvalidInterface(_))));
+    assert!(matches!(load(&source(".interface Walker\n.method walk ()V\n    RETURN")), Err(ErrorKind::InvalidInterface(_))));
+    assert!(matches!(load(&source(".class Cat\n.method abstract walk ()V")), Err(ErrorKind::InvalidInterface(_))));
+    assert!(matches!(load(&source(".interface A\n.implements B\n.interface B\n.implements A")), Err(ErrorKind::InheritanceCycle(_))));
+}
+
+#[test]
+fn interfaces_can_not_be_created_nor_called_directly() {
+    let main = |main: &str| program(ANIMALS, main);
+    assert!(matches!(load(&main("    NEW Named")), Err(ErrorKind::InvalidInterface(_))));
+    assert!(matches!(load(&main("    NEW Dog\n    INVOKESPECIAL Named/name")), Err(ErrorKind::InvalidCall(_))));
+    assert_eq!(load(&main("    NEW Dog\n    INSTANCEOF Missing")).err(), Some(ErrorKind::UnknownClass("Missing".to_string())));
+}
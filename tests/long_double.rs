@@ -0,0 +1,116 @@
+//! Longs and doubles, each program leaves its result at the top of the stack of the main code
+
+extern crate vpm;
+
+mod common;
+
+use common::{program, verify};
+use vpm::memory::vpk_stack::Type;
+use vpm::isa::bytecode::parse_const;
+use vpm::ErrorKind;
+
+fn run_with(code: &str, overflow: &str) -> Result<Option<Type>, ErrorKind> {
+    common::run(&program(&format!(".overflow {}", overflow), code))
+}
+
+fn value(code: &str) -> Type {
+    match run_with(code, "wrap") {
+        Ok(Some(v)) => v,
+        other => panic!("Expected a value but got {:?}", other)
+    }
+}
+
+#[test]
+fn literals() {
+    assert_eq!(parse_const("10L"), Type::Long(10));
+    assert_eq!(parse_const("-9000000000L"), Type::Long(-9_000_000_000));
+    assert_eq!(parse_const("2.75"), Type::Double(2.75));
+    assert_eq!(parse_const("-2.5e3"), Type::Double(-2500.0));
+    assert_eq!(parse_const("1e-3"), Type::Double(0.001));
+    assert_eq!(parse_const("10"), Type::Integer(10));
+    for text in ["L", "1.", ".5", "1.5L", "inf", "NaN", "3.14 apples", "version 1.2"].iter() {
+        assert_eq!(parse_const(text), Type::String(text.to_string()), "{}", text);
+    }
+}
+
+#[test]
+fn long_arithmetic() {
+    assert_eq!(value("    CONST 3000000000L\n    CONST 2L\n    LMUL"), Type::Long(6_000_000_000));
+    assert_eq!(value("    CONST 7L\n    CONST 10L\n    LSUB"), Type::Long(-3));
+    assert_eq!(value("    CONST 7L\n    CONST 10L\n    LADD"), Type::Long(17));
+    assert_eq!(value("    CONST -7L\n    CONST 2L\n    LDIV"), Type::Long(-3));
+    assert_eq!(value("    CONST -7L\n    CONST 2L\n    LREM"), Type::Long(-1));
+    assert_eq!(value("    CONST 5L\n    LNEG"), Type::Long(-5));
+    assert!(matches!(run_with("    CONST 5L\n    CONST 0L\n    LDIV", "wrap"), Err(ErrorKind::ArithmeticError(_))));
+    assert!(matches!(run_with("    CONST 5\n    CONST 1L\n    LADD", "wrap"), Err(ErrorKind::TypeMismatch(_))));
+}
+
+#[test]
+fn long_overflow_follows_the_program() {
+    let code = "    CONST 9223372036854775807L\n    CONST 1L\n    LADD";
+    assert_eq!(run_with(code, "wrap"), Ok(Some(Type::Long(i64::MIN))));
+    assert!(matches!(run_with(code, "trap"), Err(ErrorKind::ArithmeticError(_))));
+}
+
+#[test]
+fn double_arithmetic() {
+    assert_eq!(value("    CONST 1.5\n    CONST 2.25\n    DADD"), Type::Double(3.75));
+    assert_eq!(value("    CONST 1.5\n    CONST 2.25\n    DSUB"), Type::Double(-0.75));
+    assert_eq!(value("    CONST 1.5\n    CONST 2.0\n    DMUL"), Type::Double(3.0));
+    assert_eq!(value("    CONST 7.5\n    CONST 2.0\n    DREM"), Type::Double(1.5));
+    assert_eq!(value("    CONST 1.0\n    CONST 0.0\n    DDIV"), Type::Double(f64::INFINITY));
+    assert_eq!(value("    CONST 0.5\n    DNEG"), Type::Double(-0.5));
+}
+
+#[test]
+fn comparisons() {
+    assert_eq!(value("    CONST 1L\n    CONST 2L\n    LCMP"), Type::Integer(-1));
+    assert_eq!(value("    CONST 2L\n    CONST 2L\n    LCMP"), Type::Integer(0));
+    assert_eq!(value("    CONST 3.5\n    CONST 2.0\n    DCMPL"), Type::Integer(1));
+    let nan = "    CONST 0.0\n    CONST 0.0\n    DDIV\n    CONST 1.0";
+    assert_eq!(value(&format!("{}\n    DCMPL", nan)), Type::Integer(-1));
+    assert_eq!(value(&format!("{}\n    DCMPG", nan)), Type::Integer(1));
+}
+
+#[test]
+fn conversions() {
+    assert_eq!(value("    CONST -4\n    I2L"), Type::Long(-4));
+    assert_eq!(value("    CONST 3\n    I2D"), Type::Double(3.0));
+    assert_eq!(value("    CONST 4294967297L\n    L2I"), Type::Integer(1));
+    assert_eq!(value("    CONST 7L\n    L2D"), Type::Double(7.0));
+    assert_eq!(value("    CONST -2.9\n    D2I"), Type::Integer(-2));
+    assert_eq!(value("    CONST 1e20\n    D2I"), Type::Integer(i32::MAX));
+    assert_eq!(value("    CONST 1e20\n    D2L"), Type::Long(100_000_000_000_000_000_000f64 as i64));
+    assert_eq!(value("    CONST 0.0\n    CONST 0.0\n    DDIV\n    D2L"), Type::Long(0));
+}
+
+#[test]
+fn concatenation() {
+    assert_eq!(value("    CONST \"n = \"\n    CONST 12L\n    SADD"), Type::String("n = 12".to_string()));
+    assert_eq!(value("    CONST 2.0\n    CONST \" m\"\n    SADD"), Type::String("2.0 m".to_string()));
+}
+
+#[test]
+fn fields_and_descriptors() {
+    let source = "
+.class Account
+.field balance L 5000000000
+.field rate D 0.25
+.method constructor ()V
+    RETURN
+.method interest ()D
+    LOAD 0
+    GETFIELD balance
+    L2D
+    LOAD 0
+    GETFIELD rate
+    DMUL
+    RETURN
+.main
+    NEW Account
+    METHODCALL Account/interest
+    RETURN
+";
+    assert_eq!(verify(source), Ok(()));
+    assert_eq!(common::run(source), Ok(Some(Type::Double(1_250_000_000.0))));
+}
@@ -0,0 +1,153 @@
+//! The standard native methods and the ones that embedders register
+
+extern crate vpm;
+
+mod common;
+
+use common::{assemble, int, program, run, text};
+use vpm::memory::vpk_stack::Type;
+use vpm::{Vm, ErrorKind, Stage};
+
+fn native_error(message: &str) -> Result<Option<Type>, ErrorKind> {
+    Err(ErrorKind::NativeError(message.to_string()))
+}
+
+/// Runs the main code after registering the host methods
+fn run_with<F: FnOnce(&mut Vm)>(main: &str, register: F) -> Result<Option<Type>, ErrorKind> {
+    let mut vm = Vm::load(assemble(&program("", main))).unwrap();
+    register(&mut vm);
+    vm.run().map_err(|err| err.kind)
+}
+
+#[test]
+fn system_times_are_longs() {
+    match run(&program("", "    INVOKESTATIC System/time")) {
+        Ok(Some(Type::Long(seconds))) => assert!(seconds > 1_700_000_000),
+        other => panic!("Expected a long but found {:?}", other)
+    }
+    match run(&program("", "    INVOKESTATIC System/millis\n    INVOKESTATIC System/millis\n    LSUB\n    LNEG")) {
+        Ok(Some(Type::Long(elapsed))) => assert!(elapsed >= 0),
+        other => panic!("Expected a long but found {:?}", other)
+    }
+}
+
+#[test]
+fn strings_count_characters() {
+    assert_eq!(run(&program("", "    CONST añoñ\n    METHODCALL String/length")), int(4));
+    assert_eq!(run(&program("", "    CONST añoñ\n    CONST 1\n    CONST 3\n    METHODCALL String/substring")), text("ño"));
+    assert_eq!(run(&program("", "    CONST añoñ\n    CONST 3\n    METHODCALL String/charAt")), text("ñ"));
+    assert_eq!(run(&program("", "    CONST \" -17 \"\n    METHODCALL String/parseInt")), int(-17));
+}
+
+#[test]
+fn strings_check_the_ranges() {
+    let substring = |begin: i32, end: i32| run(&program("", &format!("    CONST abc\n    CONST {}\n    CONST {}\n    METHODCALL String/substring", begin, end)));
+    assert_eq!(substring(0, 3), text("abc"));
+    assert_eq!(substring(3, 3), text(""));
+    assert_eq!(substring(-1, 2), native_error("The range -1..2 is out of a string of length 3"));
+    assert_eq!(substring(1, 4), native_error("The range 1..4 is out of a string of length 3"));
+    assert_eq!(substring(2, 1), native_error("The range 2..1 is out of a string of length 3"));
+
+    let char_at = |i: i32| run(&program("", &format!("    CONST abc\n    CONST {}\n    METHODCALL String/charAt", i)));
+    assert_eq!(char_at(2), text("c"));
+    assert_eq!(char_at(3), native_error("The index 3 is out of a string of length 3"));
+    assert_eq!(char_at(-1), native_error("The index -1 is out of a string of length 3"));
+}
+
+#[test]
+fn parse_int_fails_on_what_is_not_an_integer() {
+    let parse = |s: &str| run(&program("", &format!("    CONST \"{}\"\n    METHODCALL String/parseInt", s)));
+    assert_eq!(parse("12a"), native_error("\"12a\" is not an integer"));
+    assert_eq!(parse(""), native_error("\"\" is not an integer"));
+    assert_eq!(parse("2147483648"), native_error("\"2147483648\" is not an integer"));
+    assert_eq!(parse("1,5"), native_error("\"1,5\" is not an integer"));
+}
+
+#[test]
+fn math_fails_when_the_result_does_not_fit() {
+    let call = |args: &str, method: &str| run(&program("", &format!("{}\n    METHODCALL Math/{}", args, method)));
+    assert_eq!(call("    CONST -2147483647", "abs"), int(2147483647));
+    assert_eq!(call("    CONST -2147483648", "abs"), native_error("The absolute value of -2147483648 doesn't fit in an integer"));
+    assert_eq!(call("    CONST 2\n    CONST 30", "pow"), int(1 << 30));
+    assert_eq!(call("    CONST 2\n    CONST 31", "pow"), native_error("2 to the power of 31 doesn't fit in an integer"));
+    assert_eq!(call("    CONST -2\n    CONST 31", "pow"), int(i32::MIN));
+    assert_eq!(call("    CONST 2\n    CONST -1", "pow"), native_error("The exponent -1 is negative"));
+    assert_eq!(call("    CONST 3\n    CONST -4", "min"), int(-4));
+    assert_eq!(call("    CONST 3\n    CONST -4", "max"), int(3));
+}
+
+#[test]
+fn native_errors_can_be_caught() {
+    let main = "
+.catch NativeError start end failed
+    LABEL start
+    CONST -2147483648
+    METHODCALL Math/abs
+    RETURN
+    LABEL end
+    LABEL failed
+    METHODCALL Exception/getMessage";
+    assert_eq!(run(&program("", main)), text("The absolute value of -2147483648 doesn't fit in an integer"));
+}
+
+#[test]
+fn host_functions_are_called_with_the_arguments() {
+    let result = run_with("    CONST 6\n    CONST abc\n    METHODCALL Host/repeat", |vm| {
+        vm.register_native("Host/repeat", "(IS)S", |n: i32, s: String| s.repeat(n as usize / 3)).unwrap();
+    });
+    assert_eq!(result, text("abcabc"));
+    let result = run_with("    CONST 2L\n    CONST 0.5\n    INVOKESTATIC Host/scale", |vm| {
+        vm.register_native("Host/scale", "(LD)D", |x: i64, f: f64| x as f64 * f).unwrap();
+    });
+    assert_eq!(result, Ok(Some(Type::Double(1.0))));
+}
+
+#[test]
+fn host_errors_are_native_errors() {
+    let lookup = |key: String| -> Result<i32, String> {
+        match key.as_str() {
+            "one" => Ok(1),
+            k => Err(format!("There's no key {}", k))
+        }
+    };
+    assert_eq!(run_with("    CONST one\n    METHODCALL Host/lookup", |vm| vm.register_native("Host/lookup", "(S)I", lookup).unwrap()), int(1));
+    assert_eq!(run_with("    CONST two\n    METHODCALL Host/lookup", |vm| vm.register_native("Host/lookup", "(S)I", lookup).unwrap()),
+               native_error("There's no key two"));
+}
+
+#[test]
+fn descriptors_have_to_match_the_function() {
+    let mut vm = Vm::load(assemble(&program("", ""))).unwrap();
+    let err = vm.register_native("Host/twice", "(S)I", |x: i32| x * 2).unwrap_err();
+    assert_eq!(err.stage, Stage::Register);
+    assert_eq!(err.kind, ErrorKind::TypeMismatch("The descriptor (S)I doesn't match the arguments and the returned value of Host/twice".to_string()));
+    assert!(matches!(vm.register_native("Host/twice", "(I)S", |x: i32| x * 2).map_err(|err| err.kind), Err(ErrorKind::TypeMismatch(_))));
+    assert!(matches!(vm.register_native("Host/twice", "(II)I", |x: i32| x * 2).map_err(|err| err.kind), Err(ErrorKind::TypeMismatch(_))));
+    assert!(vm.register_native("Host/twice", "(I)I", |x: i32| x * 2).is_ok());
+}
+
+#[test]
+fn natives_registered_twice_are_replaced() {
+    let result = run_with("    CONST 5\n    METHODCALL Host/f", |vm| {
+        vm.register_native("Host/f", "(I)I", |x: i32| x + 1).unwrap();
+        vm.register_native("Host/f", "(I)I", |x: i32| x * 10).unwrap();
+    });
+    assert_eq!(result, int(50));
+    // The standard ones can be replaced too
+    let result = run_with("    CONST -5\n    METHODCALL Math/abs", |vm| vm.register_native("Math/abs", "(I)I", |x: i32| x).unwrap());
+    assert_eq!(result, int(-5));
+    // After the program is linked the new function is the one called
+    let mut vm = Vm::load(assemble(&program("", "    CONST 5\n    METHODCALL Host/f\n    CONST 5\n    METHODCALL Host/f\n    IADD"))).unwrap();
+    vm.register_native("Host/f", "(I)I", |x: i32| x + 1).unwrap();
+    vm.step().unwrap();
+    vm.step().unwrap();
+    vm.register_native("Host/f", "(I)I", |x: i32| x * 10).unwrap();
+    assert_eq!(vm.run().map_err(|err| err.kind), int(56));
+}
+
+#[test]
+fn methods_with_code_win_over_natives() {
+    let classes = ".class Math\n.method static abs (I)I\n    CONST 0\n    RETURN";
+    assert_eq!(run(&program(classes, "    CONST -3\n    METHODCALL Math/abs")), int(0));
+    assert_eq!(run(&program("", "    CONST 1\n    METHODCALL Math/missing")), Err(ErrorKind::UnknownMethod("Math/missing".to_string())));
+}
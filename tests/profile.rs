@@ -0,0 +1,107 @@
+//! Counts of the profiler: calls, instructions of each method with and without the methods it calls, and samples
+
+extern crate vpm;
+
+mod common;
+
+use common::{load, program};
+use vpm::profile::{Profiler, MethodProfile};
+use vpm::Vm;
+use std::collections::HashMap;
+
+/// The main code runs 4 instructions, each call to A/f 4 and each call to A/g 2
+const CALLS: &str = "
+.class A
+.method static f ()I
+    CONST 1
+    INVOKESTATIC A/g
+    IADD
+    RETURN
+.method static g ()I
+    CONST 2
+    RETURN
+.method static countdown (I)I
+    LOAD 0
+    IF_LE end
+    LOAD 0
+    CONST 1
+    SUB
+    INVOKESTATIC A/countdown
+    RETURN
+    LABEL end
+    CONST 0
+    RETURN
+";
+
+fn profile(main: &str, profiler: Profiler) -> Vm {
+    let mut vm = load(&program(CALLS, main)).unwrap();
+    vm.set_profiler(Some(profiler));
+    vm.run().unwrap();
+    vm
+}
+
+/// Calls, inclusive and exclusive instructions of each method by name
+fn totals(vm: &Vm) -> HashMap<String, (u64, u64, u64)> {
+    let methods = vm.profiler().unwrap().methods();
+    methods.into_iter()
+        .map(|(m, p): (usize, MethodProfile)| (vm.instructions().get_method(m).name.clone(), (p.calls, p.inclusive, p.exclusive)))
+        .collect()
+}
+
+fn expected(totals: &[(&str, (u64, u64, u64))]) -> HashMap<String, (u64, u64, u64)> {
+    totals.iter().map(|(name, t)| (name.to_string(), *t)).collect()
+}
+
+const TWICE: &str = "    INVOKESTATIC A/f\n    INVOKESTATIC A/f\n    IADD";
+
+#[test]
+fn methods_count_their_calls_and_instructions() {
+    let vm = profile(TWICE, Profiler::new());
+    // The calls count for the caller and the returns for the method that returns
+    assert_eq!(totals(&vm), expected(&[("AppMain", (1, 16, 4)), ("A/f", (2, 12, 8)), ("A/g", (2, 4, 4))]));
+    let opcodes = vm.profiler().unwrap().opcodes();
+    assert_eq!((opcodes["INVOKESTATIC"], opcodes["RETURN"], opcodes["CONST"], opcodes["IADD"]), (4, 5, 4, 3));
+}
+
+#[test]
+fn recursive_calls_count_once_in_the_inclusive_totals() {
+    let vm = profile("    CONST 2\n    INVOKESTATIC A/countdown", Profiler::new());
+    // countdown runs 7 instructions for 2 and 1 and 5 for 0, with its LABEL
+    assert_eq!(totals(&vm), expected(&[("AppMain", (1, 22, 3)), ("A/countdown", (3, 19, 19))]));
+}
+
+#[test]
+fn collapsed_stacks_have_the_instructions_of_each_chain_of_calls() {
+    let vm = profile(TWICE, Profiler::new());
+    let profiler = vm.profiler().unwrap();
+    assert_eq!(profiler.collapsed(vm.instructions()), "AppMain 4\nAppMain;A/f 8\nAppMain;A/f;A/g 4\n");
+
+    let vm = profile("    CONST 1\n    INVOKESTATIC A/countdown", Profiler::new());
+    let profiler = vm.profiler().unwrap();
+    assert_eq!(profiler.collapsed(vm.instructions()), "AppMain 3\nAppMain;A/countdown 7\nAppMain;A/countdown;A/countdown 5\n");
+}
+
+#[test]
+fn samples_are_taken_every_interval() {
+    let vm = profile(TWICE, Profiler::sampling(4));
+    let profiler = vm.profiler().unwrap();
+    let samples: HashMap<String, u64> = profiler.samples().into_iter()
+        .map(|(m, c)| (vm.instructions().get_method(m).name.clone(), c))
+        .collect();
+    // The instructions 4 and 12 are the CONST of A/g, 8 is the second call and 16 the last RETURN
+    assert_eq!(samples, [("A/g".to_string(), 2), ("AppMain".to_string(), 2)].iter().cloned().collect());
+    assert!(profiler.report(vm.instructions()).contains("Samples every 4 instructions: 4\n"));
+
+    let vm = profile(TWICE, Profiler::new());
+    assert!(vm.profiler().unwrap().samples().is_empty());
+    assert!(!vm.profiler().unwrap().report(vm.instructions()).contains("Samples"));
+}
+
+#[test]
+fn the_report_is_sorted_by_cost() {
+    let vm = profile(TWICE, Profiler::new());
+    let report = vm.profiler().unwrap().report(vm.instructions());
+    assert!(report.starts_with("Executed instructions: 16\n"));
+    let methods: Vec<&str> = report.lines().skip(3).take(3).map(|l| l.split_whitespace().next().unwrap()).collect();
+    assert_eq!(methods, ["AppMain", "A/f", "A/g"]);
+}
@@ -0,0 +1,141 @@
+//! Static fields and the initialization of the classes with `<clinit>`
+
+extern crate vpm;
+
+mod common;
+
+use common::{program, text};
+use vpm::memory::vpk_stack::Type;
+use vpm::{Vm, ErrorKind};
+
+/// Each initializer appends the name of its class to `Log/text`
+const CLASSES: &str = "
+.class Log
+.field static text S \"\"
+.method static add (S)V
+    GETSTATIC Log/text
+    LOAD 0
+    SADD
+    PUTSTATIC Log/text
+    RETURN
+
+.class Base
+.field static count I 0
+.field static name S base
+.method static <clinit> ()V
+    CONST \"Base \"
+    INVOKESTATIC Log/add
+    CONST 10
+    PUTSTATIC Base/count
+    RETURN
+.method constructor ()V
+    GETSTATIC Base/count
+    CONST 1
+    IADD
+    PUTSTATIC Base/count
+    RETURN
+.method static twice (I)I
+    LOAD 0
+    CONST 2
+    MUL
+    RETURN
+
+.class Middle
+.super Base
+.field static name S middle
+
+.class Leaf
+.super Middle
+.method static <clinit> ()V
+    CONST \"Leaf \"
+    INVOKESTATIC Log/add
+    RETURN
+";
+
+fn run(main: &str) -> Result<Option<Type>, ErrorKind> {
+    common::run(&program(CLASSES, main))
+}
+
+fn load(main: &str) -> Result<Vm, ErrorKind> {
+    common::load(&program(CLASSES, main))
+}
+
+#[test]
+fn static_fields_are_shared_by_the_objects() {
+    assert_eq!(run("    NEW Base\n    POP\n    NEW Leaf\n    POP\n    GETSTATIC Base/count"), Ok(Some(Type::Integer(12))));
+    assert_eq!(run("    CONST hi\n    PUTSTATIC Log/text\n    GETSTATIC Log/text"), text("hi"));
+}
+
+#[test]
+fn static_fields_are_searched_in_the_parents() {
+    assert_eq!(run("    GETSTATIC Leaf/count"), Ok(Some(Type::Integer(10))));
+    assert_eq!(run("    GETSTATIC Leaf/name"), text("middle"));
+    assert_eq!(run("    GETSTATIC Base/name"), text("base"));
+}
+
+#[test]
+fn parents_are_initialized_first_and_once() {
+    assert_eq!(run("    NEW Leaf\n    POP\n    NEW Leaf\n    POP\n    NEW Base\n    POP\n    GETSTATIC Log/text"), text("Base Leaf "));
+    assert_eq!(run("    GETSTATIC Base/count\n    POP\n    NEW Leaf\n    POP\n    GETSTATIC Log/text"), text("Base Leaf "));
+}
+
+#[test]
+fn classes_are_initialized_when_first_used() {
+    // The instruction that initializes the class sees the values its initializer sets
+    assert_eq!(run("    GETSTATIC Base/count"), Ok(Some(Type::Integer(10))));
+    assert_eq!(run("    CONST 4\n    INVOKESTATIC Base/twice\n    GETSTATIC Log/text\n    SADD"), text("8Base "));
+    assert_eq!(run("    CONST 1\n    PUTSTATIC Base/count\n    GETSTATIC Base/count"), Ok(Some(Type::Integer(1))));
+    // Only using a class that doesn't initialize anything doesn't run the initializer of the others
+    assert_eq!(run("    CONST 4\n    INVOKESTATIC Log/add\n    GETSTATIC Log/text"), text("4"));
+}
+
+#[test]
+fn objects_in_static_fields_are_not_collected() {
+    let source = ".class Cache\n.field static value O\n.class Box\n.field n I 7\n.method constructor ()V\n    RETURN\n";
+    let main = "    NEW Box\n    PUTSTATIC Cache/value\n    NEW Box\n    POP\n    NEW Box\n    POP\n    GETSTATIC Cache/value\n    GETFIELD n";
+    let mut vm = common::load(&program(source, main)).unwrap();
+    vm.set_gc_threshold(1);
+    assert_eq!(vm.run().map_err(|err| err.kind), Ok(Some(Type::Integer(7))));
+    assert!(vm.heap_stats().freed > 0);
+}
+
+#[test]
+fn exceptions_of_the_initializer_reach_the_user_of_the_class() {
+    let source = "
+.class Broken
+.field static value I 1
+.method static <clinit> ()V
+    CONST 1
+    CONST 0
+    DIV
+    PUTSTATIC Broken/value
+    RETURN
+.main
+.catch ArithmeticError start end failed
+    LABEL start
+    GETSTATIC Broken/value
+    RETURN
+    LABEL end
+    LABEL failed
+    POP
+    GETSTATIC Broken/value
+    RETURN
+";
+    // The class stays initialized, the second access doesn't run the initializer again
+    assert_eq!(common::run(source), Ok(Some(Type::Integer(1))));
+}
+
+#[test]
+fn static_and_instance_fields_are_apart() {
+    assert!(matches!(run("    NEW Base\n    GETFIELD count"), Err(ErrorKind::UnknownField(_))));
+    assert_eq!(load("    GETSTATIC Base/missing").err(), Some(ErrorKind::UnknownField("Base/missing".to_string())));
+    assert_eq!(load("    GETSTATIC count").err(), Some(ErrorKind::UnknownField("count".to_string())));
+    assert_eq!(load("    GETSTATIC Missing/count").err(), Some(ErrorKind::UnknownClass("Missing".to_string())));
+}
+
+#[test]
+fn initializers_are_only_called_by_the_machine() {
+    assert!(matches!(load("    INVOKESTATIC Base/<clinit>"), Err(ErrorKind::InvalidCall(_))));
+    assert!(matches!(common::load(".class A\n.method <clinit> ()V\n    RETURN\n.main\n    RETURN\n"), Err(ErrorKind::InvalidCall(_))));
+    assert!(matches!(common::load(".class A\n.method static <clinit> (I)V\n    RETURN\n.main\n    RETURN\n"), Err(ErrorKind::InvalidCall(_))));
+}
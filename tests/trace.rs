@@ -0,0 +1,79 @@
+//! Traces of the executed instructions and of the objects they allocate and the fields they write, in each format
+
+extern crate vpm;
+
+mod common;
+
+use common::{load, program};
+use vpm::trace::{Tracer, TraceFormat};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// Output that the test reads once the tracer has written to it
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const POINT: &str = ".class Point\n.field x I 0\n.method constructor ()V\n    RETURN";
+
+/// Trace of the main code, which creates a point and writes its field, and the pcs of the main code and of the
+/// constructor
+fn trace(format: TraceFormat) -> (Vec<String>, usize, usize) {
+    let mut vm = load(&program(POINT, "    NEW Point\n    STORE 0\n    LOAD 0\n    CONST 7\n    PUTFIELD x")).unwrap();
+    let start = |name: &str| vm.instructions().get_method(vm.instructions().get_method_index(name).unwrap()).pc;
+    let (main, constructor) = (start("AppMain"), start("Point/constructor"));
+    let out = Shared::default();
+    vm.set_tracer(Some(Tracer::new(format, Box::new(out.clone()))));
+    vm.run().unwrap();
+    let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+    (text.lines().map(String::from).collect(), main, constructor)
+}
+
+#[test]
+fn text_traces_have_a_line_for_each_instruction_and_event() {
+    let (lines, main, constructor) = trace(TraceFormat::Text);
+    let ins = |pc: usize, method: &str, ins: &str, stacks: &str| format!("{:>6} {:<24} {:<28} {}", pc, method, ins, stacks);
+    assert_eq!(lines, [
+        ins(main, "AppMain", "NEW Point", "[] -> []"),
+        "       alloc Object(0) Point".to_string(),
+        ins(constructor, "Point/constructor", "RETURN", "[] -> [Object(0)]"),
+        ins(main + 1, "AppMain", "STORE 0", "[Object(0)] -> []"),
+        ins(main + 2, "AppMain", "LOAD 0", "[] -> [Object(0)]"),
+        ins(main + 3, "AppMain", "CONST 7", "[Object(0)] -> [Object(0), Integer(7)]"),
+        ins(main + 4, "AppMain", "PUTFIELD x", "[Object(0), Integer(7)] -> []"),
+        "       putfield Object(0).x = Integer(7)".to_string(),
+        ins(main + 5, "AppMain", "RETURN", "[] -> []"),
+    ]);
+}
+
+#[test]
+fn json_traces_have_an_object_for_each_instruction_and_event() {
+    let (lines, main, constructor) = trace(TraceFormat::Json);
+    let events: Vec<Value> = lines.iter().map(|l| serde_json::from_str(l).expect("Each line is a JSON object")).collect();
+    let ins = |pc: usize, method: &str, ins: &str, before: Value, after: Value| json!({
+        "event": "instruction", "pc": pc, "method": method, "instruction": ins, "before": before, "after": after
+    });
+    let (object, seven) = (json!({"Object": 0}), json!({"Integer": 7}));
+    assert_eq!(events, [
+        ins(main, "AppMain", "NEW Point", json!([]), json!([])),
+        json!({"event": "alloc", "handle": 0, "class": "Point"}),
+        ins(constructor, "Point/constructor", "RETURN", json!([]), json!([object])),
+        ins(main + 1, "AppMain", "STORE 0", json!([object]), json!([])),
+        ins(main + 2, "AppMain", "LOAD 0", json!([]), json!([object])),
+        ins(main + 3, "AppMain", "CONST 7", json!([object]), json!([object, seven])),
+        ins(main + 4, "AppMain", "PUTFIELD x", json!([object, seven]), json!([])),
+        json!({"event": "putfield", "handle": 0, "field": "x", "value": seven}),
+        ins(main + 5, "AppMain", "RETURN", json!([]), json!([])),
+    ]);
+}
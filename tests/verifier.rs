@@ -0,0 +1,133 @@
+//! The verifier, checked with the program that uses every instruction and with one broken program per check
+
+extern crate vpm;
+
+mod common;
+
+use common::{assemble, classes, program, verify, EVERY_OPCODE};
+use vpm::{verifier, ErrorKind};
+
+/// Verifies a main code that uses the classes of the program with every instruction
+fn main(code: &str) -> Result<(), ErrorKind> {
+    verify(&program(classes(EVERY_OPCODE), code))
+}
+
+#[test]
+fn every_instruction_is_accepted() {
+    assert_eq!(verify(EVERY_OPCODE), Ok(()));
+}
+
+/// Every error of the program as the method, the pc and the kind
+fn errors(source: &str) -> Vec<(String, usize, ErrorKind)> {
+    match verifier::verify(&assemble(source)) {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors.into_iter().map(|err| (err.method, err.pc, err.kind)).collect()
+    }
+}
+
+fn error(method: &str, pc: usize, kind: ErrorKind) -> Vec<(String, usize, ErrorKind)> {
+    vec![(method.to_string(), pc, kind)]
+}
+
+#[test]
+fn stack_depth_is_checked() {
+    assert_eq!(errors(".main\n    CONST 1\n    POP\n    POP\n    RETURN\n"), error("AppMain", 2, ErrorKind::StackUnderflow));
+    assert_eq!(errors(".main\n    CONST 1\n    IADD\n    RETURN\n"), error("AppMain", 1, ErrorKind::StackUnderflow));
+    // The paths that reach the label leave a different number of values
+    let source = ".main\n    CONST 0\n    IF_EQ skip\n    CONST 2\n    LABEL skip\n    RETURN\n";
+    assert_eq!(errors(source), error("AppMain", 3, ErrorKind::InconsistentStack("depth 0 and 1".to_string())));
+}
+
+#[test]
+fn types_are_merged_at_the_branches() {
+    let branches = |other: &str| format!("
+.main
+    CONST 0
+    IF_EQ other
+    CONST 1
+    GOTO end
+    LABEL other
+    {}
+    LABEL end
+    PRINT
+    RETURN
+", other);
+    assert_eq!(verify(&branches("CONST 2")), Ok(()));
+    assert_eq!(errors(&branches("CONST text")), error("AppMain", 6, ErrorKind::InconsistentStack("Integer and String".to_string())));
+    // A null merges with an object but not with an integer
+    assert!(matches!(verify(&branches("NULL")), Err(ErrorKind::InconsistentStack(_))));
+}
+
+#[test]
+fn locals_are_read_after_they_are_stored() {
+    assert_eq!(errors(".main\n    LOAD 3\n    PRINT\n    RETURN\n"), error("AppMain", 0, ErrorKind::UninitializedLocal(3)));
+    assert_eq!(errors(".main\n    INC 0 1\n    RETURN\n"), error("AppMain", 0, ErrorKind::UninitializedLocal(0)));
+    // The local variable is only stored in one of the paths
+    let one_path = ".main\n    CONST 0\n    IF_EQ skip\n    CONST 1\n    STORE 0\n    LABEL skip\n    LOAD 0\n    PRINT\n    RETURN\n";
+    assert_eq!(errors(one_path), error("AppMain", 5, ErrorKind::UninitializedLocal(0)));
+    // The arguments are stored before the first instruction, after the object
+    assert_eq!(verify(".class A\n.method f (IS)S\n    LOAD 2\n    LOAD 1\n    SADD\n    RETURN\n.main\n    RETURN\n"), Ok(()));
+}
+
+#[test]
+fn returns_match_the_descriptor() {
+    let method = |desc: &str, code: &str| errors(&format!(".class A\n.method f {}\n{}\n    RETURN\n.main\n    RETURN\n", desc, code));
+    assert_eq!(method("()I", "    CONST 1"), Vec::new());
+    assert_eq!(method("()V", ""), Vec::new());
+    assert_eq!(method("()I", "    CONST abc"), error("A/f", 1, ErrorKind::InvalidReturn("expected Integer but found String".to_string())));
+    assert_eq!(method("()I", ""), error("A/f", 0, ErrorKind::StackUnderflow));
+    assert!(matches!(method("()L", "    CONST 1")[0].2, ErrorKind::InvalidReturn(_)));
+}
+
+#[test]
+fn jumps_go_to_the_labels_of_the_method() {
+    assert_eq!(errors(".main\n    GOTO nowhere\n"), error("AppMain", 0, ErrorKind::UnknownLabel("nowhere".to_string())));
+    // The labels of the other methods can't be used
+    let source = ".class A\n.method f ()V\n    LABEL there\n    RETURN\n.main\n    CONST 1\n    IF_EQ there\n    RETURN\n";
+    assert_eq!(errors(source), error("AppMain", 1, ErrorKind::UnknownLabel("there".to_string())));
+    // Running past the last instruction
+    assert_eq!(errors(".main\n    CONST 1\n    POP\n"), error("AppMain", 1, ErrorKind::InvalidPc(2)));
+}
+
+#[test]
+fn every_broken_method_is_reported() {
+    let source = ".class A\n.method f ()I\n    RETURN\n.method g ()V\n    RETURN\n.main\n    POP\n    RETURN\n";
+    assert_eq!(errors(source), [
+        ("A/f".to_string(), 0, ErrorKind::StackUnderflow),
+        ("AppMain".to_string(), 0, ErrorKind::StackUnderflow),
+    ]);
+}
+
+#[test]
+fn calls_match_the_kind_of_method() {
+    assert_eq!(main("    CONST 2\n    INVOKESTATIC Counter/add\n    PRINT"), Ok(()));
+    assert!(matches!(main("    NEW Square\n    INVOKESTATIC Square/area\n    PRINT"), Err(ErrorKind::InvalidCall(_))));
+    assert!(matches!(main("    INVOKESTATIC Counter/<clinit>"), Err(ErrorKind::InvalidCall(_))));
+    // A static method has no receiver in the local variable 0
+    assert_eq!(verify(".class Util\n.method static id ()O\n    LOAD 0\n    RETURN\n.main\n    RETURN\n"), Err(ErrorKind::UninitializedLocal(0)));
+}
+
+#[test]
+fn handlers_are_checked() {
+    // The local variable is only set inside the range, the handler can't read it
+    let unset = ".main\n.catch Exception start end failed\n    LABEL start\n    CONST 1\n    STORE 0\n    LABEL end\n    RETURN\n    LABEL failed\n    LOAD 0\n    RETURN\n";
+    assert_eq!(verify(unset), Err(ErrorKind::UninitializedLocal(0)));
+    assert_eq!(verify(".main\n.catch Exception a b a\n    LABEL a\n    RETURN\n"), Err(ErrorKind::UnknownLabel("b".to_string())));
+    assert!(matches!(verify(".class Box\n.main\n.catch Box a a a\n    LABEL a\n    RETURN\n"), Err(ErrorKind::TypeMismatch(_))));
+}
+
+#[test]
+fn fields_have_the_type_of_their_declaration() {
+    assert!(matches!(main("    NEW Cube\n    GETFIELD Square/label\n    CONST 1\n    IADD\n    PRINT"), Err(ErrorKind::TypeMismatch(_))));
+    assert_eq!(main("    NEW Cube\n    GETFIELD Cube/depth\n    PRINT"), Err(ErrorKind::UnknownField("Cube/depth".to_string())));
+    assert!(matches!(main("    CONST abc\n    PUTSTATIC Counter/created"), Err(ErrorKind::TypeMismatch(_))));
+    // Static fields aren't fields of the objects
+    assert_eq!(main("    NEW Counter\n    GETFIELD created\n    PRINT"), Err(ErrorKind::UnknownField("created".to_string())));
+}
+
+#[test]
+fn operands_have_the_type_of_the_instruction() {
+    assert!(matches!(main("    CONST 1\n    CONST 2L\n    LADD\n    PRINT"), Err(ErrorKind::TypeMismatch(_))));
+    assert!(matches!(main("    CONST 1\n    INSTANCEOF Shape\n    PRINT"), Err(ErrorKind::TypeMismatch(_))));
+    assert!(matches!(main("    NEW Square\n    INVOKESPECIAL Shape/area\n    PRINT"), Err(ErrorKind::InvalidCall(_))));
+}
@@ -0,0 +1,229 @@
+//! The `Vm` API that embedders use: loading programs, running them and the errors they get back
+
+extern crate vpm;
+
+mod common;
+
+use common::{assemble, load, program, run, int};
+use vpm::isa::bytecode::ByteCode;
+use vpm::memory::vpk_stack::Type;
+use vpm::punkfile::punk_file::PunkFile;
+use vpm::error::StackTraceEntry;
+use vpm::{Vm, VmError, ErrorKind, Stage, State};
+
+/// Reads the JSON punk file with the content
+fn from_json(name: &str, json: &str) -> Result<PunkFile, VmError> {
+    let path = std::env::temp_dir().join(format!("vpm_vm_{}.json", name));
+    std::fs::write(&path, json).unwrap();
+    let punk_file = PunkFile::from_file(path.to_str().unwrap());
+    std::fs::remove_file(&path).unwrap();
+    punk_file
+}
+
+fn json(main: &str) -> String {
+    format!("{{\"magic_number\": \"CAFECAFE\", \"classes\": [
+        {{\"_this\": \"A\", \"_super\": \"\", \"fields\": [], \"methods\": [
+            {{\"name\": \"f\", \"descriptor\": \"()I\", \"code\": [\"CONST 1\", \"GOTO\", \"RETURN\"]}}
+        ]}}
+    ], \"main_code\": [{}]}}", main)
+}
+
+#[test]
+fn malformed_files_are_errors() {
+    let err = PunkFile::from_file("/nonexistent/program.json").unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::MalformedFile(_)));
+    assert_eq!(err.stage, Stage::Load);
+    assert!(err.to_string().starts_with("The program couldn't be loaded: Malformed punk file, /nonexistent/program.json"));
+
+    assert!(matches!(from_json("syntax", "{\"magic_number\": ").map(|_| ()), Err(VmError { kind: ErrorKind::MalformedFile(_), .. })));
+    let other_magic = json("\"RETURN\"").replace("CAFECAFE", "BEEF");
+    assert!(matches!(from_json("magic", &other_magic).map(|_| ()), Err(VmError { kind: ErrorKind::MalformedFile(_), .. })));
+}
+
+#[test]
+fn malformed_code_says_where_it_is() {
+    let err = from_json("code", &json("\"RETURN\"")).unwrap_err();
+    assert_eq!((err.kind, err.method.as_str(), err.pc), (ErrorKind::InvalidInstruction("GOTO expects an operand".to_string()), "A/f", 1));
+
+    let valid = json("\"RETURN\"").replace("\"GOTO\", ", "");
+    assert!(from_json("valid", &valid).is_ok());
+    let err = from_json("main", &valid.replace("[\"RETURN\"]", "[\"CONST 1\", \"FOO\", \"RETURN\"]")).unwrap_err();
+    assert_eq!((err.method.as_str(), err.pc), ("AppMain", 1));
+    let err = from_json("empty", &valid.replace("[\"RETURN\"]", "[\" \"]")).unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::InvalidInstruction(_)));
+}
+
+#[test]
+fn registration_errors_have_their_own_context() {
+    let mut vm = Vm::load(assemble(".main\n    RETURN\n")).unwrap();
+    let err = vm.register_native("Host/twice", "(I", |x: i32| x * 2).unwrap_err();
+    assert_eq!(err.stage, Stage::Register);
+    assert_eq!(err.to_string(), "The native Host/twice couldn't be registered: The descriptor (I is not valid");
+}
+
+#[test]
+fn steps_run_one_instruction() {
+    let mut vm = load(".main\n    CONST 2\n    CONST 3\n    IADD\n    RETURN\n").unwrap();
+    let start = vm.pc();
+    assert_eq!(vm.step(), Ok(State::Running));
+    assert_eq!(vm.pc(), start + 1);
+    assert_eq!(vm.frames()[0].0.operands(), [Type::Integer(2)]);
+    assert_eq!(vm.step(), Ok(State::Running));
+    assert_eq!(vm.step(), Ok(State::Running));
+    assert_eq!(vm.step(), Ok(State::Finished(Some(Type::Integer(5)))));
+    // Once finished the machine stays that way
+    assert_eq!(vm.step(), Ok(State::Finished(Some(Type::Integer(5)))));
+    assert_eq!(vm.state(), &State::Finished(Some(Type::Integer(5))));
+    assert_eq!(vm.run(), Ok(Some(Type::Integer(5))));
+}
+
+#[test]
+fn run_continues_after_the_steps() {
+    let mut vm = load(".main\n    CONST 2\n    CONST 3\n    IADD\n    RETURN\n").unwrap();
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.run(), Ok(Some(Type::Integer(5))));
+    assert_eq!(run(".main\n    RETURN\n"), Ok(None));
+}
+
+const CALLS: &str = "
+.class A
+.method static f ()I
+    CONST 1
+    INVOKESTATIC A/g
+    RETURN
+.method static g ()I
+    CONST 0
+    POP
+    POP
+    RETURN
+";
+
+#[test]
+fn errors_say_where_they_happened() {
+    let mut vm = load(&program(CALLS, "    CONST 7\n    INVOKESTATIC A/f")).unwrap();
+    let start = |name: &str| vm.instructions().get_method(vm.instructions().get_method_index(name).unwrap()).pc;
+    let (main, f, g) = (start("AppMain"), start("A/f"), start("A/g"));
+    let err = vm.run().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::StackUnderflow);
+    assert_eq!(err.stage, Stage::Run);
+    // The pcs are positions in the code of every method
+    assert_eq!((err.method.as_str(), err.pc), ("A/g", g + 2));
+    let entry = |method: &str, pc: usize| StackTraceEntry { method: method.to_string(), pc };
+    assert_eq!(err.backtrace, [entry("A/g", g + 2), entry("A/f", f + 1), entry("AppMain", main + 1)]);
+    assert_eq!(err.to_string(), format!("At pc: {}, in A/g, the execution throw an error: No element on the operator stack
+    at A/g (pc: {})
+    at A/f (pc: {})
+    at AppMain (pc: {})", g + 2, g + 2, f + 1, main + 1));
+
+    // The machine can't continue, it keeps reporting the error
+    assert_eq!(vm.step(), Err(err.clone()));
+    assert_eq!(vm.run(), Err(err));
+}
+
+#[test]
+fn labels_belong_to_their_method() {
+    let count = |class: &str| format!(".class {}
+.method static count (I)I
+    CONST 0
+    STORE 1
+    LABEL loop
+    LOAD 0
+    IF_LE end
+    INC 0 -1
+    INC 1 1
+    GOTO loop
+    LABEL end
+    LOAD 1
+    RETURN", class);
+    let classes = format!("{}\n{}", count("A"), count("B"));
+    assert_eq!(run(&program(&classes, "    CONST 3\n    INVOKESTATIC A/count\n    CONST 4\n    INVOKESTATIC B/count\n    IADD")), int(7));
+
+    let err = Vm::new(assemble(".class A\n.method static f ()V\n    LABEL a\n    LABEL a\n    RETURN\n.main\n    RETURN\n")).map(|_| ()).unwrap_err();
+    assert_eq!((err.kind, err.method.as_str(), err.stage), (ErrorKind::DuplicateLabel("a".to_string()), "A/f", Stage::Load));
+    // The main code can't jump to the labels of A/count
+    let err = Vm::new(assemble(&program(&count("A"), "    GOTO loop"))).map(|_| ()).unwrap_err();
+    assert_eq!((err.kind, err.method.as_str()), (ErrorKind::UnknownLabel("loop".to_string()), "AppMain"));
+}
+
+#[test]
+fn operands_are_linked_when_loaded() {
+    let source = program(".class Point\n.method constructor ()V\n    RETURN\n.method norm ()I\n    CONST 0\n    RETURN",
+        "    GOTO next\n    LABEL next\n    NEW Point\n    METHODCALL Point/norm");
+    let vm = load(&source).unwrap();
+    let instructions = vm.instructions();
+    let main = instructions.get_method(instructions.get_method_index("AppMain").unwrap()).pc;
+    let point = vm.punk_file().classes.iter().position(|c| c.this == "Point").unwrap();
+    match &instructions.code[main] {
+        ByteCode::GOTO(label) => assert_eq!(label.index, main + 1),
+        other => panic!("Expected GOTO but found {:?}", other)
+    }
+    match &instructions.code[main + 2] {
+        ByteCode::NEW {class, constructor} => {
+            assert_eq!(class.index, point);
+            assert_eq!(*constructor, instructions.get_method_index("Point/constructor").unwrap());
+        },
+        other => panic!("Expected NEW but found {:?}", other)
+    }
+    match &instructions.code[main + 3] {
+        ByteCode::METHODCALL {method, slot} => {
+            assert_eq!(method.index, instructions.get_method_index("Point/norm").unwrap());
+            assert_eq!(instructions.vtables[point].methods[*slot], method.index);
+        },
+        other => panic!("Expected METHODCALL but found {:?}", other)
+    }
+    // What can't be linked fails to load
+    assert_eq!(load(&program("", "    NEW Missing")).err(), Some(ErrorKind::UnknownClass("Missing".to_string())));
+    assert_eq!(load(&program(".class A", "    METHODCALL A/missing")).err(), Some(ErrorKind::UnknownMethod("A/missing".to_string())));
+}
+
+#[test]
+fn objects_are_handles_to_their_class() {
+    let classes = ".class A
+.field n I 1
+.method constructor ()V
+    RETURN
+.method name ()S
+    CONST A
+    RETURN
+
+.class AB
+.field n I 2
+.method constructor ()V
+    RETURN
+.method name ()S
+    CONST AB
+    RETURN
+
+.class Vec2
+.super A
+.method name ()S
+    CONST Vec2
+    RETURN";
+    // Objects of a class whose name starts with the name of another one are apart
+    let both = |main: &str| run(&program(classes, main));
+    assert_eq!(both("    NEW AB\n    METHODCALL AB/name\n    NEW A\n    METHODCALL A/name\n    SADD"), Ok(Some(Type::String("ABA".to_string()))));
+    assert_eq!(both("    NEW A\n    NEW AB\n    GETFIELD n\n    STORE 0\n    GETFIELD n\n    LOAD 0\n    SADD"), Ok(Some(Type::String("12".to_string()))));
+    // Class names can have digits
+    assert_eq!(both("    NEW Vec2\n    METHODCALL A/name"), Ok(Some(Type::String("Vec2".to_string()))));
+
+    let mut vm = load(&program(classes, "    NEW Vec2\n    NEW AB\n    NULL")).unwrap();
+    // RETURN takes the null, the objects are left below it
+    assert_eq!(vm.run(), Ok(Some(Type::Null)));
+    let operands = vm.frames()[0].0.operands().to_vec();
+    let class = |value: &Type| match value {
+        Type::Object(handle) => vm.punk_file().classes[vm.object(*handle).unwrap().class].this.clone(),
+        other => panic!("Expected an object but found {:?}", other)
+    };
+    assert_eq!(operands.iter().map(class).collect::<Vec<_>>(), ["Vec2", "AB"]);
+}
+
+#[test]
+fn null_is_not_an_object() {
+    let classes = ".class A\n.field n I 1\n.field next O\n.method constructor ()V\n    RETURN\n.method f ()I\n    CONST 1\n    RETURN";
+    assert_eq!(run(&program(classes, "    NULL")), Ok(Some(Type::Null)));
+    assert_eq!(run(&program(classes, "    NULL\n    GETFIELD n")), Err(ErrorKind::NullReference));
+    assert_eq!(run(&program(classes, "    NULL\n    METHODCALL A/f")), Err(ErrorKind::NullReference));
+    // Null is a value of the fields like any other
+    assert_eq!(run(&program(classes, "    NEW A\n    STORE 0\n    LOAD 0\n    NULL\n    PUTFIELD next\n    LOAD 0\n    GETFIELD next")), Ok(Some(Type::Null)));
+}
@@ -0,0 +1,107 @@
+//! Virtual method tables, built when the program is loaded
+
+extern crate vpm;
+
+mod common;
+
+use common::{load, program, text};
+use vpm::memory::vpk_stack::Type;
+use vpm::ErrorKind;
+
+const SHAPES: &str = "
+.class Shape
+.method constructor ()V
+    RETURN
+.method name ()S
+    CONST shape
+    RETURN
+.method describe ()S
+    LOAD 0
+    METHODCALL Shape/name
+    CONST \" with \"
+    SADD
+    LOAD 0
+    METHODCALL Shape/sides
+    SADD
+    CONST \" sides\"
+    SADD
+    RETURN
+.method sides ()I
+    CONST 0
+    RETURN
+
+.class Polygon
+.super Shape
+.method sides ()I
+    CONST 3
+    RETURN
+
+.class Square
+.super Polygon
+.method name ()S
+    CONST square
+    RETURN
+.method sides ()I
+    CONST 4
+    RETURN
+
+.class Triangle
+.super Polygon
+.method name ()S
+    CONST triangle
+    RETURN
+
+.class Box
+.method constructor ()V
+    RETURN
+";
+
+fn run(main: &str) -> Result<Option<Type>, ErrorKind> {
+    common::run(&program(SHAPES, main))
+}
+
+#[test]
+fn overrides_replace_the_inherited_slots() {
+    assert_eq!(run("    NEW Shape\n    METHODCALL Shape/describe"), text("shape with 0 sides"));
+    assert_eq!(run("    NEW Square\n    METHODCALL Shape/describe"), text("square with 4 sides"));
+    assert_eq!(run("    NEW Square\n    INVOKEVIRTUAL Polygon/name"), text("square"));
+}
+
+#[test]
+fn slots_are_inherited_through_the_chain() {
+    assert_eq!(run("    NEW Triangle\n    METHODCALL Shape/describe"), text("triangle with 3 sides"));
+    assert_eq!(run("    NEW Triangle\n    INVOKEVIRTUAL Triangle/describe"), text("triangle with 3 sides"));
+}
+
+#[test]
+fn receiver_without_the_method() {
+    assert_eq!(run("    NEW Box\n    INVOKEVIRTUAL Shape/name"), Err(ErrorKind::UnknownMethod("Shape/name".to_string())));
+    // A class outside the hierarchy with a method of the same name in the same slot doesn't have it either
+    let label = ".class Label\n.method constructor ()V\n    RETURN\n.method name ()S\n    CONST label\n    RETURN";
+    let source = program(&format!("{}\n{}", SHAPES, label), "    NEW Label\n    INVOKEVIRTUAL Shape/name");
+    let vm = load(&source).unwrap();
+    let (shape, label) = (vm.instructions().vtables[0].slot("name"), vm.instructions().vtables[5].slot("name"));
+    assert_eq!((shape, label), (Some(1), Some(1)));
+    assert_eq!(common::run(&source), Err(ErrorKind::UnknownMethod("Shape/name".to_string())));
+}
+
+#[test]
+fn inheritance_cycles_are_rejected() {
+    let source = ".class A\n.super C\n.class B\n.super A\n.class C\n.super B\n.main\n    RETURN\n";
+    assert!(matches!(load(source), Err(ErrorKind::InheritanceCycle(_))));
+    assert_eq!(load(".class A\n.super A\n.main\n    RETURN\n").err(), Some(ErrorKind::InheritanceCycle("A".to_string())));
+}
+
+#[test]
+fn unknown_parents_are_rejected() {
+    assert_eq!(load(".class A\n.super Missing\n.main\n    RETURN\n").err(), Some(ErrorKind::UnknownClass("Missing".to_string())));
+}
+
+#[test]
+fn overrides_must_keep_the_descriptor() {
+    let source = |method: &str| program(&format!("{}\n.class Circle\n.super Shape\n.method {}\n    CONST 1\n    RETURN", SHAPES, method), "");
+    assert!(load(&source("sides ()I")).is_ok());
+    assert!(matches!(load(&source("sides ()S")), Err(ErrorKind::IncompatibleOverride(_))));
+    assert!(matches!(load(&source("sides (I)I")), Err(ErrorKind::IncompatibleOverride(_))));
+    assert!(matches!(load(&source("static sides ()I")), Err(ErrorKind::IncompatibleOverride(_))));
+}